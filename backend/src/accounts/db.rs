@@ -0,0 +1,268 @@
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::Rng;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, params};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    accounts::{Account, Session},
+    db,
+};
+
+// Tokens left unused for this long stop working, so lost or leaked ones don't last forever
+const TOKEN_IDLE_EXPIRY_SECONDS: i64 = 90 * 24 * 60 * 60;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    id TEXT PRIMARY KEY,
+    -- Null for guests
+    username TEXT UNIQUE COLLATE NOCASE,
+    password_hash TEXT,
+    created_at INTEGER NOT NULL
+);
+
+-- Only a hash of each token is kept, the token itself is only ever sent to its client
+CREATE TABLE IF NOT EXISTS tokens (
+    token_hash TEXT PRIMARY KEY,
+    account_id TEXT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER NOT NULL
+);
+";
+
+/// ===============================================
+/// Account Store
+/// ===============================================
+pub(crate) struct Accounts {
+    conn: Mutex<Connection>,
+}
+
+impl Accounts {
+    pub fn open() -> Self {
+        Accounts {
+            conn: Mutex::new(db::open("accounts", SCHEMA)),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Self {
+        Accounts {
+            conn: Mutex::new(db::open_in_memory(SCHEMA)),
+        }
+    }
+
+    pub fn create_guest(&self) -> Result<Session, String> {
+        let conn = self.conn.lock().unwrap();
+        let account_id = Uuid::new_v4();
+        conn.execute(
+            "INSERT INTO accounts (id, created_at) VALUES (?1, ?2)",
+            params![account_id.to_string(), now()],
+        )
+        .map_err(storage_error)?;
+        let token = issue_token(&conn, account_id)?;
+        Ok(Session {
+            token,
+            player_id: account_id,
+            username: None,
+        })
+    }
+
+    /// Creates an account, or upgrades `guest` to one so it keeps its id and history
+    pub fn register(
+        &self,
+        username: &str,
+        password_hash: &str,
+        guest: Option<Uuid>,
+    ) -> Result<Session, String> {
+        let conn = self.conn.lock().unwrap();
+        let account_id = guest.unwrap_or_else(Uuid::new_v4);
+        let stored = match guest {
+            Some(id) => conn.execute(
+                "UPDATE accounts SET username = ?2, password_hash = ?3
+                 WHERE id = ?1 AND username IS NULL",
+                params![id.to_string(), username, password_hash],
+            ),
+            None => conn.execute(
+                "INSERT INTO accounts (id, username, password_hash, created_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![account_id.to_string(), username, password_hash, now()],
+            ),
+        };
+        match stored {
+            Ok(0) => return Err("This account already has a username".to_string()),
+            Ok(_) => {}
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) => {
+                return Err("Username is taken".to_string());
+            }
+            Err(e) => return Err(storage_error(e)),
+        }
+        let token = issue_token(&conn, account_id)?;
+        Ok(Session {
+            token,
+            player_id: account_id,
+            username: Some(username.to_string()),
+        })
+    }
+
+    /// The account id, username and password hash registered under `username`
+    pub fn find_login(&self, username: &str) -> Result<Option<(Uuid, String, String)>, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, username, password_hash FROM accounts
+             WHERE username = ?1 AND password_hash IS NOT NULL",
+            params![username],
+            |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(storage_error)
+        .map(|found| {
+            found.and_then(|(id, username, hash)| Some((id.parse().ok()?, username, hash)))
+        })
+    }
+
+    pub fn start_session(&self, account_id: Uuid, username: String) -> Result<Session, String> {
+        let conn = self.conn.lock().unwrap();
+        let token = issue_token(&conn, account_id)?;
+        Ok(Session {
+            token,
+            player_id: account_id,
+            username: Some(username),
+        })
+    }
+
+    /// Whether a registered account already has `username`, ignoring case
+    pub fn is_registered(&self, username: &str) -> Result<bool, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM accounts WHERE username = ?1)",
+            params![username],
+            |row| row.get(0),
+        )
+        .map_err(storage_error)
+    }
+
+    /// The account a token belongs to, marking the token as used. Expired tokens
+    /// belong to no one.
+    pub fn authenticate(&self, token: &str) -> Result<Option<Account>, String> {
+        let conn = self.conn.lock().unwrap();
+        let token_hash = hash_token(token);
+        let account = conn
+            .query_row(
+                "SELECT a.id, a.username FROM tokens t JOIN accounts a ON a.id = t.account_id
+                 WHERE t.token_hash = ?1 AND t.last_used_at >= ?2",
+                params![token_hash, now() - TOKEN_IDLE_EXPIRY_SECONDS],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+            )
+            .optional()
+            .map_err(storage_error)?;
+        let Some((id, username)) = account else {
+            return Ok(None);
+        };
+        conn.execute(
+            "UPDATE tokens SET last_used_at = ?2 WHERE token_hash = ?1",
+            params![token_hash, now()],
+        )
+        .map_err(storage_error)?;
+        Ok(id.parse().ok().map(|player_id| Account {
+            player_id,
+            username,
+        }))
+    }
+
+    pub fn revoke(&self, token: &str) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM tokens WHERE token_hash = ?1",
+            params![hash_token(token)],
+        )
+        .map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+/// Issues a new token, clearing out everyone's expired ones while it's at it
+fn issue_token(conn: &Connection, account_id: Uuid) -> Result<String, String> {
+    let bytes: [u8; 32] = rand::rng().random();
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let now = now();
+    conn.execute(
+        "DELETE FROM tokens WHERE last_used_at < ?1",
+        params![now - TOKEN_IDLE_EXPIRY_SECONDS],
+    )
+    .map_err(storage_error)?;
+    conn.execute(
+        "INSERT INTO tokens (token_hash, account_id, created_at, last_used_at)
+         VALUES (?1, ?2, ?3, ?3)",
+        params![hash_token(&token), account_id.to_string(), now],
+    )
+    .map_err(storage_error)?;
+    Ok(token)
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn storage_error(e: rusqlite::Error) -> String {
+    warn!("Account storage error: {}", e);
+    "Failed to access accounts".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_registered_usernames_regardless_of_case() {
+        let accounts = Accounts::open_in_memory();
+        accounts.create_guest().unwrap();
+        accounts.register("Alice", "hash", None).unwrap();
+        assert!(accounts.is_registered("alice").unwrap());
+        assert!(accounts.is_registered("ALICE").unwrap());
+        assert!(!accounts.is_registered("bob").unwrap());
+    }
+
+    #[test]
+    fn expires_tokens_left_unused() {
+        let accounts = Accounts::open_in_memory();
+        let session = accounts.create_guest().unwrap();
+        let account = accounts.authenticate(&session.token).unwrap().unwrap();
+        assert_eq!(account.player_id, session.player_id);
+
+        accounts
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE tokens SET last_used_at = ?1",
+                params![now() - TOKEN_IDLE_EXPIRY_SECONDS - 1],
+            )
+            .unwrap();
+        assert!(accounts.authenticate(&session.token).unwrap().is_none());
+
+        // Issuing any new token clears out the expired ones
+        accounts.create_guest().unwrap();
+        let left: i64 = accounts
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM tokens", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(left, 1);
+    }
+}
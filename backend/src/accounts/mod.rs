@@ -0,0 +1,212 @@
+use std::sync::LazyLock;
+
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use axum::{
+    Json,
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    response::{IntoResponse, Response},
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::{accounts::db::Accounts, state::validation::validate_username};
+pub mod db;
+
+/// ===============================================
+/// Player Accounts
+/// ===============================================
+/// Guests get a long-lived device token, registered players also get a username and
+/// password. Either token can be sent with `Join` so the player keeps the same id.
+/// Tokens expire after going unused for 90 days.
+pub static ACCOUNTS: LazyLock<Accounts> = LazyLock::new(Accounts::open);
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// The player a token belongs to
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Account {
+    pub player_id: Uuid,
+    // None for guests
+    pub username: Option<String>,
+}
+
+/// Returned whenever a token is issued
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Session {
+    pub token: String,
+    pub player_id: Uuid,
+    pub username: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// The account for a `Join` token. No token means an anonymous player.
+pub(crate) async fn identify(token: Option<String>) -> Result<Option<Account>, String> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+    let account = run_blocking(move || ACCOUNTS.authenticate(&token)).await?;
+    match account {
+        Some(account) => Ok(Some(account)),
+        None => Err("Invalid or expired token".to_string()),
+    }
+}
+
+/// Registered players always play under their account's username, guests and
+/// anonymous players under the one they asked for, as long as no account has it
+pub(crate) async fn player_name(
+    account: Option<&Account>,
+    requested: &str,
+) -> Result<String, String> {
+    if let Some(username) = account.and_then(|a| a.username.clone()) {
+        return Ok(username);
+    }
+    let username = validate_username(requested)?;
+    let lookup = username.clone();
+    if run_blocking(move || ACCOUNTS.is_registered(&lookup)).await? {
+        return Err("That username belongs to a registered player, sign in to use it".to_string());
+    }
+    Ok(username)
+}
+
+/// The account for the request's `Authorization: Bearer` token
+pub(crate) async fn authenticate(headers: &HeaderMap) -> Result<Account, Response> {
+    let token = bearer_token(headers)
+        .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Missing bearer token").into_response())?;
+    match identify(Some(token)).await {
+        Ok(Some(account)) => Ok(account),
+        Ok(None) | Err(_) => {
+            Err((StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response())
+        }
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    tokio::task::spawn_blocking(f).await.unwrap_or_else(|e| {
+        warn!("Account task panicked: {}", e);
+        Err("Failed to access accounts".to_string())
+    })
+}
+
+fn validate_password(password: &str) -> Result<(), String> {
+    let length = password.chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Err(format!(
+            "Passwords must be {} to {} characters",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+fn session_response(result: Result<Session, String>, status: StatusCode) -> Response {
+    match result {
+        Ok(session) => (status, Json(session)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// ===============================================
+/// Handlers
+/// ===============================================
+pub async fn create_guest() -> impl IntoResponse {
+    session_response(
+        run_blocking(|| ACCOUNTS.create_guest()).await,
+        StatusCode::CREATED,
+    )
+}
+
+/// Registers a new account. Sending a guest token upgrades that guest instead.
+#[instrument(name = "REGISTER", skip_all, fields(username = %credentials.username))]
+pub async fn register(headers: HeaderMap, Json(credentials): Json<Credentials>) -> Response {
+    let username = match validate_username(&credentials.username) {
+        Ok(username) => username,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if let Err(e) = validate_password(&credentials.password) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let guest = match bearer_token(&headers) {
+        Some(_) => match authenticate(&headers).await {
+            Ok(account) => Some(account.player_id),
+            Err(response) => return response,
+        },
+        None => None,
+    };
+    let result = run_blocking(move || {
+        let salt_bytes: [u8; 16] = rand::rng().random();
+        let hash = SaltString::encode_b64(&salt_bytes)
+            .and_then(|salt| {
+                Argon2::default()
+                    .hash_password(credentials.password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+            })
+            .map_err(|e| {
+                warn!("Failed to hash password: {}", e);
+                "Failed to register".to_string()
+            })?;
+        ACCOUNTS.register(&username, &hash, guest)
+    })
+    .await;
+    if result.is_ok() {
+        info!("Registered account");
+    }
+    session_response(result, StatusCode::CREATED)
+}
+
+#[instrument(name = "LOGIN", skip_all, fields(username = %credentials.username))]
+pub async fn login(Json(credentials): Json<Credentials>) -> Response {
+    let result = run_blocking(move || {
+        let invalid = || "Incorrect username or password".to_string();
+        let (account_id, username, hash) = ACCOUNTS
+            .find_login(credentials.username.trim())?
+            .ok_or_else(invalid)?;
+        let hash = PasswordHash::new(&hash).map_err(|_| invalid())?;
+        Argon2::default()
+            .verify_password(credentials.password.as_bytes(), &hash)
+            .map_err(|_| invalid())?;
+        ACCOUNTS.start_session(account_id, username)
+    })
+    .await;
+    match result {
+        Ok(session) => Json(session).into_response(),
+        Err(e) => (StatusCode::UNAUTHORIZED, e).into_response(),
+    }
+}
+
+pub async fn logout(headers: HeaderMap) -> Response {
+    let Some(token) = bearer_token(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "Missing bearer token").into_response();
+    };
+    match run_blocking(move || ACCOUNTS.revoke(&token)).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+pub async fn me(headers: HeaderMap) -> Response {
+    match authenticate(&headers).await {
+        Ok(account) => Json(account).into_response(),
+        Err(response) => response,
+    }
+}
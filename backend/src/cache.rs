@@ -0,0 +1,45 @@
+use std::{
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+/// Concurrent map whose entries expire `ttl` after insertion. Expired entries are
+/// ignored on read and dropped by `purge_expired`, called from the periodic cleanup.
+pub(crate) struct TtlCache<K, V> {
+    entries: DashMap<K, (Instant, V)>,
+    ttl: Duration,
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    pub fn new(ttl: Duration) -> Self {
+        TtlCache {
+            entries: DashMap::new(),
+            ttl,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let entry = self.entries.get(key)?;
+        let (inserted, value) = entry.value();
+        if inserted.elapsed() < self.ttl {
+            Some(value.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.entries.insert(key, (Instant::now(), value));
+    }
+
+    pub fn purge_expired(&self) {
+        self.entries
+            .retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+    }
+}
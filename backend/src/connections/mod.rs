@@ -1,11 +1,19 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use tokio::sync::mpsc;
-use tracing::info;
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, stream::SplitSink};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
 use uuid::Uuid;
 
+use crate::state::AppState;
+
 pub(crate) trait ConnectionManager {
-    fn connection_drop(&self, player_id: Uuid);
+    fn connection_drop(self: &Arc<Self>, player_id: Uuid, connection_id: Uuid, state: &AppState);
     fn no_connections(&self) -> bool;
     fn lobby_code(&self) -> String;
 }
@@ -16,7 +24,9 @@ where
 {
     pub game: Arc<G>,
     pub player_id: Uuid,
-    pub cleanup_tx: mpsc::UnboundedSender<String>,
+    // Tells this connection's private channels apart from a reconnect's
+    pub connection_id: Uuid,
+    pub state: AppState,
 }
 
 impl<G> Drop for ConnectionGuard<G>
@@ -25,10 +35,92 @@ where
 {
     fn drop(&mut self) {
         info!("Dropping connection for player {}", self.player_id);
-        self.game.connection_drop(self.player_id);
+        self.game
+            .connection_drop(self.player_id, self.connection_id, &self.state);
         if self.game.no_connections() {
             info!("No more connections, cleaning up game");
-            let _ = self.cleanup_tx.send(self.game.lobby_code());
+            let _ = self.state.cleanup.send(self.game.lobby_code());
+        }
+    }
+}
+
+/// Per-player channels for events that only one connection should see
+/// (private errors, hints, personalised results).
+pub(crate) struct PlayerChannels<E> {
+    // Keyed by player, with the id of the connection that registered the channel
+    channels: Mutex<HashMap<Uuid, (Uuid, mpsc::UnboundedSender<E>)>>,
+}
+
+impl<E> PlayerChannels<E> {
+    pub fn new() -> Self {
+        PlayerChannels {
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn register(&self, player_id: Uuid, connection_id: Uuid) -> mpsc::UnboundedReceiver<E> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.channels
+            .lock()
+            .unwrap()
+            .insert(player_id, (connection_id, tx));
+        rx
+    }
+
+    /// Leaves the channel alone if the player has since reconnected and registered a new one
+    pub fn remove(&self, player_id: &Uuid, connection_id: Uuid) {
+        let mut channels = self.channels.lock().unwrap();
+        if channels
+            .get(player_id)
+            .is_some_and(|(id, _)| *id == connection_id)
+        {
+            channels.remove(player_id);
+        }
+    }
+
+    /// Returns false if the player has no open connection
+    pub fn send(&self, player_id: &Uuid, event: E) -> bool {
+        match self.channels.lock().unwrap().get(player_id) {
+            Some((_, tx)) => tx.send(event).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Drives a connection's outgoing half, merging the lobby broadcast with the
+/// player's private channel until either closes or the socket errors.
+pub(crate) async fn forward_events<E>(
+    mut sender: SplitSink<WebSocket, Message>,
+    mut broadcast_rx: broadcast::Receiver<E>,
+    mut direct_rx: mpsc::UnboundedReceiver<E>,
+) where
+    E: Serialize + Clone,
+{
+    loop {
+        let msg = tokio::select! {
+            msg = broadcast_rx.recv() => match msg {
+                Ok(msg) => msg,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Connection lagged, skipped {} events", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            msg = direct_rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
+        match serde_json::to_string(&msg) {
+            Ok(json) => {
+                if sender.send(Message::Text(json.into())).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                warn!("Serialization error: {:?}", e);
+                continue;
+            }
         }
     }
 }
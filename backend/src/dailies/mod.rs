@@ -0,0 +1,379 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{
+        State,
+        ws::{Message, WebSocket},
+    },
+    response::IntoResponse,
+};
+use futures_util::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use tokio::time::{Duration, Instant};
+use tracing::{Instrument, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    accounts::{identify, player_name},
+    connections::{ConnectionGuard, forward_events},
+    generate_lobby_code,
+    guess_the_song::{api, handle_guess, report_outcome, run_guess_the_song_game},
+    history::record_game,
+    state::{
+        AppState, Dailies, DailiesClientEvent, DailiesEvent, DailiesServerEvent, DailiesUserEvent,
+        DailyStage, GuessTheSongGame, GuessTheSongGameSettings, GuessTheSongUserEvent,
+        LobbyServerEvent, LobbyStatus, LobbyUserEvent, SERIES_BREAK_SECONDS, Wait,
+        geoguessr::{GeoGuessr, GeoGuessrSettings},
+        validation::validate_guess,
+    },
+};
+
+#[derive(serde::Serialize)]
+struct CreateLobbyResponse {
+    lobby_code: String,
+}
+
+#[instrument(name = "CREATE LOBBY", skip(state))]
+pub async fn create_dailies_lobby(State(state): State<AppState>) -> impl IntoResponse {
+    let mut lobby_code;
+    loop {
+        lobby_code = generate_lobby_code();
+        if !state.games.valid_lobby_code(&lobby_code) {
+            break;
+        }
+    }
+    state.games.add_dailies_lobby(&lobby_code);
+    info!("Added lobby {lobby_code}");
+
+    Json(CreateLobbyResponse { lobby_code })
+}
+
+async fn send_join_error(sender: &mut SplitSink<WebSocket, Message>, message: String) {
+    info!("JOIN ERROR");
+    let _ = sender
+        .send(Message::Text(
+            serde_json::to_string(&DailiesServerEvent::LobbyEvent(
+                LobbyServerEvent::JoinError { message },
+            ))
+            .unwrap()
+            .into(),
+        ))
+        .await;
+}
+
+/// Waits for the `Join` request, returning the lobby code, username and account id
+async fn await_join_req(
+    receiver: &mut SplitStream<WebSocket>,
+    sender: &mut SplitSink<WebSocket, Message>,
+) -> Result<(String, String, Option<Uuid>), ()> {
+    let join_req = match receiver.next().await {
+        Some(Ok(Message::Text(m))) => m,
+        _ => return Err(()),
+    };
+    let (lobby_code, username, token) = match serde_json::from_str(&join_req) {
+        Ok(DailiesClientEvent::LobbyEvent(LobbyUserEvent::Join {
+            lobby_code,
+            username,
+            token,
+        })) => (lobby_code, username, token),
+        Ok(_) => {
+            send_join_error(sender, "Expected Join Event".to_string()).await;
+            return Err(());
+        }
+        Err(_) => {
+            send_join_error(sender, "Failed to serialize Inital Request".to_string()).await;
+            return Err(());
+        }
+    };
+    let identity = match identify(token).await {
+        Ok(account) => player_name(account.as_ref(), &username)
+            .await
+            .map(|username| (account.map(|a| a.player_id), username)),
+        Err(message) => Err(message),
+    };
+    match identity {
+        Ok((account_id, username)) => Ok((lobby_code, username, account_id)),
+        Err(message) => {
+            send_join_error(sender, message).await;
+            Err(())
+        }
+    }
+}
+
+pub async fn handle_dailies(socket: WebSocket, state: AppState) {
+    let (mut sender, mut receiver) = socket.split();
+    let (lobby_code, player_username, account_id) =
+        match await_join_req(&mut receiver, &mut sender).await {
+            Ok(join) => join,
+            Err(_) => return,
+        };
+
+    let dailies = match state.games.dailies.get(&lobby_code) {
+        Some(dailies) => dailies.clone(),
+        None => {
+            send_join_error(&mut sender, "Lobby not found".to_string()).await;
+            return;
+        }
+    };
+
+    let player_id = account_id.unwrap_or_else(|| dailies.lobby.lock().unwrap().get_new_player_id());
+
+    let connection_span = tracing::info_span!(
+        "connection",
+        lobby=%lobby_code,
+        player=%player_id,
+    );
+
+    if let Err(e) = dailies.player_join(player_id, player_username.clone()) {
+        send_join_error(&mut sender, e.to_string()).await;
+        return;
+    }
+    info!("Player: {}, joined lobby: {}", player_id, lobby_code);
+
+    let connection_id = Uuid::new_v4();
+    let _guard = ConnectionGuard {
+        game: dailies.clone(),
+        player_id,
+        connection_id,
+        state: state.clone(),
+    };
+
+    let rx = dailies.broadcast.subscribe();
+    let direct_rx = dailies.direct.register(player_id, connection_id);
+    dailies.relay_direct_events(player_id, connection_id);
+
+    let _ = sender
+        .send(Message::Text(
+            serde_json::to_string(&DailiesServerEvent::DailiesEvent(DailiesEvent::SyncState {
+                players: dailies.get_players(),
+                settings: dailies.get_settings(),
+                status: dailies.get_lobby_status(),
+                standings: dailies.get_standings(),
+            }))
+            .expect("Failed to parse SyncState event")
+            .into(),
+        ))
+        .await;
+
+    let mut send_task: tokio::task::JoinHandle<()> =
+        tokio::spawn(forward_events(sender, rx, direct_rx).instrument(connection_span.clone()));
+
+    dailies.broadcast_lobby(LobbyServerEvent::PlayerJoin {
+        player_id,
+        player_username: player_username.clone(),
+    });
+
+    let mut prev_guess_time_stamp = Instant::now();
+    let mut recv_task = tokio::spawn(
+        async move {
+            while let Some(Ok(msg)) = receiver.next().await {
+                let Message::Text(req) = msg else {
+                    continue;
+                };
+                let event = match serde_json::from_str::<DailiesClientEvent>(&req) {
+                    Ok(e) => e,
+                    Err(e) => {
+                        warn!("Failed to parse client event: {:?}", e);
+                        continue;
+                    }
+                };
+                match event {
+                    DailiesClientEvent::LobbyEvent(e) => {
+                        if dailies.handle_lobby_event(player_id, e) {
+                            tokio::spawn(run_dailies(dailies.clone(), state.clone()));
+                        }
+                    }
+                    DailiesClientEvent::DailiesEvent(DailiesUserEvent::UpdateSettings {
+                        settings,
+                    }) => {
+                        info!("UPDATE SETTINGS: {:?}", settings);
+                        dailies.update_settings(player_id, settings);
+                    }
+                    DailiesClientEvent::GeoGuessr(e) => {
+                        dailies.handle_geo_guessr_event(player_id, e);
+                    }
+                    DailiesClientEvent::GuessTheSong(GuessTheSongUserEvent::Guess { content }) => {
+                        let game = &dailies.guess_the_song;
+                        if let Err(message) = validate_guess(&content) {
+                            dailies.send_error(&player_id, message);
+                            continue;
+                        }
+                        let now = Instant::now();
+                        let answer_delay = game.get_settings().answer_delay_seconds as f64;
+                        let waited = now.duration_since(prev_guess_time_stamp).as_secs_f64();
+                        if waited < answer_delay {
+                            dailies.send_error(
+                                &player_id,
+                                format!("{:.2}s: {}", answer_delay - waited, content),
+                            );
+                            continue;
+                        }
+                        if !handle_guess(game, player_id, &player_username, &content) {
+                            prev_guess_time_stamp = now;
+                        }
+                    }
+                    DailiesClientEvent::GuessTheSong(GuessTheSongUserEvent::Choose { index }) => {
+                        let game = &dailies.guess_the_song;
+                        let outcome = game.choose(player_id, index);
+                        report_outcome(game, player_id, &player_username, outcome);
+                    }
+                    DailiesClientEvent::GuessTheSong(GuessTheSongUserEvent::ReportBroken) => {
+                        if let Err(message) = dailies.guess_the_song.report_broken(player_id) {
+                            dailies.send_error(&player_id, message);
+                        }
+                    }
+                    DailiesClientEvent::GuessTheSong(_) => {
+                        dailies.send_error(
+                            &player_id,
+                            "Only guesses and reports can be sent to a stage".to_string(),
+                        );
+                    }
+                }
+            }
+        }
+        .instrument(connection_span),
+    );
+
+    tokio::select! {
+        _ = (&mut send_task) => recv_task.abort(),
+        _ = (&mut recv_task) => send_task.abort(),
+    }
+
+    info!("Websocket disconnected");
+}
+
+/// Plays each stage in its game, adding the stage scores to the lobby's standings.
+/// Aborting any stage goes back to the waiting room.
+#[instrument(name = "DAILIES", skip_all, fields(lobby=%dailies.lobby_code))]
+pub(crate) async fn run_dailies(dailies: Arc<Dailies>, state: AppState) {
+    info!("Starting dailies");
+    dailies.control.start();
+    play_dailies(&dailies, &state).await;
+    if dailies.control.is_aborted() {
+        info!("Dailies aborted");
+        dailies.geo_guessr.state.lock().unwrap().reset();
+        dailies.guess_the_song.state.lock().unwrap().reset();
+        dailies.lobby.lock().unwrap().abort_game();
+        dailies
+            .send_in_order(DailiesServerEvent::LobbyEvent(
+                LobbyServerEvent::UpdateLobbyStatus {
+                    new_status: LobbyStatus::Waiting,
+                },
+            ))
+            .await;
+    }
+    dailies.control.stop();
+}
+
+async fn play_dailies(dailies: &Arc<Dailies>, state: &AppState) {
+    let settings = dailies.get_settings();
+    let series = settings.series();
+    let mut played = Vec::new();
+    for (stage, details) in settings.stages.iter().enumerate() {
+        if dailies.lobby.lock().unwrap().empty() {
+            info!("Dailies empty, terminating loop");
+            return;
+        }
+        info!(stage, game = details.game_type().as_str(), "STAGE START");
+        dailies
+            .send_in_order(DailiesServerEvent::DailiesEvent(DailiesEvent::StageStart {
+                stage,
+                num_stages: settings.stages.len(),
+                details: details.clone(),
+            }))
+            .await;
+        let result = match details {
+            DailyStage::GeoGuessr(settings) => {
+                play_geo_guessr(&dailies.geo_guessr, settings.clone()).await
+            }
+            DailyStage::GuessTheSong(settings) => {
+                play_guess_the_song(&dailies.guess_the_song, state, settings.clone()).await
+            }
+        };
+        let scores = match result {
+            Ok(scores) => scores,
+            Err(message) => {
+                warn!("Stage failed: {}", message);
+                dailies.lobby.lock().unwrap().show_results();
+                dailies
+                    .send_in_order(DailiesServerEvent::DailiesEvent(DailiesEvent::Error {
+                        message,
+                    }))
+                    .await;
+                dailies
+                    .send_in_order(DailiesServerEvent::LobbyEvent(
+                        LobbyServerEvent::UpdateLobbyStatus {
+                            new_status: LobbyStatus::Finished,
+                        },
+                    ))
+                    .await;
+                return;
+            }
+        };
+        if dailies.control.is_aborted() || dailies.lobby.lock().unwrap().empty() {
+            return;
+        }
+        played.push((details.game_type(), scores.clone()));
+        let standings = dailies.lobby.lock().unwrap().finish(&scores, Some(&series));
+        let complete = standings.complete;
+        if complete {
+            dailies
+                .send_in_order(DailiesServerEvent::LobbyEvent(
+                    LobbyServerEvent::UpdateLobbyStatus {
+                        new_status: LobbyStatus::Finished,
+                    },
+                ))
+                .await;
+        }
+        dailies
+            .send_in_order(DailiesServerEvent::LobbyEvent(
+                LobbyServerEvent::SeriesStandings(standings),
+            ))
+            .await;
+        if complete {
+            break;
+        }
+        let wait = dailies
+            .control
+            .wait(Duration::from_secs(SERIES_BREAK_SECONDS))
+            .await;
+        if wait == Wait::Aborted {
+            return;
+        }
+    }
+    info!("DAILIES END");
+    record_game(dailies.to_record(&played));
+}
+
+async fn play_geo_guessr(
+    game: &Arc<GeoGuessr>,
+    settings: GeoGuessrSettings,
+) -> Result<HashMap<Uuid, u32>, String> {
+    *game.settings.lock().unwrap() = settings;
+    game.state.lock().unwrap().reset();
+    game.load_locations()
+        .await
+        .map_err(|e| format!("Failed to load locations: {}", e))?;
+    game.update_lobby_status(LobbyStatus::Playing);
+    GeoGuessr::run_game(Arc::clone(game)).await;
+    game.update_lobby_status(LobbyStatus::Waiting);
+    Ok(game.get_leaderboard())
+}
+
+async fn play_guess_the_song(
+    game: &Arc<GuessTheSongGame>,
+    state: &AppState,
+    settings: GuessTheSongGameSettings,
+) -> Result<HashMap<Uuid, u32>, String> {
+    let playlist_link = settings.playlist_link.clone();
+    *game.settings.lock().unwrap() = settings;
+    game.state.lock().unwrap().reset();
+    api::load_songs(state, &playlist_link, Arc::clone(game)).await?;
+    game.update_lobby_status(LobbyStatus::Playing);
+    run_guess_the_song_game(Arc::clone(game)).await;
+    game.update_lobby_status(LobbyStatus::Waiting);
+    Ok(game.get_leaderboard())
+}
@@ -0,0 +1,56 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use rusqlite::Connection;
+use tracing::{error, info};
+
+/// ===============================================
+/// SQLite
+/// ===============================================
+/// Game history, accounts and the denylist live in one local SQLite file at `DATABASE_PATH`,
+/// each with its own connection.
+pub fn database_path() -> PathBuf {
+    env::var("DATABASE_PATH")
+        .unwrap_or("dailies.db".to_string())
+        .into()
+}
+
+/// Opens a connection and creates any missing tables in `schema`. Falls back to an
+/// in-memory database if the file can't be opened, so the server still runs without
+/// keeping anything.
+pub(crate) fn open(name: &str, schema: &str) -> Connection {
+    let path = database_path();
+    let opened = Connection::open(&path).and_then(|conn| {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        init(&conn, schema)?;
+        Ok(conn)
+    });
+    match opened {
+        Ok(conn) => {
+            info!("Opened {} at {}", name, path.display());
+            conn
+        }
+        Err(e) => {
+            error!(
+                "Failed to open {} at {}, it won't be kept: {}",
+                name,
+                path.display(),
+                e
+            );
+            open_in_memory(schema)
+        }
+    }
+}
+
+/// A private database that's dropped with its connection, for tests and as a fallback
+pub(crate) fn open_in_memory(schema: &str) -> Connection {
+    let conn = Connection::open_in_memory().expect("Failed to open in-memory database");
+    init(&conn, schema).expect("Failed to create in-memory tables");
+    conn
+}
+
+fn init(conn: &Connection, schema: &str) -> rusqlite::Result<()> {
+    conn.pragma_update(None, "foreign_keys", true)?;
+    // Connections share the file, so wait out each other's writes
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch(schema)
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashSet,
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{Connection, params};
+use tracing::{error, info};
+
+use crate::{db, state::GameType};
+
+const SCHEMA: &str = "
+-- Locations and songs a lobby voted broken, e.g. a dead pano or a preview that 404s.
+-- One row per lobby, so a single lobby can't deny an item on its own.
+CREATE TABLE IF NOT EXISTS broken_reports (
+    game_type TEXT NOT NULL,
+    -- Location image id, or see `song_key`
+    item_id TEXT NOT NULL,
+    -- Something readable to find the item by
+    label TEXT NOT NULL,
+    lobby_code TEXT NOT NULL,
+    reported_at INTEGER NOT NULL,
+    PRIMARY KEY (game_type, item_id, lobby_code)
+);
+";
+
+// Separate lobbies that have to report an item before it's left out
+const MIN_REPORTING_LOBBIES: u32 = 3;
+// Reports are dropped after this long, so an item that was only down for a while comes back
+const REPORT_EXPIRY_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+/// ===============================================
+/// Denylist
+/// ===============================================
+/// Items voted broken by enough lobbies are left out when loading later games
+pub static DENYLIST: LazyLock<Denylist> = LazyLock::new(Denylist::open);
+
+pub(crate) struct Denylist {
+    conn: Mutex<Connection>,
+}
+
+impl Denylist {
+    pub fn open() -> Self {
+        Denylist {
+            conn: Mutex::new(db::open("denylist", SCHEMA)),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Self {
+        Denylist {
+            conn: Mutex::new(db::open_in_memory(SCHEMA)),
+        }
+    }
+
+    /// Records a lobby's report, replacing any earlier one from the same lobby, and clears
+    /// out expired reports
+    pub fn report(
+        &self,
+        game_type: GameType,
+        item_id: &str,
+        label: &str,
+        lobby_code: &str,
+    ) -> rusqlite::Result<()> {
+        let now = now();
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM broken_reports WHERE reported_at < ?1",
+            params![now - REPORT_EXPIRY_SECONDS],
+        )?;
+        conn.execute(
+            "INSERT OR REPLACE INTO broken_reports
+             (game_type, item_id, label, lobby_code, reported_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![game_type.as_str(), item_id, label, lobby_code, now],
+        )?;
+        Ok(())
+    }
+
+    /// Items with unexpired reports from enough separate lobbies
+    pub fn items(&self, game_type: GameType) -> rusqlite::Result<HashSet<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT item_id FROM broken_reports
+             WHERE game_type = ?1 AND reported_at >= ?2
+             GROUP BY item_id
+             HAVING COUNT(*) >= ?3",
+        )?;
+        stmt.query_map(
+            params![
+                game_type.as_str(),
+                now() - REPORT_EXPIRY_SECONDS,
+                MIN_REPORTING_LOBBIES
+            ],
+            |row| row.get(0),
+        )?
+        .collect()
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Songs are matched by name rather than preview url, since preview urls expire
+pub(crate) fn song_key(title: &str, artists: &[String]) -> String {
+    format!("{} - {}", artists.join(", "), title).to_lowercase()
+}
+
+/// Reports an item in the background, logging rather than failing the game
+pub(crate) fn report_item(game_type: GameType, item_id: String, label: String, lobby_code: String) {
+    tokio::task::spawn_blocking(move || {
+        match DENYLIST.report(game_type, &item_id, &label, &lobby_code) {
+            Ok(()) => info!(item = %item_id, "Reported {} item {}", game_type.as_str(), label),
+            Err(e) => error!(item = %item_id, "Failed to report item: {}", e),
+        }
+    });
+}
+
+/// Every denied item for a game, or none if they can't be read
+pub(crate) async fn denied(game_type: GameType) -> HashSet<String> {
+    match tokio::task::spawn_blocking(move || DENYLIST.items(game_type)).await {
+        Ok(Ok(items)) => items,
+        Ok(Err(e)) => {
+            error!("Failed to read the denylist: {}", e);
+            HashSet::new()
+        }
+        Err(e) => {
+            error!("Denylist task failed: {}", e);
+            HashSet::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(denylist: &Denylist, item_id: &str, lobby_code: &str) {
+        denylist
+            .report(GameType::GeoGuessr, item_id, "label", lobby_code)
+            .unwrap();
+    }
+
+    #[test]
+    fn denies_items_reported_by_enough_lobbies() {
+        let denylist = Denylist::open_in_memory();
+        report(&denylist, "pano", "AAAA");
+        // Reporting again from the same lobby doesn't count twice
+        report(&denylist, "pano", "AAAA");
+        report(&denylist, "pano", "BBBB");
+        assert!(denylist.items(GameType::GeoGuessr).unwrap().is_empty());
+
+        report(&denylist, "pano", "CCCC");
+        let denied = denylist.items(GameType::GeoGuessr).unwrap();
+        assert_eq!(denied, HashSet::from(["pano".to_string()]));
+        assert!(denylist.items(GameType::GuessTheSong).unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_reports_dont_count() {
+        let denylist = Denylist::open_in_memory();
+        for lobby_code in ["AAAA", "BBBB", "CCCC"] {
+            report(&denylist, "pano", lobby_code);
+        }
+        denylist
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE broken_reports SET reported_at = ?1 WHERE lobby_code = 'AAAA'",
+                params![now() - REPORT_EXPIRY_SECONDS - 1],
+            )
+            .unwrap();
+        assert!(denylist.items(GameType::GeoGuessr).unwrap().is_empty());
+
+        // Reporting anything clears the expired reports out
+        report(&denylist, "other", "DDDD");
+        let left: i64 = denylist
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM broken_reports", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(left, 3);
+    }
+}
@@ -1,11 +1,171 @@
-use std::{collections::HashMap, sync::LazyLock};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::PathBuf,
+    sync::{Arc, LazyLock, RwLock},
+};
 
-use crate::state::geoguessr::Location;
+use rand::{Rng, seq::IndexedRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 
+use crate::state::geoguessr::{Location, haversine_km};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct Map {
+    pub name: String,
     pub center: (f32, f32),
     pub locations: Vec<Location>,
     pub zoom: u8,
+    // Optional (lat, lng) polygon, only locations inside it are played
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Vec<(f32, f32)>>,
+    // Distance score decay in km, derived from the map's extent when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score_scale_km: Option<f64>,
+}
+
+impl Map {
+    /// The location pool, restricted to the region if the map has one
+    pub fn playable(&self) -> impl Iterator<Item = &Location> {
+        self.locations.iter().filter(|l| match &self.region {
+            Some(region) => in_polygon(region, l.lat, l.lng),
+            None => true,
+        })
+    }
+
+    /// Picks up to `count` playable locations that haven't been `seen`, preferring
+    /// ones far from those already picked so a game isn't clustered in one area
+    pub fn sample<R: Rng + ?Sized>(
+        &self,
+        count: usize,
+        seen: &HashSet<String>,
+        rng: &mut R,
+    ) -> Vec<Location> {
+        let pool: Vec<&Location> = self
+            .playable()
+            .filter(|l| !seen.contains(&l.image_id))
+            .collect();
+        let mut picked = Vec::new();
+        let mut available: Vec<usize> = (0..pool.len()).collect();
+        // Distance from each location to the nearest picked one
+        let mut nearest_km = vec![f64::INFINITY; pool.len()];
+        while picked.len() < count && !available.is_empty() {
+            let index = if picked.is_empty() {
+                available.choose(rng).copied()
+            } else {
+                available
+                    .choose_weighted(rng, |i| nearest_km[*i])
+                    .or_else(|_| available.choose(rng).ok_or(()))
+                    .ok()
+                    .copied()
+            };
+            let Some(index) = index else {
+                break;
+            };
+            available.retain(|i| *i != index);
+            let chosen = pool[index];
+            for i in &available {
+                let d = haversine_km(chosen.lat, chosen.lng, pool[*i].lat, pool[*i].lng);
+                nearest_km[*i] = nearest_km[*i].min(d);
+            }
+            picked.push(chosen.clone());
+        }
+        picked
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        let mut bounds = Bounds {
+            min_lat: f32::MAX,
+            min_lng: f32::MAX,
+            max_lat: f32::MIN,
+            max_lng: f32::MIN,
+        };
+        for l in self.playable() {
+            bounds.min_lat = bounds.min_lat.min(l.lat);
+            bounds.min_lng = bounds.min_lng.min(l.lng);
+            bounds.max_lat = bounds.max_lat.max(l.lat);
+            bounds.max_lng = bounds.max_lng.max(l.lng);
+        }
+        bounds
+    }
+
+    /// Mean distance of the locations from their centroid, i.e. how far off
+    /// a guess in the middle of the map would usually be
+    pub fn spread_km(&self) -> f64 {
+        let locations: Vec<&Location> = self.playable().collect();
+        if locations.is_empty() {
+            return 0.0;
+        }
+        let n = locations.len() as f32;
+        let lat = locations.iter().map(|l| l.lat).sum::<f32>() / n;
+        let lng = locations.iter().map(|l| l.lng).sum::<f32>() / n;
+        locations
+            .iter()
+            .map(|l| haversine_km(lat, lng, l.lat, l.lng))
+            .sum::<f64>()
+            / n as f64
+    }
+
+    /// Guesses lose about 63% of the distance score every this many km. Derived maps use
+    /// a tenth of the bounding box diagonal, the same ratio as 2000km on the world map.
+    pub fn score_scale_km(&self) -> f64 {
+        self.score_scale_km.unwrap_or_else(|| {
+            let b = self.bounds();
+            (haversine_km(b.min_lat, b.min_lng, b.max_lat, b.max_lng) / 10.0)
+                .max(MIN_SCORE_SCALE_KM)
+        })
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        match self.spread_km() {
+            d if d < 100.0 => Difficulty::Easy,
+            d if d < 1500.0 => Difficulty::Medium,
+            _ => Difficulty::Hard,
+        }
+    }
+}
+
+const MIN_SCORE_SCALE_KM: f64 = 0.05;
+
+/// Ray casting point in polygon test on (lat, lng) vertices. Treats coordinates as planar,
+/// so regions shouldn't cross the antimeridian. Nothing is inside fewer than 3 vertices.
+pub(crate) fn in_polygon(polygon: &[(f32, f32)], lat: f32, lng: f32) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (lat_i, lng_i) = polygon[i];
+        let (lat_j, lng_j) = polygon[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lng < (lng_j - lng_i) * (lat - lat_i) / (lat_j - lat_i) + lng_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Bounds {
+    pub min_lat: f32,
+    pub min_lng: f32,
+    pub max_lat: f32,
+    pub max_lng: f32,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
 }
 
 macro_rules! load_locations {
@@ -15,31 +175,442 @@ macro_rules! load_locations {
     };
 }
 
-pub static MAPS: LazyLock<HashMap<String, Map>> = LazyLock::new(|| {
-    let mut m = HashMap::new();
-    m.insert(
-        "World".to_string(),
+fn builtin_maps() -> Vec<Map> {
+    vec![
         Map {
+            name: "World".to_string(),
             center: (0.0, 0.0),
             locations: load_locations!("../geo_data/world.json"),
             zoom: 3,
+            region: None,
+            score_scale_km: Some(2000.0),
         },
-    );
-    m.insert(
-        "Australian Cities".to_string(),
         Map {
+            name: "Australian Cities".to_string(),
             center: (-25.2744, 133.7751),
             locations: load_locations!("../geo_data/australian_cities.json"),
             zoom: 4,
+            region: None,
+            score_scale_km: None,
         },
-    );
-    m.insert(
-        "Sydney".to_string(),
         Map {
+            name: "Sydney".to_string(),
             center: (-33.8688, 151.2093),
             locations: load_locations!("../geo_data/sydney.json"),
             zoom: 10,
+            region: None,
+            score_scale_km: None,
         },
-    );
-    m
-});
\ No newline at end of file
+    ]
+}
+
+pub static MAPS: LazyLock<MapStore> = LazyLock::new(MapStore::load);
+
+pub fn maps_dir() -> PathBuf {
+    PathBuf::from(env::var("MAPS_DIR").unwrap_or("maps".to_string()))
+}
+
+/// ===============================================
+/// Map Store
+/// ===============================================
+/// The compiled in maps plus any uploaded at runtime, which are saved as JSON
+/// files in `MAPS_DIR` and loaded again on startup.
+pub(crate) struct MapStore {
+    maps: RwLock<HashMap<String, Arc<Map>>>,
+    builtin: HashSet<String>,
+}
+
+impl MapStore {
+    fn load() -> Self {
+        let mut maps = HashMap::new();
+        let mut builtin = HashSet::new();
+        for map in builtin_maps() {
+            builtin.insert(map.name.clone());
+            maps.insert(map.name.clone(), Arc::new(map));
+        }
+
+        let dir = maps_dir();
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for path in entries.flatten().map(|e| e.path()) {
+                if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                // Saved maps are read back as uploads, so a hand edited file gets the same checks
+                let map = std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|contents| {
+                        serde_json::from_str::<MapUpload>(&contents).map_err(|e| e.to_string())
+                    })
+                    .and_then(MapUpload::into_map);
+                match map {
+                    Ok(map) if !builtin.contains(&map.name) => {
+                        maps.insert(map.name.clone(), Arc::new(map));
+                    }
+                    Ok(map) => warn!(
+                        "Ignoring {}, {} is a built in map",
+                        path.display(),
+                        map.name
+                    ),
+                    Err(e) => warn!("Failed to load map {}: {}", path.display(), e),
+                }
+            }
+        }
+        info!("Loaded {} GeoGuessr maps", maps.len());
+        MapStore {
+            maps: RwLock::new(maps),
+            builtin,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Map>> {
+        self.maps.read().unwrap().get(name).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.maps.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.maps.read().unwrap().is_empty()
+    }
+
+    pub fn list(&self) -> Vec<MapSummary> {
+        let mut maps: Vec<MapSummary> = self
+            .maps
+            .read()
+            .unwrap()
+            .values()
+            .map(|map| MapSummary::new(map, !self.builtin.contains(&map.name)))
+            .collect();
+        maps.sort_by(|a, b| a.custom.cmp(&b.custom).then_with(|| a.name.cmp(&b.name)));
+        maps
+    }
+
+    /// Writes the map to `MAPS_DIR` and makes it available to new games.
+    /// Uploading a map with an existing custom map's name replaces it.
+    pub async fn save(&self, map: Map) -> Result<MapSummary, String> {
+        if self.builtin.contains(&map.name) {
+            return Err(format!("{} is a built in map", map.name));
+        }
+        let dir = maps_dir();
+        let path = dir.join(format!("{}.json", file_stem(&map.name)));
+        let json = serde_json::to_string(&map).map_err(|e| e.to_string())?;
+        let saved = match tokio::fs::create_dir_all(&dir).await {
+            Ok(_) => tokio::fs::write(&path, json).await,
+            Err(e) => Err(e),
+        };
+        saved.map_err(|e| {
+            warn!("Failed to save map to {}: {:?}", path.display(), e);
+            "Failed to save map".to_string()
+        })?;
+
+        info!(
+            "Saved map {} with {} locations",
+            map.name,
+            map.locations.len()
+        );
+        let summary = MapSummary::new(&map, true);
+        self.maps
+            .write()
+            .unwrap()
+            .insert(map.name.clone(), Arc::new(map));
+        Ok(summary)
+    }
+}
+
+/// Lowercase ascii and dashes, so names can't escape the maps directory, followed by a hash
+/// of the exact name, so names that read the same here (e.g. "Europe!" and "Europe?") don't
+/// share a file
+fn file_stem(name: &str) -> String {
+    let readable: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let hash: String = Sha256::digest(name.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}-{}", readable, hash)
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MapSummary {
+    pub name: String,
+    pub center: (f32, f32),
+    pub zoom: u8,
+    pub location_count: usize,
+    pub bounds: Bounds,
+    pub difficulty: Difficulty,
+    pub score_scale_km: f64,
+    pub custom: bool,
+}
+
+impl MapSummary {
+    fn new(map: &Map, custom: bool) -> Self {
+        MapSummary {
+            name: map.name.clone(),
+            center: map.center,
+            zoom: map.zoom,
+            location_count: map.playable().count(),
+            bounds: map.bounds(),
+            difficulty: map.difficulty(),
+            score_scale_km: map.score_scale_km(),
+            custom,
+        }
+    }
+}
+
+/// ===============================================
+/// Uploads
+/// ===============================================
+const MAX_MAP_NAME_LENGTH: usize = 40;
+const MAX_IMAGE_ID_LENGTH: usize = 128;
+const MAX_MAP_LOCATIONS: usize = 10_000;
+const MAX_ZOOM: u8 = 22;
+const DEFAULT_UPLOAD_ZOOM: u8 = 3;
+
+/// A map upload, either with a plain `locations` list or as a GeoJSON
+/// FeatureCollection of points whose properties carry the image id.
+/// A region can be given as (lat, lng) pairs or as a single GeoJSON polygon feature.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MapUpload {
+    pub name: String,
+    pub center: Option<(f32, f32)>,
+    pub zoom: Option<u8>,
+    pub region: Option<Vec<(f32, f32)>>,
+    pub score_scale_km: Option<f64>,
+    #[serde(default)]
+    pub locations: Vec<Location>,
+    #[serde(default)]
+    pub features: Vec<GeoJsonFeature>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct GeoJsonFeature {
+    pub geometry: Option<GeoJsonGeometry>,
+    #[serde(default)]
+    pub properties: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub(crate) enum GeoJsonGeometry {
+    // GeoJSON positions are [lng, lat]
+    Point {
+        coordinates: Vec<f64>,
+    },
+    // Only the outer ring is used
+    Polygon {
+        coordinates: Vec<Vec<Vec<f64>>>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl MapUpload {
+    /// Checks the upload and turns it into a map, centred on its locations if no center was given
+    pub fn into_map(self) -> Result<Map, String> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_MAP_NAME_LENGTH {
+            return Err(format!(
+                "Map name must be between 1 and {} characters",
+                MAX_MAP_NAME_LENGTH
+            ));
+        }
+        if !self.locations.is_empty() && !self.features.is_empty() {
+            return Err("Provide either locations or GeoJSON features, not both".to_string());
+        }
+
+        let mut locations = self.locations;
+        let mut region = self.region;
+        for (i, feature) in self.features.into_iter().enumerate() {
+            match feature.geometry {
+                Some(GeoJsonGeometry::Polygon { coordinates }) => {
+                    if region.is_some() {
+                        return Err("Maps can only have one region".to_string());
+                    }
+                    region = Some(polygon_ring(i, coordinates)?);
+                }
+                _ => locations.push(feature_location(i, feature)?),
+            }
+        }
+        if locations.is_empty() {
+            return Err("Map has no locations".to_string());
+        }
+        if locations.len() > MAX_MAP_LOCATIONS {
+            return Err(format!(
+                "Maps can have at most {} locations",
+                MAX_MAP_LOCATIONS
+            ));
+        }
+
+        let mut seen = HashSet::new();
+        for location in &locations {
+            validate_image_id(&location.image_id)?;
+            if !valid_coordinates(location.lat, location.lng) {
+                return Err(format!(
+                    "Invalid coordinates for {}: ({}, {})",
+                    location.image_id, location.lat, location.lng
+                ));
+            }
+            if !seen.insert(location.image_id.as_str()) {
+                return Err(format!("Duplicate image id {}", location.image_id));
+            }
+        }
+
+        if let Some(region) = &region {
+            if region.len() < 3 {
+                return Err("Region needs at least 3 points".to_string());
+            }
+            if let Some((lat, lng)) = region
+                .iter()
+                .find(|(lat, lng)| !valid_coordinates(*lat, *lng))
+            {
+                return Err(format!("Invalid region point ({}, {})", lat, lng));
+            }
+        }
+        let zoom = self.zoom.unwrap_or(DEFAULT_UPLOAD_ZOOM);
+        if zoom > MAX_ZOOM {
+            return Err(format!("Zoom must be at most {}", MAX_ZOOM));
+        }
+        if let Some(scale) = self.score_scale_km
+            && !(scale.is_finite() && scale >= MIN_SCORE_SCALE_KM)
+        {
+            return Err(format!(
+                "Score scale must be at least {}km",
+                MIN_SCORE_SCALE_KM
+            ));
+        }
+        let mut map = Map {
+            name,
+            center: (0.0, 0.0),
+            locations,
+            zoom,
+            region,
+            score_scale_km: self.score_scale_km,
+        };
+
+        let playable: Vec<&Location> = map.playable().collect();
+        if playable.is_empty() {
+            return Err("No locations are inside the region".to_string());
+        }
+        map.center = match self.center {
+            Some((lat, lng)) if valid_coordinates(lat, lng) => (lat, lng),
+            Some((lat, lng)) => return Err(format!("Invalid center ({}, {})", lat, lng)),
+            None => {
+                let n = playable.len() as f32;
+                (
+                    playable.iter().map(|l| l.lat).sum::<f32>() / n,
+                    playable.iter().map(|l| l.lng).sum::<f32>() / n,
+                )
+            }
+        };
+        Ok(map)
+    }
+}
+
+fn feature_location(index: usize, feature: GeoJsonFeature) -> Result<Location, String> {
+    let Some(GeoJsonGeometry::Point { coordinates }) = feature.geometry else {
+        return Err(format!("Feature {} is not a point", index));
+    };
+    let [lng, lat, ..] = coordinates[..] else {
+        return Err(format!("Feature {} has invalid coordinates", index));
+    };
+    let image_id = ["imageId", "panoId", "id"]
+        .iter()
+        .find_map(|key| match &feature.properties[key] {
+            serde_json::Value::String(id) => Some(id.clone()),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        })
+        .ok_or_else(|| format!("Feature {} has no imageId property", index))?;
+    Ok(Location {
+        image_id,
+        lat: lat as f32,
+        lng: lng as f32,
+    })
+}
+
+fn polygon_ring(index: usize, rings: Vec<Vec<Vec<f64>>>) -> Result<Vec<(f32, f32)>, String> {
+    let ring = rings
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("Feature {} has an empty polygon", index))?;
+    ring.iter()
+        .map(|position| match position[..] {
+            [lng, lat, ..] => Ok((lat as f32, lng as f32)),
+            _ => Err(format!("Feature {} has invalid coordinates", index)),
+        })
+        .collect()
+}
+
+fn valid_coordinates(lat: f32, lng: f32) -> bool {
+    lat.is_finite()
+        && lng.is_finite()
+        && (-90.0..=90.0).contains(&lat)
+        && (-180.0..=180.0).contains(&lng)
+}
+
+/// Street view image ids are url safe tokens (Mapillary ids are numeric, Google pano ids base64url)
+fn validate_image_id(image_id: &str) -> Result<(), String> {
+    if image_id.is_empty()
+        || image_id.len() > MAX_IMAGE_ID_LENGTH
+        || !image_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!("Invalid image id '{}'", image_id));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_stems_keep_similar_names_apart() {
+        assert_ne!(file_stem("Europe!"), file_stem("Europe?"));
+        assert_ne!(file_stem("日本"), file_stem("中国"));
+        assert_eq!(file_stem("Europe!"), file_stem("Europe!"));
+        assert!(file_stem("../Europe").starts_with("---europe-"));
+    }
+
+    #[test]
+    fn nothing_is_inside_a_degenerate_polygon() {
+        assert!(!in_polygon(&[], 0.0, 0.0));
+        assert!(!in_polygon(&[(0.0, 0.0), (1.0, 1.0)], 0.5, 0.5));
+        let square = [(0.0, 0.0), (0.0, 2.0), (2.0, 2.0), (2.0, 0.0)];
+        assert!(in_polygon(&square, 1.0, 1.0));
+        assert!(!in_polygon(&square, 3.0, 1.0));
+    }
+
+    #[test]
+    fn saved_maps_are_validated_like_uploads() {
+        let valid = r#"{"name":"Park","center":[1.0,1.0],"zoom":12,
+            "locations":[{"imageId":"a","lat":1.0,"lng":1.0}]}"#;
+        let map = serde_json::from_str::<MapUpload>(valid)
+            .unwrap()
+            .into_map()
+            .unwrap();
+        assert_eq!(map.center, (1.0, 1.0));
+
+        let bad_region = r#"{"name":"Park","center":[1.0,1.0],"zoom":12,"region":[],
+            "locations":[{"imageId":"a","lat":1.0,"lng":1.0}]}"#;
+        assert!(
+            serde_json::from_str::<MapUpload>(bad_region)
+                .unwrap()
+                .into_map()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::{env, path::PathBuf, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::geo_guessr::api::in_polygon;
+
+/// ===============================================
+/// Offline Reverse Geocoding
+/// ===============================================
+/// Country borders as a GeoJSON FeatureCollection, each feature a Polygon or MultiPolygon
+/// with an ISO code and name property. The file isn't part of the repo, fetch Natural Earth's
+/// admin 0 countries (public domain) to `COUNTRIES_PATH`, e.g. from `backend/`:
+///
+/// curl -L --create-dirs -o data/countries.geojson \
+///     https://raw.githubusercontent.com/nvkelso/natural-earth-vector/master/geojson/ne_110m_admin_0_countries.geojson
+///
+/// The Docker image does this at build time. Without the file every lookup comes back None.
+pub static COUNTRIES: LazyLock<Vec<Country>> = LazyLock::new(|| {
+    let path = countries_path();
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!(
+                "No country borders at {} ({}), country lookups are disabled",
+                path.display(),
+                e
+            );
+            return Vec::new();
+        }
+    };
+    match parse_countries(&contents) {
+        Ok(countries) => {
+            info!("Loaded {} countries", countries.len());
+            countries
+        }
+        Err(e) => {
+            warn!("Failed to parse {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+});
+
+pub fn countries_path() -> PathBuf {
+    PathBuf::from(env::var("COUNTRIES_PATH").unwrap_or("data/countries.geojson".to_string()))
+}
+
+fn parse_countries(contents: &str) -> serde_json::Result<Vec<Country>> {
+    let collection: FeatureCollection = serde_json::from_str(contents)?;
+    Ok(collection
+        .features
+        .into_iter()
+        .filter_map(|feature| {
+            let country = Country::from_feature(feature);
+            if country.is_none() {
+                warn!("Skipping country without an ISO code, name or polygon");
+            }
+            country
+        })
+        .collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct CountryRef {
+    pub code: String,
+    pub name: String,
+}
+
+pub(crate) struct Country {
+    pub code: String,
+    pub name: String,
+    // Each polygon is an outer ring followed by any holes, as (lat, lng)
+    polygons: Vec<Vec<Vec<(f32, f32)>>>,
+    // (min lat, min lng, max lat, max lng), checked before the polygons
+    bbox: (f32, f32, f32, f32),
+}
+
+impl Country {
+    fn from_feature(feature: Feature) -> Option<Self> {
+        // Natural Earth has "-99" as the ISO_A2 of a few countries, France among them
+        let code = ["ISO_A2", "iso_a2", "ISO_A2_EH", "code"]
+            .iter()
+            .find_map(|key| {
+                feature.properties[key]
+                    .as_str()
+                    .filter(|code| code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()))
+            })?
+            .to_string();
+        let name = ["NAME", "name", "ADMIN", "admin"]
+            .iter()
+            .find_map(|key| feature.properties[key].as_str())?
+            .to_string();
+        let polygons: Vec<Vec<Vec<(f32, f32)>>> = match feature.geometry? {
+            Geometry::Polygon { coordinates } => vec![rings(coordinates)],
+            Geometry::MultiPolygon { coordinates } => coordinates.into_iter().map(rings).collect(),
+            Geometry::Other => return None,
+        };
+        let mut bbox = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for &(lat, lng) in polygons.iter().filter_map(|p| p.first()).flatten() {
+            bbox = (
+                bbox.0.min(lat),
+                bbox.1.min(lng),
+                bbox.2.max(lat),
+                bbox.3.max(lng),
+            );
+        }
+        Some(Country {
+            code,
+            name,
+            polygons,
+            bbox,
+        })
+    }
+
+    fn contains(&self, lat: f32, lng: f32) -> bool {
+        let (min_lat, min_lng, max_lat, max_lng) = self.bbox;
+        if lat < min_lat || lat > max_lat || lng < min_lng || lng > max_lng {
+            return false;
+        }
+        self.polygons
+            .iter()
+            .any(|polygon| match polygon.split_first() {
+                Some((outer, holes)) => {
+                    in_polygon(outer, lat, lng) && !holes.iter().any(|h| in_polygon(h, lat, lng))
+                }
+                None => false,
+            })
+    }
+
+    pub fn to_ref(&self) -> CountryRef {
+        CountryRef {
+            code: self.code.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+/// The country a point falls in, or None for the sea and anywhere the dataset doesn't cover
+pub fn country_at(lat: f32, lng: f32) -> Option<&'static Country> {
+    find_country(&COUNTRIES, lat, lng)
+}
+
+/// Whether any country borders were loaded, country streak can't be played without them
+pub fn countries_loaded() -> bool {
+    !COUNTRIES.is_empty()
+}
+
+fn find_country(countries: &[Country], lat: f32, lng: f32) -> Option<&Country> {
+    countries.iter().find(|c| c.contains(lat, lng))
+}
+
+fn rings(coordinates: Vec<Vec<Vec<f64>>>) -> Vec<Vec<(f32, f32)>> {
+    coordinates
+        .into_iter()
+        .map(|ring| {
+            ring.into_iter()
+                .filter_map(|position| match position[..] {
+                    [lng, lat, ..] => Some((lat as f32, lng as f32)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|ring| ring.len() >= 3)
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    geometry: Option<Geometry>,
+    #[serde(default)]
+    properties: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Geometry {
+    Polygon {
+        coordinates: Vec<Vec<Vec<f64>>>,
+    },
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Vec<f64>>>>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shaped like Natural Earth's features, including France's missing ISO_A2
+    const BORDERS: &str = r#"{"type": "FeatureCollection", "features": [
+        {"type": "Feature", "properties": {"ISO_A2": "-99", "ISO_A2_EH": "FR", "NAME": "France"},
+         "geometry": {"type": "MultiPolygon", "coordinates": [
+            [[[-4.6, 48.7], [2.5, 51.1], [8.2, 49.0], [7.4, 43.7], [3.1, 42.4], [-1.8, 43.4], [-4.6, 48.7]]],
+            [[[8.6, 42.6], [9.6, 42.2], [9.2, 41.4], [8.6, 41.9], [8.6, 42.6]]]]}},
+        {"type": "Feature", "properties": {"ISO_A2": "LS", "NAME": "Lesotho"},
+         "geometry": {"type": "Polygon", "coordinates": [[[27.0, -30.6], [29.4, -30.6], [29.4, -28.6], [27.0, -28.6], [27.0, -30.6]]]}},
+        {"type": "Feature", "properties": {"ISO_A2": "ZA", "NAME": "South Africa"},
+         "geometry": {"type": "Polygon", "coordinates": [
+            [[16.5, -34.8], [32.9, -34.8], [32.9, -22.1], [16.5, -22.1], [16.5, -34.8]],
+            [[27.0, -30.6], [29.4, -30.6], [29.4, -28.6], [27.0, -28.6], [27.0, -30.6]]]}},
+        {"type": "Feature", "properties": {"ISO_A2": "-99", "NAME": "Nowhere"},
+         "geometry": {"type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0]]]}}
+    ]}"#;
+
+    fn code_at(countries: &[Country], lat: f32, lng: f32) -> Option<&str> {
+        find_country(countries, lat, lng).map(|c| c.code.as_str())
+    }
+
+    #[test]
+    fn falls_back_to_other_iso_codes() {
+        let countries = parse_countries(BORDERS).unwrap();
+        // Nowhere has no usable code at all
+        assert_eq!(countries.len(), 3);
+        assert_eq!(code_at(&countries, 48.86, 2.35), Some("FR"));
+        // Corsica
+        assert_eq!(code_at(&countries, 42.0, 9.0), Some("FR"));
+    }
+
+    #[test]
+    fn holes_belong_to_the_enclosed_country() {
+        let countries = parse_countries(BORDERS).unwrap();
+        assert_eq!(code_at(&countries, -29.3, 28.2), Some("LS"));
+        assert_eq!(code_at(&countries, -33.9, 18.4), Some("ZA"));
+        assert_eq!(code_at(&countries, 30.0, -40.0), None);
+    }
+
+    // Needs the real dataset at `COUNTRIES_PATH`, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn resolves_points_with_the_installed_borders() {
+        assert!(
+            countries_loaded(),
+            "No borders at {}",
+            countries_path().display()
+        );
+        let code = |lat, lng| country_at(lat, lng).map(|c| c.code.as_str());
+        // Paris and Lyon
+        assert_eq!(code(48.8566, 2.3522), Some("FR"));
+        assert_eq!(code(45.764, 4.8357), Some("FR"));
+        assert_eq!(code(-33.8688, 151.2093), Some("AU"));
+        assert_eq!(code(35.6762, 139.6503), Some("JP"));
+        assert_eq!(code(-1.2921, 36.8219), Some("KE"));
+        assert_eq!(code(-29.31, 27.48), Some("LS"));
+        // Middle of the Atlantic
+        assert_eq!(code(30.0, -40.0), None);
+    }
+}
@@ -1,18 +1,28 @@
 use axum::{
     Json,
     extract::{
-        State,
+        Path, State,
         ws::{Message, WebSocket},
     },
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
 use futures_util::{SinkExt, StreamExt};
 use tracing::{Instrument, info, instrument, warn};
+use uuid::Uuid;
 pub mod api;
+pub mod countries;
+pub mod results;
 
 use crate::{
-    connections::ConnectionGuard,
+    accounts::authenticate,
+    connections::{ConnectionGuard, forward_events},
     generate_lobby_code,
+    geo_guessr::{
+        api::{MAPS, MapUpload},
+        results::{GameSummary, RESULTS},
+    },
+    history::find_game,
     state::{
         AppState, LobbyServerEvent,
         geoguessr::{GeoGuesserClientEvent, GeoGuessr, GeoGuessrGameEvent, GeoGuessrServerEvent},
@@ -36,16 +46,56 @@ pub async fn create_geo_guessr_lobby(State(state): State<AppState>) -> impl Into
     state.games.add_geo_guessr_lobby(&lobby_code);
     info!("Added lobby {lobby_code}");
 
-    Json(CreateLobbyResponse {
-        lobby_code: lobby_code,
-    })
+    Json(CreateLobbyResponse { lobby_code })
+}
+
+pub async fn list_maps() -> impl IntoResponse {
+    Json(MAPS.list())
+}
+
+/// Only registered players can upload, so every custom map has an account behind it
+#[instrument(name = "UPLOAD MAP", skip_all, fields(name = %upload.name))]
+pub async fn upload_map(headers: HeaderMap, Json(upload): Json<MapUpload>) -> Response {
+    let account = match authenticate(&headers).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let Some(username) = account.username else {
+        return (StatusCode::FORBIDDEN, "Sign in to upload maps").into_response();
+    };
+    info!(uploader = %username, "Map upload");
+    let map = match upload.into_map() {
+        Ok(map) => map,
+        Err(e) => {
+            info!("Rejected map upload: {}", e);
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    };
+    match MAPS.save(map).await {
+        Ok(summary) => (StatusCode::CREATED, Json(summary)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// Recent games are served from `RESULTS`, older ones are rebuilt from the game history
+pub async fn get_results(Path(game_id): Path<Uuid>) -> impl IntoResponse {
+    if let Some(summary) = RESULTS.get(&game_id) {
+        return Json(summary).into_response();
+    }
+    match find_game(game_id).await.and_then(GameSummary::from_record) {
+        Some(summary) => {
+            RESULTS.insert(game_id, summary.clone());
+            Json(summary).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Results not found").into_response(),
+    }
 }
 
 pub async fn handle_geo_guessr(socket: WebSocket, state: AppState) {
     let (mut sender, mut receiver) = socket.split();
-    let (lobby_code, player_username) =
+    let (lobby_code, player_username, account_id) =
         match GeoGuessr::await_join_req(&mut receiver, &mut sender).await {
-            Ok((lobby_code, player_username)) => (lobby_code, player_username),
+            Ok(join) => join,
             Err(_) => return,
         };
 
@@ -68,7 +118,8 @@ pub async fn handle_geo_guessr(socket: WebSocket, state: AppState) {
         }
     };
 
-    let player_id = game_obj.lobby.lock().unwrap().get_new_player_id();
+    let player_id =
+        account_id.unwrap_or_else(|| game_obj.lobby.lock().unwrap().get_new_player_id());
 
     let connection_span = tracing::info_span!(
         "connection",
@@ -76,7 +127,7 @@ pub async fn handle_geo_guessr(socket: WebSocket, state: AppState) {
         player=%player_id,
     );
 
-    match game_obj.player_join(player_id.clone(), player_username.clone()) {
+    match game_obj.player_join(player_id, player_username.clone()) {
         Ok(_) => {
             info!("Player: {}, joined lobby: {}", player_id, lobby_code);
         }
@@ -96,16 +147,20 @@ pub async fn handle_geo_guessr(socket: WebSocket, state: AppState) {
         }
     }
 
+    let connection_id = Uuid::new_v4();
     let _guard = ConnectionGuard {
         game: game_obj.clone(),
-        player_id: player_id.clone(),
-        cleanup_tx: state.cleanup.clone(),
+        player_id,
+        connection_id,
+        state: state.clone(),
     };
 
     // Clone the broadcast channel into tx (Sender)
     let tx = game_obj.broadcast.clone();
     // Subscribe to the broadcast channel to aquire a (Receiver)
-    let mut rx = tx.subscribe();
+    let rx = tx.subscribe();
+    // Register a private channel for events targeted at this player only
+    let direct_rx = game_obj.direct.register(player_id, connection_id);
 
     // Extract the gamestate
     let _ = sender
@@ -116,6 +171,9 @@ pub async fn handle_geo_guessr(socket: WebSocket, state: AppState) {
                     settings: game_obj.get_settings(),
                     leaderboard: game_obj.get_leaderboard(),
                     status: game_obj.get_lobby_status(),
+                    preset: game_obj.get_settings().active_preset(),
+                    teams: game_obj.get_teams(),
+                    team_leaderboard: game_obj.get_team_leaderboard(),
                 },
             ))
             .expect("Failed to parse SyncState event")
@@ -123,29 +181,13 @@ pub async fn handle_geo_guessr(socket: WebSocket, state: AppState) {
         ))
         .await;
 
-    // Create the send_task
-    let mut send_task: tokio::task::JoinHandle<()> = tokio::spawn(
-        async move {
-            while let Ok(msg) = rx.recv().await {
-                match serde_json::to_string(&msg) {
-                    Ok(json) => {
-                        if sender.send(Message::Text(json.into())).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Serialization error: {:?}", e);
-                        continue;
-                    }
-                }
-            }
-        }
-        .instrument(connection_span.clone()),
-    );
+    // Create the send_task, merging the lobby broadcast with this player's private channel
+    let mut send_task: tokio::task::JoinHandle<()> =
+        tokio::spawn(forward_events(sender, rx, direct_rx).instrument(connection_span.clone()));
 
     let _ = game_obj.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
         LobbyServerEvent::PlayerJoin {
-            player_id: player_id.clone(),
+            player_id,
             player_username: player_username.clone(),
         },
     ));
@@ -0,0 +1,55 @@
+use std::{collections::HashMap, sync::LazyLock, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    cache::TtlCache,
+    state::{
+        TeamLeaderboard,
+        geoguessr::{GeoGuessrMode, Location, PlayerRoundResult},
+    },
+};
+
+/// ===============================================
+/// Game Results
+/// ===============================================
+/// Summaries of finished games, kept for a day so they can be shared and replayed.
+/// Expired summaries are dropped by the periodic cleanup.
+pub static RESULTS: LazyLock<TtlCache<Uuid, GameSummary>> =
+    LazyLock::new(|| TtlCache::new(RESULTS_TTL));
+
+const RESULTS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GameSummary {
+    pub game_id: Uuid,
+    pub lobby_code: String,
+    pub map: String,
+    pub mode: GeoGuessrMode,
+    // Unix timestamp in seconds
+    pub finished_at: u64,
+    // Ordered by rank
+    pub players: Vec<PlayerSummary>,
+    pub rounds: Vec<RoundSummary>,
+    pub team_leaderboard: Option<TeamLeaderboard>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PlayerSummary {
+    pub player_id: Uuid,
+    pub username: String,
+    pub score: u32,
+    // Tied players share a rank, e.g. 1, 1, 3
+    pub rank: u32,
+    pub team: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RoundSummary {
+    pub location: Location,
+    pub results: HashMap<Uuid, PlayerRoundResult>,
+}
@@ -1,96 +1,410 @@
-use std::{env, sync::Arc};
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 // src/spotify.rs
 use dotenv::dotenv;
-use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
-use rspotify::{ClientCredsSpotify, Credentials, clients::BaseClient, model::PlaylistId};
+use futures_util::{StreamExt, stream};
+use rand::{
+    Rng, SeedableRng,
+    rngs::StdRng,
+    seq::{IndexedRandom, SliceRandom},
+};
+use rspotify::{ClientCredsSpotify, Credentials};
+use serde::Serialize;
 use tracing::{info, instrument, warn};
 
-use crate::state::{GuessTheSongGame, SongState};
-
-pub async fn get_spotify_client() -> ClientCredsSpotify {
-    dotenv().ok();
-    let id = env::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID must be set");
-    let secret = env::var("SPOTIFY_CLIENT_SECRET").expect("SPOTIFY_CLIENT_SECRET must be set");
-    let creds = Credentials::new(&id, &secret);
-    // ClientCredsSpotify is used for the "Client Credentials Flow"
-    // (Server-to-Server, no user login required)
-    let spotify = ClientCredsSpotify::new(creds);
-
-    // This requests the initial token.
-    // rspotify creates a specialized client that checks token expiration
-    // before every future request and refreshes it automatically if needed.
-    spotify
-        .request_token()
-        .await
-        .expect("Failed to get initial Spotify token");
+use crate::{
+    cache::TtlCache,
+    denylist::{denied, song_key},
+    guess_the_song::sources::{self, Track, parse_year},
+    state::{
+        AppState, GameType, GuessMode, GuessTheSongGame, GuessTheSongGameSettings,
+        GuessTheSongServerEvent, PREVIEW_LENGTH_SECONDS, Song, SongState,
+    },
+};
+
+const SPOTIFY_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum IntegrationStatus {
+    Ok,
+    Pending,
+    Unconfigured,
+    Unavailable,
+}
+
+struct SpotifyClientState {
+    client: Option<Arc<ClientCredsSpotify>>,
+    last_attempt: Option<Instant>,
+    last_error: Option<String>,
+}
+
+/// Lazily creates the Spotify client on first use and retries failed token requests
+/// (at most once per `SPOTIFY_RETRY_BACKOFF`), so the server boots without Spotify.
+pub(crate) struct SpotifyProvider {
+    credentials: Option<Credentials>,
+    state: tokio::sync::Mutex<SpotifyClientState>,
+}
+
+impl SpotifyProvider {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let credentials = match (
+            env::var("SPOTIFY_CLIENT_ID"),
+            env::var("SPOTIFY_CLIENT_SECRET"),
+        ) {
+            (Ok(id), Ok(secret)) => Some(Credentials::new(&id, &secret)),
+            _ => {
+                warn!(
+                    "SPOTIFY_CLIENT_ID/SPOTIFY_CLIENT_SECRET not set, Spotify playlists disabled"
+                );
+                None
+            }
+        };
+        SpotifyProvider {
+            credentials,
+            state: tokio::sync::Mutex::new(SpotifyClientState {
+                client: None,
+                last_attempt: None,
+                last_error: None,
+            }),
+        }
+    }
+
+    pub async fn get(&self) -> Result<Arc<ClientCredsSpotify>, String> {
+        let Some(creds) = &self.credentials else {
+            return Err("Spotify is not configured on this server".to_string());
+        };
+        let mut state = self.state.lock().await;
+        if let Some(client) = &state.client {
+            return Ok(client.clone());
+        }
+        if state
+            .last_attempt
+            .is_some_and(|t| t.elapsed() < SPOTIFY_RETRY_BACKOFF)
+        {
+            return Err("Spotify is currently unavailable, try again shortly".to_string());
+        }
+
+        state.last_attempt = Some(Instant::now());
+        // ClientCredsSpotify is used for the "Client Credentials Flow"
+        // (Server-to-Server, no user login required)
+        let spotify = ClientCredsSpotify::new(creds.clone());
+
+        // This requests the initial token.
+        // rspotify creates a specialized client that checks token expiration
+        // before every future request and refreshes it automatically if needed.
+        match spotify.request_token().await {
+            Ok(_) => {
+                info!("Spotify client initialised");
+                let client = Arc::new(spotify);
+                state.client = Some(client.clone());
+                state.last_error = None;
+                Ok(client)
+            }
+            Err(e) => {
+                warn!("Failed to get Spotify token: {:?}", e);
+                state.last_error = Some(e.to_string());
+                Err("Spotify is currently unavailable, try again shortly".to_string())
+            }
+        }
+    }
 
-    spotify
+    /// Current status without triggering a token request
+    pub fn status(&self) -> (IntegrationStatus, Option<String>) {
+        if self.credentials.is_none() {
+            return (IntegrationStatus::Unconfigured, None);
+        }
+        match self.state.try_lock() {
+            Ok(state) if state.client.is_some() => (IntegrationStatus::Ok, None),
+            Ok(state) if state.last_error.is_some() => {
+                (IntegrationStatus::Unavailable, state.last_error.clone())
+            }
+            _ => (IntegrationStatus::Pending, None),
+        }
+    }
 }
 
-#[instrument(skip(game, spotify_client, playlist_link), fields(lobby=%game.lobby_code))]
+// Deezer preview URLs are signed and expire, so neither cache can hold them for long
+const PREVIEW_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const PLAYLIST_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const PREVIEW_CONCURRENCY: usize = 8;
+// Look at up to this many tracks per requested song before giving up on filling the game
+const OVERSAMPLE_FACTOR: usize = 4;
+const MULTIPLE_CHOICE_OPTIONS: usize = 4;
+
+pub(crate) struct SongCache {
+    // ISRC -> Deezer track details, including tracks Deezer doesn't have, but never failed lookups
+    pub previews: TtlCache<String, Option<DeezerTrack>>,
+    // Playlist link -> tracks
+    pub playlists: TtlCache<String, Vec<Track>>,
+}
+
+impl SongCache {
+    pub fn new() -> Self {
+        SongCache {
+            previews: TtlCache::new(PREVIEW_CACHE_TTL),
+            playlists: TtlCache::new(PLAYLIST_CACHE_TTL),
+        }
+    }
+
+    pub fn purge_expired(&self) {
+        self.previews.purge_expired();
+        self.playlists.purge_expired();
+    }
+}
+
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+#[instrument(skip(state, game), fields(lobby=%game.lobby_code))]
 pub async fn load_songs(
-    spotify_client: &ClientCredsSpotify,
+    state: &AppState,
     playlist_link: &str,
     game: Arc<GuessTheSongGame>,
 ) -> Result<(), String> {
-    // Extract playlist ID from link
-    let playlist_id = playlist_link.split("playlist/").nth(1);
-    let playlist_id = match playlist_id {
-        Some(id) => PlaylistId::from_id(id).unwrap(),
-        None => {
-            warn!("Failed to extract playlist ID from link");
-            return Err("Failed to extract playlistID from link".to_string());
+    let playlist_key = playlist_link.trim().to_string();
+    let mut tracks = match state.song_cache.playlists.get(&playlist_key) {
+        Some(tracks) => {
+            info!("Using cached playlist");
+            tracks
         }
-    };
-    let mut playlist_items = match spotify_client.playlist(playlist_id, None, None).await {
-        Ok(r) => r.tracks,
-        Err(e) => {
-            warn!("Failed to fetch playlist: {:?}", e);
-            return Err("Failed to fetch playlist".to_string());
+        None => {
+            let source = sources::from_link(playlist_link, &state.spotify, &state.http).await?;
+            let tracks = source.fetch_tracks().await?;
+            if source.cacheable() {
+                state
+                    .song_cache
+                    .playlists
+                    .insert(playlist_key, tracks.clone());
+            }
+            tracks
         }
     };
+    if tracks.is_empty() {
+        return Err("Playlist has no tracks".to_string());
+    }
 
     // Shuffle tracks
     let mut rng = {
         let mut rng = rand::rng();
         StdRng::from_rng(&mut rng)
     };
-    playlist_items.items.shuffle(&mut rng);
+    tracks.shuffle(&mut rng);
 
-    // First obtain isrc, then make another request to deezer endpoint to get preview URLs
-    for item in playlist_items
-        .items
-        .iter()
-        .take(game.get_num_songs() as usize)
+    // Distinct titles across the whole playlist, used as multiple choice distractors
+    let mut title_pool: Vec<String> = Vec::new();
+    for track in &tracks {
+        if !title_pool
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(&track.title))
+        {
+            title_pool.push(track.title.clone());
+        }
+    }
+    let settings = game.get_settings();
+    let mode = settings.mode;
+    if mode == GuessMode::MultipleChoice && title_pool.len() < MULTIPLE_CHOICE_OPTIONS {
+        return Err(format!(
+            "Multiple choice needs at least {} different songs in the playlist",
+            MULTIPLE_CHOICE_OPTIONS
+        ));
+    }
+
+    // Leave out songs voted broken in earlier games
+    let denied = denied(GameType::GuessTheSong).await;
+    tracks.retain(|track| !denied.contains(&song_key(&track.title, &track.artists)));
+
+    // Resolve previews concurrently, pulling further tracks to replace any misses
+    let num_songs = game.get_num_songs() as usize;
+    let _ = game
+        .broadcast
+        .send(GuessTheSongServerEvent::LoadingProgress {
+            resolved: 0,
+            requested: num_songs,
+        });
+    let mut pending = stream::iter(tracks.into_iter().take(num_songs * OVERSAMPLE_FACTOR))
+        .map(|track| resolve_song(state, track, mode))
+        .buffer_unordered(PREVIEW_CONCURRENCY);
+    let mut songs = Vec::new();
+    while songs.len() < num_songs
+        && let Some(result) = pending.next().await
     {
-        if let Some(rspotify::model::PlayableItem::Track(track)) = &item.track {
-            if let Some(isrc) = track.external_ids.get("isrc") {
-                let deezer_url = format!("https://api.deezer.com/track/isrc:{}", isrc);
-
-                // Fetch from Deezer
-                if let Ok(resp) = reqwest::get(&deezer_url).await {
-                    if let Ok(json) = resp.json::<serde_json::Value>().await {
-                        if let Some(preview_url) = json["preview"].as_str() {
-                            info!("Received URL for {}", track.name);
-
-                            game.state.lock().unwrap().add_song(SongState {
-                                title: (track.name.clone(), false),
-                                artists: track
-                                    .artists
-                                    .iter()
-                                    .map(|a| (a.name.clone(), false))
-                                    .collect(),
-                                url: preview_url.to_string(),
-                            });
-                        } else {
-                            // ISRC isn't found
-                            warn!("No preview found for ISRC: {}", isrc);
-                        }
-                    }
-                }
+        match result {
+            Ok(song) => {
+                songs.push(song);
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::LoadingProgress {
+                        resolved: songs.len(),
+                        requested: num_songs,
+                    });
+            }
+            Err((title, reason)) => {
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::TrackSkipped { title, reason });
             }
         }
     }
+
+    if songs.is_empty() {
+        return Err("No playable previews found in playlist".to_string());
+    }
+    if songs.len() < num_songs {
+        warn!("Only found {} of {} songs", songs.len(), num_songs);
+    }
+    let mut game_state = game.state.lock().unwrap();
+    for song in songs {
+        let choices = if mode == GuessMode::MultipleChoice {
+            pick_choices(&song.title, &title_pool, &mut rng)
+        } else {
+            Vec::new()
+        };
+        let clip_start = pick_clip_start(&settings, &mut rng);
+        game_state.add_song(SongState::new(song, mode, choices, clip_start));
+    }
     Ok(())
 }
+
+/// Uses the source's preview if it has one, otherwise looks the ISRC up on Deezer, which
+/// also fills in album and year where the source didn't have them.
+/// Skipped tracks come back as (title, reason).
+async fn resolve_song(
+    state: &AppState,
+    track: Track,
+    mode: GuessMode,
+) -> Result<Song, (String, String)> {
+    let needs_details = (mode == GuessMode::Year && track.year.is_none())
+        || (mode == GuessMode::Album && track.album.is_none());
+    let mut lookup_failed = false;
+    let details = match &track.isrc {
+        Some(isrc) if track.preview_url.is_none() || needs_details => {
+            match state.song_cache.previews.get(isrc) {
+                Some(cached) => cached,
+                None => match deezer_track(&state.http, isrc).await {
+                    Ok(details) => {
+                        state
+                            .song_cache
+                            .previews
+                            .insert(isrc.clone(), details.clone());
+                        details
+                    }
+                    // Left uncached so the next game tries again
+                    Err(e) => {
+                        warn!("Failed to look up ISRC {}: {}", isrc, e);
+                        lookup_failed = true;
+                        None
+                    }
+                },
+            }
+        }
+        _ => None,
+    };
+    let (details_preview, details_album, details_year) = match details {
+        Some(d) => (d.preview, d.album, d.year),
+        None => (None, None, None),
+    };
+
+    let Some(url) = track.preview_url.or(details_preview) else {
+        warn!("No preview found for {}", track.title);
+        let reason = if track.isrc.is_none() {
+            "No ISRC to look up a preview"
+        } else if lookup_failed {
+            "Preview lookup failed"
+        } else {
+            "No preview available"
+        };
+        return Err((track.title, reason.to_string()));
+    };
+    let song = Song {
+        url,
+        album: track.album.or(details_album),
+        year: track.year.or(details_year),
+        title: track.title,
+        artists: track.artists,
+    };
+    match mode {
+        GuessMode::Year if song.year.is_none() => {
+            Err((song.title, "No release year available".to_string()))
+        }
+        GuessMode::Album if song.album.is_none() => {
+            Err((song.title, "No album available".to_string()))
+        }
+        _ => {
+            info!("Received URL for {}", song.title);
+            Ok(song)
+        }
+    }
+}
+
+/// A random offset that keeps the whole clip inside the preview, or the start of it
+fn pick_clip_start(settings: &GuessTheSongGameSettings, rng: &mut StdRng) -> u8 {
+    if settings.random_clip_start {
+        rng.random_range(0..=PREVIEW_LENGTH_SECONDS - settings.clip_length())
+    } else {
+        0
+    }
+}
+
+/// The correct title plus distinct titles from elsewhere in the playlist, shuffled
+fn pick_choices(title: &str, pool: &[String], rng: &mut StdRng) -> Vec<String> {
+    let mut choices: Vec<String> = pool
+        .iter()
+        .filter(|t| !t.eq_ignore_ascii_case(title))
+        .cloned()
+        .collect::<Vec<_>>()
+        .choose_multiple(rng, MULTIPLE_CHOICE_OPTIONS - 1)
+        .cloned()
+        .collect();
+    choices.push(title.to_string());
+    choices.shuffle(rng);
+    choices
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DeezerTrack {
+    preview: Option<String>,
+    album: Option<String>,
+    year: Option<i32>,
+}
+
+// Deezer's error code for an ISRC it has no track for
+const DEEZER_NO_DATA: i64 = 800;
+
+/// None if Deezer has no track for the ISRC, or an error if the lookup itself failed
+async fn deezer_track(http: &reqwest::Client, isrc: &str) -> Result<Option<DeezerTrack>, String> {
+    let deezer_url = format!("https://api.deezer.com/track/isrc:{}", isrc);
+    let json = http
+        .get(&deezer_url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| e.to_string())?;
+    if json["error"].is_object() {
+        if json["error"]["code"].as_i64() == Some(DEEZER_NO_DATA) {
+            return Ok(None);
+        }
+        return Err(format!("Deezer: {}", json["error"]["message"]));
+    }
+    Ok(Some(DeezerTrack {
+        preview: json["preview"]
+            .as_str()
+            .filter(|p| !p.is_empty())
+            .map(str::to_string),
+        album: json["album"]["title"].as_str().map(str::to_string),
+        year: json["release_date"].as_str().and_then(parse_year),
+    }))
+}
@@ -4,8 +4,16 @@ use std::{
 };
 
 use crate::{
-    AppState, generate_lobby_code,
-    state::{GuessTheSongGame, GuessTheSongServerEvent, GuessTheSongUserEvent, LobbyStatus},
+    AppState,
+    accounts::{identify, player_name},
+    connections::forward_events,
+    generate_lobby_code,
+    history::record_game,
+    state::{
+        ControlCommand, GuessOutcome, GuessTheSongGame, GuessTheSongServerEvent,
+        GuessTheSongUserEvent, LobbyStatus, RematchOptions, SERIES_BREAK_SECONDS, Wait,
+        validation::validate_guess,
+    },
 };
 use axum::{
     Json,
@@ -17,12 +25,14 @@ use axum::{
 };
 use futures_util::{SinkExt, stream::StreamExt};
 use tokio::{
-    sync::{broadcast, mpsc},
-    time::{Duration, Instant, sleep},
+    sync::broadcast,
+    task::JoinHandle,
+    time::{Duration, Instant},
 };
 use tracing::{Instrument, info, instrument, warn};
 use uuid::Uuid;
 pub mod api;
+pub mod sources;
 
 #[derive(serde::Serialize)]
 struct CreateLobbyResponse {
@@ -31,27 +41,42 @@ struct CreateLobbyResponse {
 struct GuessTheSongConnectionGuard {
     game: Arc<GuessTheSongGame>,
     player_id: Uuid,
+    connection_id: Uuid,
     broadcast: broadcast::Sender<GuessTheSongServerEvent>,
-    cleanup_tx: mpsc::UnboundedSender<String>,
+    state: AppState,
 }
 
 impl Drop for GuessTheSongConnectionGuard {
     fn drop(&mut self) {
-        let mut lobby_state = self.game.lobby_state.lock().unwrap();
-        lobby_state.player_leave(&self.player_id);
+        let (empty, rematch) = {
+            let mut lobby_state = self.game.lobby_state.lock().unwrap();
+            lobby_state.player_leave(&self.player_id);
+            (
+                lobby_state.players.is_empty(),
+                lobby_state.try_start_rematch(),
+            )
+        };
+        self.game.direct.remove(&self.player_id, self.connection_id);
         info!(
             "Player {} disconnected from lobby: {}",
             self.player_id, self.game.lobby_code
         );
         let _ = self.broadcast.send(GuessTheSongServerEvent::PlayerLeave {
-            player_id: self.player_id.clone(),
+            player_id: self.player_id,
         });
-        if lobby_state.players.is_empty() {
+        self.game
+            .cancel_loading("A player left while loading".to_string());
+        if empty {
             info!(
                 "Lobby {} is now empty, scheduling for cleanup",
                 self.game.lobby_code
             );
-            let _ = self.cleanup_tx.send(self.game.lobby_code.clone());
+            let _ = self.state.cleanup.send(self.game.lobby_code.clone());
+        } else {
+            self.game.recount_broken_reports();
+            if let Some((starter, options)) = rematch {
+                start_rematch(&self.game, &self.state, starter, options);
+            }
         }
     }
 }
@@ -68,9 +93,7 @@ pub async fn guess_the_song_create_lobby(State(state): State<AppState>) -> impl
     state.games.add_guess_the_song_lobby(&lobby_code);
     info!("Added lobby {lobby_code}");
 
-    Json(CreateLobbyResponse {
-        lobby_code: lobby_code,
-    })
+    Json(CreateLobbyResponse { lobby_code })
 }
 
 pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
@@ -100,11 +123,12 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
     };
 
     // 2. Use Pattern Matching to extract the fields from the Join variant
-    let (lobby_code, player_username) = match event {
+    let (lobby_code, player_username, token) = match event {
         GuessTheSongUserEvent::Join {
             lobby_code,
             username,
-        } => (lobby_code, username),
+            token,
+        } => (lobby_code, username, token),
         _ => {
             info!("JOIN ERROR");
             let _ = sender
@@ -120,6 +144,27 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
         }
     };
 
+    let identity = match identify(token).await {
+        Ok(account) => player_name(account.as_ref(), &player_username)
+            .await
+            .map(|username| (account.map(|a| a.player_id), username)),
+        Err(message) => Err(message),
+    };
+    let (account_id, player_username) = match identity {
+        Ok(identity) => identity,
+        Err(message) => {
+            info!("JOIN ERROR");
+            let _ = sender
+                .send(Message::Text(
+                    serde_json::to_string(&GuessTheSongServerEvent::JoinError { message })
+                        .unwrap()
+                        .into(),
+                ))
+                .await;
+            return;
+        }
+    };
+
     let game_obj = match state.games.guess_the_song.get(&lobby_code) {
         Some(g) => g.clone(),
         None => {
@@ -137,7 +182,7 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
         }
     };
 
-    let player_id = game_obj.get_new_player_id();
+    let player_id = account_id.unwrap_or_else(|| game_obj.get_new_player_id());
 
     let connection_span = tracing::info_span!(
         "connection",
@@ -145,7 +190,7 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
         player=%player_id,
     );
 
-    match game_obj.player_join(player_id.clone(), player_username.clone()) {
+    match game_obj.player_join(player_id, player_username.clone()) {
         Ok(_) => {
             info!("Player: {}, joined lobby: {}", player_id, lobby_code);
         }
@@ -162,16 +207,20 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
             return;
         }
     }
+    let connection_id = Uuid::new_v4();
     let _guard = GuessTheSongConnectionGuard {
         game: game_obj.clone(),
-        player_id: player_id.clone(),
+        player_id,
+        connection_id,
         broadcast: game_obj.broadcast.clone(),
-        cleanup_tx: state.cleanup.clone(),
+        state: state.clone(),
     };
     // Clone the broadcast channel into tx (Sender)
     let tx = game_obj.broadcast.clone();
     // Subscribe to the broadcast channel to aquire a (Receiver)
-    let mut rx = tx.subscribe();
+    let rx = tx.subscribe();
+    // Register a private channel for events targeted at this player only
+    let direct_rx = game_obj.direct.register(player_id, connection_id);
 
     // Extract the gamestate
     let _ = sender
@@ -183,36 +232,24 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
                 preview_url: game_obj.get_current_song().map(|s| s.url),
                 status: game_obj.get_lobby_status(),
                 round_start_time: game_obj.get_round_start_time(),
+                choices: game_obj.get_current_choices(),
+                clip: game_obj.get_current_clip(),
+                teams: game_obj.get_teams(),
+                team_leaderboard: game_obj.get_team_leaderboard(),
             })
             .expect("Failed to parse SyncState event")
             .into(),
         ))
         .await;
 
-    // Create the send_task
-    let mut send_task: tokio::task::JoinHandle<()> = tokio::spawn(
-        async move {
-            while let Ok(msg) = rx.recv().await {
-                match serde_json::to_string(&msg) {
-                    Ok(json) => {
-                        if sender.send(Message::Text(json.into())).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Serialization error: {:?}", e);
-                        continue;
-                    }
-                }
-            }
-        }
-        .instrument(connection_span.clone()),
-    );
+    // Create the send_task, merging the lobby broadcast with this player's private channel
+    let mut send_task: tokio::task::JoinHandle<()> =
+        tokio::spawn(forward_events(sender, rx, direct_rx).instrument(connection_span.clone()));
 
     let _ = game_obj
         .broadcast
         .send(GuessTheSongServerEvent::PlayerJoin {
-            player_id: player_id.clone(),
+            player_id,
             player_username: player_username.clone(),
         });
 
@@ -233,75 +270,67 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
                         };
                         match req {
                             GuessTheSongUserEvent::Ready => {
+                                // Readying up on the results screen votes for a rematch
+                                if game_obj.get_lobby_status() == LobbyStatus::Finished {
+                                    vote_rematch(&game_obj, &state, player_id);
+                                    continue;
+                                }
                                 game_obj.player_ready(&player_id);
                                 info!("READY");
-                                let _ =
-                                    game_obj
-                                        .broadcast
-                                        .send(GuessTheSongServerEvent::PlayerReady {
-                                            player_id: player_id.clone(),
-                                        });
-                                if game_obj.all_ready()
-                                    && game_obj.lobby_state.lock().unwrap().status
-                                        == LobbyStatus::Waiting
-                                {
-                                    let _ =
-                                        game_obj.broadcast.send(GuessTheSongServerEvent::AllReady);
-                                    let playlist_link = game_obj.get_playlist_link();
-                                    let l = game_obj.clone();
-                                    let spotify_client = state.spotify_client.clone();
-                                    let player_id_c = player_id.clone();
-                                    tokio::spawn(async move {
-                                        let res = api::load_songs(
-                                            &spotify_client,
-                                            &playlist_link,
-                                            l.clone(),
-                                        )
-                                        .await;
-                                        match res {
-                                            Ok(_) => {
-                                                l.update_lobby_status(LobbyStatus::Playing);
-                                            }
-                                            Err(msg) => {
-                                                l.update_lobby_status(LobbyStatus::Waiting);
-                                                l.player_unready(&player_id_c);
-                                                let _ = l.broadcast.send(
-                                                    GuessTheSongServerEvent::PlayerUnready {
-                                                        player_id: player_id_c,
-                                                    },
-                                                );
-                                                let _ = l.broadcast.send(
-                                                    GuessTheSongServerEvent::PlaylistError {
-                                                        message: msg,
-                                                    },
-                                                );
-                                                return;
-                                            }
-                                        }
-                                        run_guess_the_song_game(l).await;
-                                    });
+                                let _ = game_obj
+                                    .broadcast
+                                    .send(GuessTheSongServerEvent::PlayerReady { player_id });
+                                if game_obj.try_begin_loading() {
+                                    start_loading(game_obj.clone(), state.clone(), player_id);
                                 }
                             }
                             GuessTheSongUserEvent::Unready => {
                                 info!("UNREADY");
                                 game_obj.player_unready(&player_id);
-                                let _ = game_obj.broadcast.send(
-                                    GuessTheSongServerEvent::PlayerUnready {
-                                        player_id: player_id.clone(),
-                                    },
-                                );
+                                let _ = game_obj
+                                    .broadcast
+                                    .send(GuessTheSongServerEvent::PlayerUnready { player_id });
+                                game_obj.cancel_loading(format!(
+                                    "{} is no longer ready",
+                                    player_username
+                                ));
                             }
                             GuessTheSongUserEvent::UpdateGameSettings { settings } => {
                                 info!("UPDATE SETTINGS: {:?}", settings);
-                                game_obj.update_game_settings(settings.clone());
+                                // Songs are prepared for the mode at load time
+                                if game_obj.get_lobby_status() != LobbyStatus::Waiting {
+                                    game_obj.send_to(
+                                        &player_id,
+                                        GuessTheSongServerEvent::Error {
+                                            message:
+                                                "Settings can only be changed in the waiting room"
+                                                    .to_string(),
+                                        },
+                                    );
+                                    continue;
+                                }
+                                if let Err(message) = game_obj.update_game_settings(settings) {
+                                    game_obj.send_to(
+                                        &player_id,
+                                        GuessTheSongServerEvent::Error { message },
+                                    );
+                                    continue;
+                                }
                                 let _ = game_obj.broadcast.send(
                                     GuessTheSongServerEvent::GameSettingsUpdated {
-                                        settings: settings,
+                                        settings: game_obj.get_settings(),
                                     },
                                 );
                             }
                             GuessTheSongUserEvent::Guess { content } => {
                                 info!(guess=%content, "GUESS:");
+                                if let Err(message) = validate_guess(&content) {
+                                    game_obj.send_to(
+                                        &player_id,
+                                        GuessTheSongServerEvent::Error { message },
+                                    );
+                                    continue;
+                                }
                                 let cur_guess_time_stamp = Instant::now();
                                 let duration_since_last_guess = cur_guess_time_stamp
                                     .duration_since(prev_guess_time_stamp)
@@ -309,62 +338,67 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
                                 if duration_since_last_guess
                                     < game_obj.get_settings().answer_delay_seconds as f64
                                 {
-                                    let _ = game_obj.broadcast.send(
-                                        GuessTheSongServerEvent::PlayerGuess {
-                                            username: "ERROR".to_string(),
-                                            content: format!(
-                                                "{} - {:.2}s: {}",
-                                                player_username.clone(),
+                                    game_obj.send_to(
+                                        &player_id,
+                                        GuessTheSongServerEvent::Error {
+                                            message: format!(
+                                                "Wait {:.2}s before guessing again",
                                                 game_obj.get_settings().answer_delay_seconds as f64
                                                     - duration_since_last_guess,
-                                                content.clone()
                                             ),
                                         },
                                     );
                                     continue;
-                                } else {
-                                    let _ = game_obj.broadcast.send(
-                                        GuessTheSongServerEvent::PlayerGuess {
-                                            username: player_username.clone(),
-                                            content: content.clone(),
-                                        },
-                                    );
                                 }
-                                let mut correct = false;
-                                if let Some(s) = game_obj.is_correct_song(&content) {
-                                    game_obj.increment_player_score(&player_id, 2);
-                                    info!(guess=%content, "CORRECT SONG:");
-                                    let _ = game_obj.broadcast.send(
-                                        GuessTheSongServerEvent::CorrectGuess {
-                                            player_id: player_id.clone(),
-                                            msg: format!(
-                                                "{} guessed the song correctly! The song was '{}'.",
-                                                player_username, s
-                                            ),
-                                        },
-                                    );
-                                    correct = true;
+                                let correct =
+                                    handle_guess(&game_obj, player_id, &player_username, &content);
+                                if !correct {
+                                    prev_guess_time_stamp = cur_guess_time_stamp;
                                 }
-                                if let Some(a) = game_obj.is_correct_artist(&content) {
-                                    game_obj.increment_player_score(&player_id, 2);
-                                    info!(guess=%content, "CORRECT ARTIST:");
-                                    let _ = game_obj.broadcast.send(
-                                    GuessTheSongServerEvent::CorrectGuess {
-                                        player_id: player_id.clone(),
-                                        msg: format!(
-                                            "{} guessed the artist correctly! The artist was '{}'.",
-                                            player_username, a
-                                            ),
-                                        },
+                            }
+                            GuessTheSongUserEvent::Choose { index } => {
+                                info!(index, "CHOOSE:");
+                                let outcome = game_obj.choose(player_id, index);
+                                report_outcome(&game_obj, player_id, &player_username, outcome);
+                            }
+                            GuessTheSongUserEvent::JoinTeam { team } => {
+                                info!(team, "JOIN TEAM:");
+                                update_teams(
+                                    &game_obj,
+                                    player_id,
+                                    game_obj.join_team(player_id, team),
+                                );
+                            }
+                            GuessTheSongUserEvent::AutoBalanceTeams => {
+                                info!("AUTO BALANCE TEAMS");
+                                update_teams(&game_obj, player_id, game_obj.auto_balance_teams());
+                            }
+                            GuessTheSongUserEvent::Rematch => {
+                                info!("REMATCH");
+                                vote_rematch(&game_obj, &state, player_id);
+                            }
+                            GuessTheSongUserEvent::SetRematchOptions { options } => {
+                                info!("REMATCH OPTIONS: {:?}", options);
+                                if let Err(message) = game_obj.set_rematch_options(options) {
+                                    game_obj.send_to(
+                                        &player_id,
+                                        GuessTheSongServerEvent::Error { message },
                                     );
-                                    correct = true;
                                 }
-                                if !correct {
-                                    prev_guess_time_stamp = cur_guess_time_stamp;
+                            }
+                            GuessTheSongUserEvent::ReportBroken => {
+                                info!("REPORT BROKEN");
+                                if let Err(message) = game_obj.report_broken(player_id) {
+                                    game_obj.send_to(
+                                        &player_id,
+                                        GuessTheSongServerEvent::Error { message },
+                                    );
                                 }
                             }
-                            _ => {
-                                continue;
+                            req => {
+                                if let Some(command) = req.control_command() {
+                                    control_game(&game_obj, player_id, command);
+                                }
                             }
                         }
                     }
@@ -385,15 +419,252 @@ pub async fn handle_guess_the_song(socket: WebSocket, state: AppState) {
     info!("Websocket disconnected");
 }
 
+/// Starts the rematch once everyone has voted for it, straight away if it keeps the settings
+fn vote_rematch(game: &Arc<GuessTheSongGame>, state: &AppState, player_id: Uuid) {
+    match game.vote_rematch(player_id) {
+        Ok(Some(options)) => start_rematch(game, state, player_id, options),
+        Ok(None) => {}
+        Err(message) => game.send_to(&player_id, GuessTheSongServerEvent::Error { message }),
+    }
+}
+
+fn start_rematch(
+    game: &Arc<GuessTheSongGame>,
+    state: &AppState,
+    player_id: Uuid,
+    options: RematchOptions,
+) {
+    game.start_rematch(&options);
+    info!(?options, "Starting rematch");
+    if options.same_settings && game.try_begin_loading() {
+        start_loading(game.clone(), state.clone(), player_id);
+    }
+}
+
+fn control_game(game: &GuessTheSongGame, player_id: Uuid, command: ControlCommand) {
+    match game.apply_control(command) {
+        Ok(outcome) => {
+            info!(?command, "GAME CONTROL");
+            let _ = game
+                .broadcast
+                .send(GuessTheSongServerEvent::from_control(player_id, outcome));
+        }
+        Err(message) => game.send_to(&player_id, GuessTheSongServerEvent::Error { message }),
+    }
+}
+
+/// Broadcasts the new teams, or tells the player why their change was rejected
+fn update_teams(game: &GuessTheSongGame, player_id: Uuid, result: Result<(), String>) {
+    match result {
+        Ok(_) => {
+            let _ = game.broadcast.send(GuessTheSongServerEvent::TeamsUpdated {
+                teams: game.get_teams(),
+            });
+        }
+        Err(message) => game.send_to(&player_id, GuessTheSongServerEvent::Error { message }),
+    }
+}
+
+/// Scores a guess against the current round and tells the lobby (or just the guesser,
+/// for anything that would give the answer away). Returns whether it earned points.
+pub(crate) fn handle_guess(
+    game: &GuessTheSongGame,
+    player_id: Uuid,
+    player_username: &str,
+    content: &str,
+) -> bool {
+    let one_shot = game.get_settings().mode.is_one_shot();
+    let outcomes = game.evaluate_guess(player_id, content);
+    if !one_shot {
+        let _ = game.broadcast.send(GuessTheSongServerEvent::PlayerGuess {
+            username: player_username.to_string(),
+            content: content.to_string(),
+        });
+    }
+
+    let mut correct = false;
+    for outcome in outcomes {
+        correct |= report_outcome(game, player_id, player_username, outcome);
+    }
+    correct
+}
+
+/// Sends the messages for an already scored outcome, returning whether it earned points
+pub(crate) fn report_outcome(
+    game: &GuessTheSongGame,
+    player_id: Uuid,
+    player_username: &str,
+    outcome: GuessOutcome,
+) -> bool {
+    match outcome {
+        GuessOutcome::Correct { kind, answer, .. } => {
+            info!(answer=%answer, "CORRECT {}:", kind.label().to_uppercase());
+            let _ = game.broadcast.send(GuessTheSongServerEvent::CorrectGuess {
+                player_id,
+                msg: format!(
+                    "{} guessed the {} correctly! The {} was '{}'.",
+                    player_username,
+                    kind.label(),
+                    kind.label(),
+                    answer
+                ),
+            });
+            true
+        }
+        GuessOutcome::Year {
+            guessed,
+            actual,
+            points,
+        } => {
+            let _ = game.broadcast.send(GuessTheSongServerEvent::PlayerGuess {
+                username: player_username.to_string(),
+                content: "locked in a year".to_string(),
+            });
+            game.send_to(
+                &player_id,
+                GuessTheSongServerEvent::GuessFeedback {
+                    msg: format!(
+                        "You guessed {}, {} years off: +{} points",
+                        guessed,
+                        guessed.abs_diff(actual),
+                        points
+                    ),
+                },
+            );
+            points > 0
+        }
+        GuessOutcome::Choice { correct, points } => {
+            let _ = game.broadcast.send(GuessTheSongServerEvent::PlayerGuess {
+                username: player_username.to_string(),
+                content: "locked in an answer".to_string(),
+            });
+            game.send_to(
+                &player_id,
+                GuessTheSongServerEvent::GuessFeedback {
+                    msg: if correct {
+                        format!("Correct! +{} points", points)
+                    } else {
+                        "Wrong answer".to_string()
+                    },
+                },
+            );
+            correct
+        }
+        GuessOutcome::AlreadyAnswered => {
+            game.send_to(
+                &player_id,
+                GuessTheSongServerEvent::Error {
+                    message: "You've already answered this round".to_string(),
+                },
+            );
+            false
+        }
+        GuessOutcome::Invalid(message) => {
+            game.send_to(&player_id, GuessTheSongServerEvent::Error { message });
+            false
+        }
+    }
+}
+
+/// Loads the playlist in a task that can be cancelled while the lobby is `Loading`,
+/// then runs the game once every song has been resolved.
+fn start_loading(game: Arc<GuessTheSongGame>, state: AppState, player_id: Uuid) {
+    let _ = game.broadcast.send(GuessTheSongServerEvent::AllReady);
+    if let Some(teams) = game.prepare_teams() {
+        let _ = game
+            .broadcast
+            .send(GuessTheSongServerEvent::TeamsUpdated { teams });
+    }
+    let _ = game
+        .broadcast
+        .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+            new_status: LobbyStatus::Loading,
+        });
+    // A rematch replaying the last game already has its songs
+    let loader = (!game.has_songs()).then(|| spawn_loader(&game, &state));
+    tokio::spawn(async move {
+        let Some(loader) = loader else {
+            game.update_lobby_status(LobbyStatus::Playing);
+            let _ = game
+                .broadcast
+                .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                    new_status: LobbyStatus::Playing,
+                });
+            run_series(game, state).await;
+            return;
+        };
+        let res = loader.await;
+        game.loading.lock().unwrap().take();
+
+        match res {
+            Ok(Ok(_)) => {
+                game.update_lobby_status(LobbyStatus::Playing);
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                        new_status: LobbyStatus::Playing,
+                    });
+            }
+            Ok(Err(msg)) => {
+                game.clear_songs();
+                game.update_lobby_status(LobbyStatus::Waiting);
+                game.player_unready(&player_id);
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::PlayerUnready { player_id });
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                        new_status: LobbyStatus::Waiting,
+                    });
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::PlaylistError { message: msg });
+                return;
+            }
+            Err(_) => {
+                info!("Loading cancelled");
+                game.clear_songs();
+                game.update_lobby_status(LobbyStatus::Waiting);
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                        new_status: LobbyStatus::Waiting,
+                    });
+                return;
+            }
+        }
+        run_series(game, state).await;
+    });
+}
+
+/// Loads the playlist in its own task. The loader's handle is stored before returning, so
+/// an Unready straight after the last Ready, a player leaving or an abort can cancel it.
+fn spawn_loader(game: &Arc<GuessTheSongGame>, state: &AppState) -> JoinHandle<Result<(), String>> {
+    let playlist_link = game.get_playlist_link();
+    let loader = tokio::spawn({
+        let game = game.clone();
+        let state = state.clone();
+        async move { api::load_songs(&state, &playlist_link, game).await }
+    });
+    *game.loading.lock().unwrap() = Some(loader.abort_handle());
+    loader
+}
+
 #[instrument(name="GAME LOOP", skip(game), fields(lobby=%game.lobby_code))]
-async fn run_guess_the_song_game(game: Arc<GuessTheSongGame>) {
+pub(crate) async fn run_guess_the_song_game(game: Arc<GuessTheSongGame>) {
     info!("Starting Guess The Song game");
     let _ = game.broadcast.send(GuessTheSongServerEvent::GameStart);
-    sleep(Duration::from_secs(3)).await;
+    if game.control.wait(Duration::from_secs(3)).await == Wait::Aborted {
+        return;
+    }
     let settings = {
         let s = game.settings.lock().unwrap();
         s.clone()
     };
+    let reveal_steps = settings.reveal_steps();
+    let round_length = Duration::from_secs(settings.round_length_seconds as u64);
+    let round_delay = Duration::from_secs(settings.round_delay_seconds as u64);
 
     for _ in 0..settings.num_songs {
         if game.lobby_state.lock().unwrap().empty() {
@@ -413,7 +684,6 @@ async fn run_guess_the_song_game(game: Arc<GuessTheSongGame>) {
                 }
                 None => {
                     info!("No songs left, ending game");
-                    let _ = game.broadcast.send(GuessTheSongServerEvent::GameEnd);
                     break;
                 }
             }
@@ -423,20 +693,157 @@ async fn run_guess_the_song_game(game: Arc<GuessTheSongGame>) {
         let _ = game.broadcast.send(GuessTheSongServerEvent::RoundStart {
             preview_url: song.url.clone(),
             round_start_time,
+            mode: settings.mode,
+            choices: game.get_current_choices(),
+            clip: game.get_current_clip().expect("Round has a current song"),
+            reveal_steps: reveal_steps.len(),
         });
+        game.lobby_state.lock().unwrap().open_broken_reports();
 
-        sleep(Duration::from_secs(settings.round_length_seconds as u64)).await;
+        // Unlock each progressive step on its timer, stopping at the end of the round
+        let mut played = Duration::ZERO;
+        let mut ended = Wait::Elapsed;
+        for step in 1..reveal_steps.len() {
+            let unlock_at =
+                Duration::from_secs(settings.reveal_interval_seconds as u64 * step as u64);
+            if unlock_at >= round_length {
+                break;
+            }
+            ended = game.control.wait(unlock_at - played).await;
+            if ended != Wait::Elapsed {
+                break;
+            }
+            played = unlock_at;
+            if let Some(clip) = game.unlock_reveal_step(step) {
+                info!(step, length = clip.length_seconds, "CLIP UNLOCKED:");
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::ClipUnlocked { step, clip });
+            }
+        }
+        if ended == Wait::Elapsed {
+            ended = game.control.wait(round_length - played).await;
+        }
+        game.lobby_state.lock().unwrap().close_broken_reports();
+        // Nothing scores once the answer is about to be revealed
+        game.close_round();
 
-        info!("ROUNDEND");
+        info!("ROUNDEND ({:?})", ended);
+        match ended {
+            Wait::Aborted => return,
+            // The round ends without anyone scoring
+            Wait::Skipped => game.discard_round(),
+            Wait::Elapsed => game.end_round(),
+        }
+        let (answers, correct_choice) = {
+            let state = game.state.lock().unwrap();
+            (state.get_locked_answers(), state.get_correct_choice())
+        };
         let _ = game.broadcast.send(GuessTheSongServerEvent::RoundEnd {
             correct_title: song.title.clone(),
             correct_artists: song.artists.clone(),
+            album: song.album.clone(),
+            year: song.year,
+            mode: settings.mode,
+            answers,
+            correct_choice,
             leaderboard: game.get_leaderboard(),
+            team_leaderboard: game.get_team_leaderboard(),
         });
-        sleep(Duration::from_secs(settings.round_delay_seconds as u64)).await;
+        if game.control.wait(round_delay).await == Wait::Aborted {
+            return;
+        }
     }
     info!("GAME END");
-    sleep(Duration::from_secs(3 - settings.round_delay_seconds as u64)).await;
-    let _ = game.broadcast.send(GuessTheSongServerEvent::GameEnd);
-    game.reset();
+    if (settings.round_delay_seconds as u64) < 3
+        && game
+            .control
+            .wait(Duration::from_secs(3 - settings.round_delay_seconds as u64))
+            .await
+            == Wait::Aborted
+    {
+        return;
+    }
+    let _ = game.broadcast.send(GuessTheSongServerEvent::GameEnd {
+        leaderboard: game.get_leaderboard(),
+        team_leaderboard: game.get_team_leaderboard(),
+    });
+    if !game.stage {
+        record_game(game.to_record());
+    }
+}
+
+/// Runs games back to back until the lobby's series is over, or just the one game
+/// without a series. Aborting any of them goes back to the waiting room.
+async fn run_series(game: Arc<GuessTheSongGame>, state: AppState) {
+    game.control.start();
+    play_series(&game, &state).await;
+    if game.control.is_aborted() {
+        info!("Game aborted");
+        game.abort();
+    }
+    game.control.stop();
+}
+
+async fn play_series(game: &Arc<GuessTheSongGame>, state: &AppState) {
+    loop {
+        run_guess_the_song_game(game.clone()).await;
+        if game.control.is_aborted() || game.lobby_state.lock().unwrap().empty() || !game.finish() {
+            return;
+        }
+        info!("Next game of the series in {}s", SERIES_BREAK_SECONDS);
+        let wait = game
+            .control
+            .wait(Duration::from_secs(SERIES_BREAK_SECONDS))
+            .await;
+        if wait == Wait::Aborted || game.lobby_state.lock().unwrap().empty() {
+            return;
+        }
+        game.state.lock().unwrap().reset();
+        // Each game announces its teams, like the first one does in `start_loading`
+        if let Some(teams) = game.prepare_teams() {
+            let _ = game
+                .broadcast
+                .send(GuessTheSongServerEvent::TeamsUpdated { teams });
+        }
+        // Loads like the first game, so it can be cancelled the same ways
+        game.update_lobby_status(LobbyStatus::Loading);
+        let _ = game
+            .broadcast
+            .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                new_status: LobbyStatus::Loading,
+            });
+        let res = spawn_loader(game, state).await;
+        game.loading.lock().unwrap().take();
+        match res {
+            Ok(Ok(_)) => {
+                game.update_lobby_status(LobbyStatus::Playing);
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                        new_status: LobbyStatus::Playing,
+                    });
+            }
+            Ok(Err(message)) => {
+                game.lobby_state.lock().unwrap().show_results();
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::PlaylistError { message });
+                let _ = game
+                    .broadcast
+                    .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                        new_status: LobbyStatus::Finished,
+                    });
+                return;
+            }
+            Err(_) => {
+                info!("Loading cancelled");
+                // An abort is cleaned up by `run_series`, otherwise drop the rest of the series
+                if !game.control.is_aborted() {
+                    game.abort();
+                }
+                return;
+            }
+        }
+    }
 }
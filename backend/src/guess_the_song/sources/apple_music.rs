@@ -0,0 +1,95 @@
+use futures_util::future::BoxFuture;
+use tracing::warn;
+
+use super::{SongSource, Track, extract_id, parse_year};
+
+/// Apple Music albums, resolved through the public iTunes lookup API which
+/// includes preview URLs. Playlists need a developer token and aren't supported.
+pub(crate) struct AppleMusicAlbum {
+    http: reqwest::Client,
+    album_id: String,
+}
+
+impl AppleMusicAlbum {
+    pub fn new(http: reqwest::Client, id: &str) -> Result<Self, String> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+            return Err("Invalid Apple Music album ID".to_string());
+        }
+        Ok(AppleMusicAlbum {
+            http,
+            album_id: id.to_string(),
+        })
+    }
+}
+
+/// The album ID, the last path segment of `https://music.apple.com/<country>/album/<name>/<id>`
+pub(crate) fn album_id(link: &str) -> Result<String, String> {
+    if link.contains("/playlist/") {
+        return Err("Apple Music playlists aren't supported, use an album link".to_string());
+    }
+    extract_id(link, &["album/"])
+        .and_then(|rest| rest.rsplit('/').next().map(str::to_string))
+        .ok_or("Failed to extract album ID from link".to_string())
+}
+
+impl SongSource for AppleMusicAlbum {
+    fn fetch_tracks(&self) -> BoxFuture<'_, Result<Vec<Track>, String>> {
+        Box::pin(async move {
+            let url = format!(
+                "https://itunes.apple.com/lookup?id={}&entity=song&limit=200",
+                self.album_id
+            );
+            let json = match self.http.get(&url).send().await {
+                Ok(resp) => resp.json::<serde_json::Value>().await.map_err(|e| {
+                    warn!("Failed to parse iTunes response: {:?}", e);
+                    "Failed to fetch album".to_string()
+                })?,
+                Err(e) => {
+                    warn!("Failed to fetch iTunes album: {:?}", e);
+                    return Err("Failed to fetch album".to_string());
+                }
+            };
+
+            Ok(json["results"]
+                .as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter(|item| item["wrapperType"] == "track")
+                        .filter_map(|item| {
+                            Some(Track {
+                                title: item["trackName"].as_str()?.to_string(),
+                                artists: vec![item["artistName"].as_str()?.to_string()],
+                                isrc: None,
+                                preview_url: item["previewUrl"].as_str().map(str::to_string),
+                                album: item["collectionName"].as_str().map(str::to_string),
+                                year: item["releaseDate"].as_str().and_then(parse_year),
+                            })
+                        })
+                        .collect()
+                })
+                .unwrap_or_default())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_album_ids_from_links() {
+        assert_eq!(
+            album_id("https://music.apple.com/us/album/abbey-road-remastered/1441164426?i=1")
+                .unwrap(),
+            "1441164426"
+        );
+    }
+
+    #[test]
+    fn rejects_playlists_and_bad_ids() {
+        assert!(album_id("https://music.apple.com/us/playlist/todays-hits/pl.f4d1").is_err());
+        assert!(album_id("https://music.apple.com/us/browse").is_err());
+        assert!(AppleMusicAlbum::new(reqwest::Client::new(), "abbey-road").is_err());
+    }
+}
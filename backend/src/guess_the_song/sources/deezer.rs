@@ -0,0 +1,145 @@
+use futures_util::future::BoxFuture;
+use tracing::warn;
+
+use super::{MAX_PLAYLIST_TRACKS, SongSource, Track, extract_id, parse_year};
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum DeezerList {
+    Playlist(String),
+    Album(String),
+    Chart,
+}
+
+impl DeezerList {
+    /// Reads `https://www.deezer.com/playlist/<id>`, `https://www.deezer.com/album/<id>`
+    /// or any link mentioning the chart, e.g. `deezer:chart`
+    pub fn from_link(link: &str) -> Result<Self, String> {
+        if link.contains("chart") {
+            return Ok(DeezerList::Chart);
+        }
+        if let Some(id) = extract_id(link, &["playlist/"]) {
+            return Ok(DeezerList::Playlist(id));
+        }
+        if let Some(id) = extract_id(link, &["album/"]) {
+            return Ok(DeezerList::Album(id));
+        }
+        Err("Unsupported Deezer link, expected a playlist, album or chart".to_string())
+    }
+}
+
+/// Deezer serves previews directly, so no ISRC lookup is needed afterwards
+pub(crate) struct DeezerSource {
+    http: reqwest::Client,
+    list: DeezerList,
+}
+
+impl DeezerSource {
+    pub fn new(http: reqwest::Client, list: DeezerList) -> Self {
+        DeezerSource { http, list }
+    }
+
+    fn url(&self) -> String {
+        match &self.list {
+            DeezerList::Playlist(id) => format!("https://api.deezer.com/playlist/{}/tracks", id),
+            DeezerList::Album(id) => format!("https://api.deezer.com/album/{}/tracks", id),
+            DeezerList::Chart => "https://api.deezer.com/chart/0/tracks?limit=100".to_string(),
+        }
+    }
+}
+
+impl SongSource for DeezerSource {
+    fn fetch_tracks(&self) -> BoxFuture<'_, Result<Vec<Track>, String>> {
+        Box::pin(async move {
+            // Deezer pages its lists and links to the next page until the end
+            let mut tracks = Vec::new();
+            let mut next = Some(self.url());
+            while let Some(url) = next.take() {
+                let json = match self.http.get(&url).send().await {
+                    Ok(resp) => resp.json::<serde_json::Value>().await.map_err(|e| {
+                        warn!("Failed to parse Deezer response: {:?}", e);
+                        "Failed to fetch playlist".to_string()
+                    })?,
+                    Err(e) => {
+                        warn!("Failed to fetch Deezer playlist: {:?}", e);
+                        return Err("Failed to fetch playlist".to_string());
+                    }
+                };
+                if let Some(message) = json["error"]["message"].as_str() {
+                    warn!("Deezer error: {}", message);
+                    return Err(format!("Deezer: {}", message));
+                }
+                if let Some(items) = json["data"].as_array() {
+                    tracks.extend(items.iter().filter_map(parse_track));
+                }
+                if tracks.len() < MAX_PLAYLIST_TRACKS {
+                    next = json["next"].as_str().map(str::to_string);
+                }
+            }
+            tracks.truncate(MAX_PLAYLIST_TRACKS);
+            Ok(tracks)
+        })
+    }
+}
+
+fn parse_track(item: &serde_json::Value) -> Option<Track> {
+    let title = item["title_short"]
+        .as_str()
+        .or_else(|| item["title"].as_str())?;
+    Some(Track {
+        title: title.to_string(),
+        artists: item["artist"]["name"]
+            .as_str()
+            .map(|a| vec![a.to_string()])
+            .unwrap_or_default(),
+        isrc: item["isrc"].as_str().map(str::to_string),
+        preview_url: item["preview"]
+            .as_str()
+            .filter(|p| !p.is_empty())
+            .map(str::to_string),
+        // List endpoints only include the album title, the release date needs a full track lookup
+        album: item["album"]["title"].as_str().map(str::to_string),
+        year: item["release_date"].as_str().and_then(parse_year),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lists_from_links() {
+        assert_eq!(
+            DeezerList::from_link("https://www.deezer.com/en/playlist/908622995?utm=x").unwrap(),
+            DeezerList::Playlist("908622995".to_string())
+        );
+        assert_eq!(
+            DeezerList::from_link("https://www.deezer.com/album/302127").unwrap(),
+            DeezerList::Album("302127".to_string())
+        );
+        assert_eq!(
+            DeezerList::from_link("deezer:chart").unwrap(),
+            DeezerList::Chart
+        );
+    }
+
+    #[test]
+    fn rejects_other_deezer_links() {
+        assert!(DeezerList::from_link("https://www.deezer.com/artist/27").is_err());
+    }
+
+    #[test]
+    fn prefers_the_short_title() {
+        let track = parse_track(&serde_json::json!({
+            "title": "Song (Remastered)",
+            "title_short": "Song",
+            "artist": { "name": "Band" },
+            "preview": "",
+            "album": { "title": "Album" },
+        }))
+        .unwrap();
+        assert_eq!(track.title, "Song");
+        assert_eq!(track.artists, vec!["Band".to_string()]);
+        assert_eq!(track.preview_url, None);
+        assert_eq!(track.album.as_deref(), Some("Album"));
+    }
+}
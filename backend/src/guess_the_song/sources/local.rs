@@ -0,0 +1,182 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use tracing::warn;
+
+use super::{SongSource, Track};
+
+const AUDIO_EXTENSIONS: [&str; 6] = ["mp3", "m4a", "ogg", "wav", "aac", "flac"];
+
+/// Root directory for local clips, served to clients under `LOCAL_MUSIC_ROUTE`
+pub fn local_music_dir() -> PathBuf {
+    PathBuf::from(env::var("LOCAL_MUSIC_DIR").unwrap_or("music".to_string()))
+}
+
+pub const LOCAL_MUSIC_ROUTE: &str = "/api/guess-the-song/local";
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    title: String,
+    artists: Vec<String>,
+    album: Option<String>,
+    year: Option<i32>,
+    // Path relative to the manifest, served by this backend
+    file: Option<String>,
+    // Or any remote URL the client can play directly
+    url: Option<String>,
+}
+
+/// Clips from `LOCAL_MUSIC_DIR`, either listed in a JSON manifest (`local:<name>` reads
+/// `<name>.json` or `<name>/manifest.json`) or discovered from a directory of files named
+/// `Artist - Title.mp3`. Needs no network access, which makes it usable offline and in tests.
+pub(crate) struct LocalSource {
+    root: PathBuf,
+    target: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(name: &str) -> Result<Self, String> {
+        let name = name.trim().trim_matches('/');
+        if name.split(['/', '\\']).any(|part| part == "..") {
+            return Err("Invalid local playlist name".to_string());
+        }
+        let root = local_music_dir();
+        Ok(LocalSource {
+            target: root.join(name),
+            root,
+        })
+    }
+
+    fn clip_url(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let encoded: Vec<String> = relative
+            .components()
+            .map(|c| percent_encode(&c.as_os_str().to_string_lossy()))
+            .collect();
+        Some(format!("{}/{}", LOCAL_MUSIC_ROUTE, encoded.join("/")))
+    }
+
+    async fn read_manifest(&self, manifest: &Path) -> Result<Vec<Track>, String> {
+        let contents = tokio::fs::read_to_string(manifest).await.map_err(|e| {
+            warn!("Failed to read manifest {:?}: {:?}", manifest, e);
+            "Failed to read local playlist".to_string()
+        })?;
+        let entries: Vec<ManifestEntry> = serde_json::from_str(&contents).map_err(|e| {
+            warn!("Failed to parse manifest {:?}: {:?}", manifest, e);
+            "Local playlist manifest is malformed".to_string()
+        })?;
+        let base = manifest.parent().unwrap_or(&self.root);
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| Track {
+                preview_url: entry
+                    .url
+                    .or_else(|| entry.file.and_then(|f| self.clip_url(&base.join(f)))),
+                title: entry.title,
+                artists: entry.artists,
+                isrc: None,
+                album: entry.album,
+                year: entry.year,
+            })
+            .collect())
+    }
+
+    async fn scan_directory(&self, dir: &Path) -> Result<Vec<Track>, String> {
+        let mut entries = tokio::fs::read_dir(dir).await.map_err(|e| {
+            warn!("Failed to read directory {:?}: {:?}", dir, e);
+            "Local playlist not found".to_string()
+        })?;
+        let mut tracks = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let is_audio = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+            if !is_audio {
+                continue;
+            }
+            let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let (artists, title) = match stem.split_once(" - ") {
+                Some((artists, title)) => (
+                    artists.split(", ").map(|a| a.trim().to_string()).collect(),
+                    title.trim().to_string(),
+                ),
+                None => (Vec::new(), stem.trim().to_string()),
+            };
+            tracks.push(Track {
+                title,
+                artists,
+                isrc: None,
+                preview_url: self.clip_url(&path),
+                album: None,
+                year: None,
+            });
+        }
+        Ok(tracks)
+    }
+}
+
+impl SongSource for LocalSource {
+    fn fetch_tracks(&self) -> BoxFuture<'_, Result<Vec<Track>, String>> {
+        Box::pin(async move {
+            let manifest = self.target.with_extension("json");
+            if tokio::fs::metadata(&manifest).await.is_ok() {
+                return self.read_manifest(&manifest).await;
+            }
+            let manifest = self.target.join("manifest.json");
+            if tokio::fs::metadata(&manifest).await.is_ok() {
+                return self.read_manifest(&manifest).await;
+            }
+            self.scan_directory(&self.target).await
+        })
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_names_outside_the_music_dir() {
+        assert!(LocalSource::new("../etc").is_err());
+        assert!(LocalSource::new("quiz/../../etc").is_err());
+        assert!(LocalSource::new("/quiz/").is_ok());
+    }
+
+    #[test]
+    fn encodes_clip_urls() {
+        let source = LocalSource::new("quiz").unwrap();
+        let path = source.root.join("quiz").join("AC/DC - Back in Black.mp3");
+        assert_eq!(
+            source.clip_url(&path).unwrap(),
+            format!(
+                "{}/quiz/AC/DC%20-%20Back%20in%20Black.mp3",
+                LOCAL_MUSIC_ROUTE
+            )
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use futures_util::future::BoxFuture;
+
+use crate::guess_the_song::api::SpotifyProvider;
+
+pub mod apple_music;
+pub mod deezer;
+pub mod local;
+pub mod spotify;
+pub mod stub;
+
+/// Upper bound on tracks read from a single playlist across all of its pages
+pub const MAX_PLAYLIST_TRACKS: usize = 1000;
+
+/// A track as reported by a source, before a playable preview has been resolved
+#[derive(Debug, Clone)]
+pub(crate) struct Track {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub isrc: Option<String>,
+    pub preview_url: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<i32>,
+}
+
+pub(crate) trait SongSource: Send + Sync {
+    fn fetch_tracks(&self) -> BoxFuture<'_, Result<Vec<Track>, String>>;
+
+    /// Whether fetched tracks may be reused by later games on the same link
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Picks a source from the format of the playlist link entered in the lobby settings:
+/// - `https://open.spotify.com/playlist/<id>` or `spotify:playlist:<id>`
+/// - `https://www.deezer.com/playlist/<id>`, `https://www.deezer.com/album/<id>` or `deezer:chart`
+/// - `https://music.apple.com/<country>/album/<name>/<id>`
+/// - `local:<name>` for a directory or manifest under `LOCAL_MUSIC_DIR`
+/// - `stub:<count>` for made up tracks that need no network access
+pub(crate) async fn from_link(
+    link: &str,
+    spotify: &SpotifyProvider,
+    http: &reqwest::Client,
+) -> Result<Box<dyn SongSource>, String> {
+    let link = link.trim();
+    if link.is_empty() {
+        return Err("No playlist link set".to_string());
+    }
+    if let Some(name) = link.strip_prefix("local:") {
+        return Ok(Box::new(local::LocalSource::new(name)?));
+    }
+    if let Some(count) = link.strip_prefix("stub:") {
+        return Ok(Box::new(stub::StubSource::new(count)?));
+    }
+    if link.contains("spotify.com/") || link.starts_with("spotify:") {
+        let id = spotify::playlist_id(link)?;
+        let client = spotify.get().await?;
+        return Ok(Box::new(spotify::SpotifyPlaylist::new(client, &id)?));
+    }
+    if link.contains("deezer.com/") || link.starts_with("deezer:") {
+        return Ok(Box::new(deezer::DeezerSource::new(
+            http.clone(),
+            deezer::DeezerList::from_link(link)?,
+        )));
+    }
+    if link.contains("music.apple.com/") {
+        let id = apple_music::album_id(link)?;
+        return Ok(Box::new(apple_music::AppleMusicAlbum::new(
+            http.clone(),
+            &id,
+        )?));
+    }
+    Err("Unrecognised playlist link".to_string())
+}
+
+/// Reads the year from release dates like "1994", "1994-05" or "1994-05-12T00:00:00Z"
+pub(crate) fn parse_year(release_date: &str) -> Option<i32> {
+    release_date.get(..4)?.parse().ok()
+}
+
+/// Returns whatever follows the first matching marker, minus any query string or fragment
+pub(crate) fn extract_id(link: &str, markers: &[&str]) -> Option<String> {
+    let rest = markers.iter().find_map(|m| link.split(m).nth(1))?;
+    let id = rest.split(['?', '#']).next()?.trim_end_matches('/');
+    if id.is_empty() {
+        None
+    } else {
+        Some(id.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_years_from_release_dates() {
+        assert_eq!(parse_year("1994"), Some(1994));
+        assert_eq!(parse_year("1994-05"), Some(1994));
+        assert_eq!(parse_year("1994-05-12T00:00:00Z"), Some(1994));
+        assert_eq!(parse_year("94"), None);
+        assert_eq!(parse_year("unknown"), None);
+    }
+
+    #[test]
+    fn extracts_ids_without_query_or_fragment() {
+        let markers = ["playlist/"];
+        assert_eq!(
+            extract_id("https://example.com/playlist/abc?si=1#top", &markers),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            extract_id("https://example.com/playlist/abc/", &markers),
+            Some("abc".to_string())
+        );
+        assert_eq!(extract_id("https://example.com/playlist/", &markers), None);
+        assert_eq!(extract_id("https://example.com/album/abc", &markers), None);
+    }
+
+    #[tokio::test]
+    async fn picks_a_source_from_the_link() {
+        let spotify = SpotifyProvider::from_env();
+        let http = reqwest::Client::new();
+        let source = from_link(" stub:4 ", &spotify, &http).await.unwrap();
+        assert_eq!(source.fetch_tracks().await.unwrap().len(), 4);
+        assert!(from_link("local:quiz", &spotify, &http).await.is_ok());
+        assert!(
+            from_link("https://www.deezer.com/playlist/123", &spotify, &http)
+                .await
+                .is_ok()
+        );
+        assert!(
+            from_link("https://music.apple.com/us/album/x/123", &spotify, &http)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_links() {
+        let spotify = SpotifyProvider::from_env();
+        let http = reqwest::Client::new();
+        let error = |link: &'static str| {
+            let (spotify, http) = (&spotify, &http);
+            async move { from_link(link, spotify, http).await.err().unwrap() }
+        };
+        assert_eq!(error("  ").await, "No playlist link set");
+        assert_eq!(
+            error("https://example.com/playlist/1").await,
+            "Unrecognised playlist link"
+        );
+        assert!(error("local:../secrets").await.contains("Invalid"));
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use futures_util::{StreamExt, future::BoxFuture};
+use rspotify::{ClientCredsSpotify, clients::BaseClient, model::PlaylistId};
+use tracing::warn;
+
+use super::{MAX_PLAYLIST_TRACKS, SongSource, Track, extract_id, parse_year};
+
+pub(crate) struct SpotifyPlaylist {
+    client: Arc<ClientCredsSpotify>,
+    playlist_id: PlaylistId<'static>,
+}
+
+impl SpotifyPlaylist {
+    pub fn new(client: Arc<ClientCredsSpotify>, id: &str) -> Result<Self, String> {
+        let playlist_id = PlaylistId::from_id(id.to_string())
+            .map_err(|_| "Invalid Spotify playlist ID".to_string())?;
+        Ok(SpotifyPlaylist {
+            client,
+            playlist_id,
+        })
+    }
+}
+
+/// The playlist ID from `https://open.spotify.com/playlist/<id>` or `spotify:playlist:<id>`
+pub(crate) fn playlist_id(link: &str) -> Result<String, String> {
+    extract_id(link, &["playlist/", "playlist:"])
+        .ok_or("Failed to extract playlistID from link".to_string())
+}
+
+impl SongSource for SpotifyPlaylist {
+    fn fetch_tracks(&self) -> BoxFuture<'_, Result<Vec<Track>, String>> {
+        Box::pin(async move {
+            // Follow every page of the playlist rather than just the first 100 items
+            let mut items = self
+                .client
+                .playlist_items(self.playlist_id.clone(), None, None)
+                .take(MAX_PLAYLIST_TRACKS);
+            let mut tracks = Vec::new();
+            while let Some(item) = items.next().await {
+                let item = match item {
+                    Ok(item) => item,
+                    Err(e) if tracks.is_empty() => {
+                        warn!("Failed to fetch playlist: {:?}", e);
+                        return Err("Failed to fetch playlist".to_string());
+                    }
+                    Err(e) => {
+                        warn!("Failed to fetch further playlist pages: {:?}", e);
+                        break;
+                    }
+                };
+                if let Some(rspotify::model::PlayableItem::Track(track)) = item.track {
+                    tracks.push(Track {
+                        year: track.album.release_date.as_deref().and_then(parse_year),
+                        album: Some(track.album.name),
+                        title: track.name,
+                        artists: track.artists.into_iter().map(|a| a.name).collect(),
+                        isrc: track.external_ids.get("isrc").cloned(),
+                        preview_url: None,
+                    });
+                }
+            }
+            Ok(tracks)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_playlist_ids_from_links() {
+        assert_eq!(
+            playlist_id("https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M?si=abc").unwrap(),
+            "37i9dQZF1DXcBWIGoYBM5M"
+        );
+        assert_eq!(
+            playlist_id("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M").unwrap(),
+            "37i9dQZF1DXcBWIGoYBM5M"
+        );
+    }
+
+    #[test]
+    fn rejects_links_without_a_playlist() {
+        assert!(playlist_id("https://open.spotify.com/album/1DFixLWuPkv3KT3TnV35m3").is_err());
+        assert!(playlist_id("https://open.spotify.com/playlist/").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use futures_util::future::BoxFuture;
+
+use super::{MAX_PLAYLIST_TRACKS, SongSource, Track};
+
+const DEFAULT_STUB_TRACKS: usize = 20;
+
+// An empty WAV file, so clients have something they can load without any network access
+const SILENT_CLIP: &str =
+    "data:audio/wav;base64,UklGRiQAAABXQVZFZm10IBAAAAABAAEAQB8AAEAfAAABAAgAZGF0YQAAAAA=";
+
+/// Made up tracks for `stub:<count>` links, e.g. `stub:10`. Every track has a title,
+/// artist, album, year and preview, so any guess mode can be played without a real source.
+pub(crate) struct StubSource {
+    count: usize,
+}
+
+impl StubSource {
+    pub fn new(count: &str) -> Result<Self, String> {
+        let count = match count.trim() {
+            "" => DEFAULT_STUB_TRACKS,
+            count => count
+                .parse()
+                .ok()
+                .filter(|n| (1..=MAX_PLAYLIST_TRACKS).contains(n))
+                .ok_or(format!(
+                    "Stub playlists need between 1 and {} tracks",
+                    MAX_PLAYLIST_TRACKS
+                ))?,
+        };
+        Ok(StubSource { count })
+    }
+}
+
+impl SongSource for StubSource {
+    fn fetch_tracks(&self) -> BoxFuture<'_, Result<Vec<Track>, String>> {
+        Box::pin(async move {
+            Ok((1..=self.count)
+                .map(|n| Track {
+                    title: format!("Stub Song {}", n),
+                    artists: vec![format!("Stub Artist {}", n)],
+                    isrc: None,
+                    preview_url: Some(SILENT_CLIP.to_string()),
+                    album: Some(format!("Stub Album {}", n)),
+                    year: Some(1970 + (n % 50) as i32),
+                })
+                .collect())
+        })
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_the_track_count() {
+        assert_eq!(StubSource::new("").unwrap().count, DEFAULT_STUB_TRACKS);
+        assert_eq!(StubSource::new("5").unwrap().count, 5);
+    }
+
+    #[test]
+    fn rejects_bad_track_counts() {
+        assert!(StubSource::new("0").is_err());
+        assert!(StubSource::new("ten").is_err());
+        assert!(StubSource::new(&(MAX_PLAYLIST_TRACKS + 1).to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn fetches_complete_tracks() {
+        let tracks = StubSource::new("3").unwrap().fetch_tracks().await.unwrap();
+        assert_eq!(tracks.len(), 3);
+        assert!(tracks.iter().all(|t| t.preview_url.is_some()
+            && t.album.is_some()
+            && t.year.is_some()
+            && !t.artists.is_empty()));
+        assert_eq!(tracks[0].title, "Stub Song 1");
+    }
+}
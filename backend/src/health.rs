@@ -0,0 +1,61 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use serde::Serialize;
+
+use crate::{
+    geo_guessr::api::MAPS, guess_the_song::api::IntegrationStatus,
+    guess_the_song::sources::local::local_music_dir, state::AppState,
+};
+
+#[derive(Serialize)]
+struct IntegrationHealth {
+    status: IntegrationStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HealthResponse {
+    status: &'static str,
+    spotify: IntegrationHealth,
+    local_music: IntegrationHealth,
+    geo_guessr_maps: IntegrationHealth,
+}
+
+/// Reports per-integration status; the server itself is up if this responds at all
+pub async fn health(State(state): State<AppState>) -> impl IntoResponse {
+    let (status, detail) = state.spotify.status();
+    let spotify = IntegrationHealth { status, detail };
+
+    let dir = local_music_dir();
+    let local_music = if dir.is_dir() {
+        IntegrationHealth {
+            status: IntegrationStatus::Ok,
+            detail: None,
+        }
+    } else {
+        IntegrationHealth {
+            status: IntegrationStatus::Unconfigured,
+            detail: Some(format!("{} is not a directory", dir.display())),
+        }
+    };
+
+    let geo_guessr_maps = IntegrationHealth {
+        status: if MAPS.is_empty() {
+            IntegrationStatus::Unavailable
+        } else {
+            IntegrationStatus::Ok
+        },
+        detail: Some(format!("{} maps loaded", MAPS.len())),
+    };
+
+    let degraded = [&spotify, &geo_guessr_maps]
+        .iter()
+        .any(|i| i.status == IntegrationStatus::Unavailable);
+    Json(HealthResponse {
+        status: if degraded { "degraded" } else { "ok" },
+        spotify,
+        local_music,
+        geo_guessr_maps,
+    })
+}
@@ -0,0 +1,472 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use rusqlite::{Connection, OptionalExtension, params, types::Type};
+use uuid::Uuid;
+
+use crate::{
+    db,
+    history::{
+        GameRecord, LeaderboardEntry, ModeStats, PlayerKey, PlayerRecord, PlayerRoundRecord,
+        PlayerStats, RecentGame, RoundRecord,
+    },
+    state::GameType,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS games (
+    id TEXT PRIMARY KEY,
+    game_type TEXT NOT NULL,
+    lobby_code TEXT NOT NULL,
+    settings TEXT NOT NULL,
+    finished_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS games_type ON games (game_type);
+
+CREATE TABLE IF NOT EXISTS game_players (
+    game_id TEXT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    player_id TEXT NOT NULL,
+    username TEXT NOT NULL COLLATE NOCASE,
+    score INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    PRIMARY KEY (game_id, player_id)
+);
+CREATE INDEX IF NOT EXISTS game_players_username ON game_players (username);
+
+-- The location or song played each round
+CREATE TABLE IF NOT EXISTS rounds (
+    game_id TEXT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    round INTEGER NOT NULL,
+    details TEXT NOT NULL,
+    PRIMARY KEY (game_id, round)
+);
+
+CREATE TABLE IF NOT EXISTS round_results (
+    game_id TEXT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    round INTEGER NOT NULL,
+    player_id TEXT NOT NULL,
+    points INTEGER NOT NULL,
+    -- GeoGuessr only, null without a guess
+    distance_km REAL,
+    -- Right country in GeoGuessr, any points in Guess The Song
+    correct INTEGER,
+    details TEXT,
+    PRIMARY KEY (game_id, round, player_id)
+);
+";
+
+/// ===============================================
+/// Game History Database
+/// ===============================================
+pub(crate) struct History {
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    pub fn open() -> Self {
+        History {
+            conn: Mutex::new(db::open("game history", SCHEMA)),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Self {
+        History {
+            conn: Mutex::new(db::open_in_memory(SCHEMA)),
+        }
+    }
+
+    pub fn record_game(&self, game: &GameRecord) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let game_id = game.game_id.to_string();
+        tx.execute(
+            "INSERT INTO games (id, game_type, lobby_code, settings, finished_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                game_id,
+                game.game_type.as_str(),
+                game.lobby_code,
+                game.settings.to_string(),
+                game.finished_at as i64,
+            ],
+        )?;
+        {
+            let mut insert_player = tx.prepare(
+                "INSERT INTO game_players (game_id, player_id, username, score, rank)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for player in &game.players {
+                insert_player.execute(params![
+                    game_id,
+                    player.player_id.to_string(),
+                    player.username,
+                    player.score,
+                    player.rank,
+                ])?;
+            }
+            let mut insert_round =
+                tx.prepare("INSERT INTO rounds (game_id, round, details) VALUES (?1, ?2, ?3)")?;
+            let mut insert_result = tx.prepare(
+                "INSERT INTO round_results
+                 (game_id, round, player_id, points, distance_km, correct, details)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for (round, record) in (0u32..).zip(&game.rounds) {
+                insert_round.execute(params![game_id, round, record.details.to_string()])?;
+                for result in &record.results {
+                    insert_result.execute(params![
+                        game_id,
+                        round,
+                        result.player_id.to_string(),
+                        result.points,
+                        result.distance_km,
+                        result.correct,
+                        result.details.as_ref().map(|d| d.to_string()),
+                    ])?;
+                }
+            }
+        }
+        tx.commit()
+    }
+
+    /// A finished game as it was recorded, if it's been kept
+    pub fn game(&self, game_id: Uuid) -> rusqlite::Result<Option<GameRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let id = game_id.to_string();
+        let Some(mut game) = conn
+            .query_row(
+                "SELECT game_type, lobby_code, settings, finished_at FROM games WHERE id = ?1",
+                params![id],
+                |row| {
+                    let game_type: String = row.get(0)?;
+                    Ok(GameRecord {
+                        game_id,
+                        game_type: GameType::from_name(&game_type)
+                            .ok_or(rusqlite::Error::InvalidColumnType(0, game_type, Type::Text))?,
+                        lobby_code: row.get(1)?,
+                        settings: json(row.get(2)?),
+                        finished_at: row.get::<_, i64>(3)? as u64,
+                        players: Vec::new(),
+                        rounds: Vec::new(),
+                    })
+                },
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        game.players = conn
+            .prepare(
+                "SELECT player_id, username, score, rank FROM game_players
+                 WHERE game_id = ?1
+                 ORDER BY rank, username",
+            )?
+            .query_map(params![id], |row| {
+                Ok(PlayerRecord {
+                    player_id: uuid(row.get(0)?)?,
+                    username: row.get(1)?,
+                    score: row.get(2)?,
+                    rank: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        game.rounds = conn
+            .prepare("SELECT details FROM rounds WHERE game_id = ?1 ORDER BY round")?
+            .query_map(params![id], |row| {
+                Ok(RoundRecord {
+                    details: json(row.get(0)?),
+                    results: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut results = conn.prepare(
+            "SELECT round, player_id, points, distance_km, correct, details FROM round_results
+             WHERE game_id = ?1",
+        )?;
+        let mut rows = results.query(params![id])?;
+        while let Some(row) = rows.next()? {
+            let round: u32 = row.get(0)?;
+            if let Some(record) = game.rounds.get_mut(round as usize) {
+                record.results.push(PlayerRoundRecord {
+                    player_id: uuid(row.get(1)?)?,
+                    points: row.get(2)?,
+                    distance_km: row.get(3)?,
+                    correct: row.get(4)?,
+                    details: row.get::<_, Option<String>>(5)?.map(json),
+                });
+            }
+        }
+        Ok(Some(game))
+    }
+
+    pub fn recent_games(
+        &self,
+        player: &PlayerKey,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<RecentGame>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT g.id, g.game_type, g.finished_at, p.score, p.rank,
+                    (SELECT COUNT(*) FROM game_players WHERE game_id = g.id)
+             FROM game_players p JOIN games g ON g.id = p.game_id
+             WHERE {}
+             ORDER BY g.finished_at DESC
+             LIMIT ?2",
+            player.condition()
+        ))?;
+        stmt.query_map(params![player.value(), limit], |row| {
+            Ok(RecentGame {
+                game_id: row.get(0)?,
+                game_type: row.get(1)?,
+                finished_at: row.get(2)?,
+                score: row.get(3)?,
+                rank: row.get(4)?,
+                player_count: row.get(5)?,
+            })
+        })?
+        .collect()
+    }
+
+    pub fn player_stats(&self, player: &PlayerKey) -> rusqlite::Result<PlayerStats> {
+        let conn = self.conn.lock().unwrap();
+        let mut modes: HashMap<String, ModeStats> = HashMap::new();
+        let mut games = conn.prepare(&format!(
+            "SELECT g.game_type, COUNT(*), SUM(p.rank = 1), AVG(p.score), MAX(p.score)
+             FROM game_players p JOIN games g ON g.id = p.game_id
+             WHERE {}
+             GROUP BY g.game_type",
+            player.condition()
+        ))?;
+        let mut rows = games.query(params![player.value()])?;
+        while let Some(row) = rows.next()? {
+            modes.insert(
+                row.get(0)?,
+                ModeStats {
+                    games: row.get(1)?,
+                    wins: row.get(2)?,
+                    average_score: row.get(3)?,
+                    best_score: row.get(4)?,
+                    ..Default::default()
+                },
+            );
+        }
+        let mut rounds = conn.prepare(&format!(
+            "SELECT g.game_type, COUNT(*), AVG(r.points), AVG(r.distance_km), AVG(r.correct)
+             FROM round_results r
+             JOIN game_players p ON p.game_id = r.game_id AND p.player_id = r.player_id
+             JOIN games g ON g.id = r.game_id
+             WHERE {}
+             GROUP BY g.game_type",
+            player.condition()
+        ))?;
+        let mut rows = rounds.query(params![player.value()])?;
+        while let Some(row) = rows.next()? {
+            let stats = modes.entry(row.get(0)?).or_default();
+            stats.rounds = row.get(1)?;
+            stats.average_round_points = row.get(2)?;
+            stats.average_distance_km = row.get(3)?;
+            stats.accuracy = row.get(4)?;
+        }
+        Ok(PlayerStats {
+            games_played: modes.values().map(|m| m.games).sum(),
+            wins: modes.values().map(|m| m.wins).sum(),
+            modes,
+        })
+    }
+
+    pub fn leaderboard(
+        &self,
+        game_type: GameType,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<LeaderboardEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT p.username, COUNT(*), SUM(p.rank = 1), SUM(p.score), MAX(p.score), AVG(p.score)
+             FROM game_players p JOIN games g ON g.id = p.game_id
+             WHERE g.game_type = ?1
+             GROUP BY p.username
+             ORDER BY SUM(p.rank = 1) DESC, SUM(p.score) DESC
+             LIMIT ?2",
+        )?;
+        stmt.query_map(params![game_type.as_str(), limit], |row| {
+            Ok(LeaderboardEntry {
+                username: row.get(0)?,
+                games: row.get(1)?,
+                wins: row.get(2)?,
+                total_score: row.get(3)?,
+                best_score: row.get(4)?,
+                average_score: row.get(5)?,
+            })
+        })?
+        .collect()
+    }
+}
+
+fn json(text: String) -> serde_json::Value {
+    serde_json::from_str(&text).unwrap_or_default()
+}
+
+fn uuid(text: String) -> rusqlite::Result<Uuid> {
+    Uuid::parse_str(&text)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(username: &str, score: u32, rank: u32) -> PlayerRecord {
+        PlayerRecord {
+            player_id: Uuid::new_v4(),
+            username: username.to_string(),
+            score,
+            rank,
+        }
+    }
+
+    /// One round per entry in `correct`, scored 1000 points when right
+    fn game(game_type: GameType, players: Vec<PlayerRecord>, correct: &[bool]) -> GameRecord {
+        let mut game = GameRecord::new(game_type, "ABCD", &());
+        game.rounds = correct
+            .iter()
+            .map(|correct| RoundRecord {
+                details: serde_json::json!({ "imageId": "pano" }),
+                results: players
+                    .iter()
+                    .map(|p| PlayerRoundRecord {
+                        player_id: p.player_id,
+                        points: if *correct { 1000 } else { 0 },
+                        distance_km: Some(10.0),
+                        correct: Some(*correct),
+                        details: None,
+                    })
+                    .collect(),
+            })
+            .collect();
+        game.players = players;
+        game
+    }
+
+    #[test]
+    fn reads_back_recorded_games() {
+        let history = History::open_in_memory();
+        let recorded = game(
+            GameType::GeoGuessr,
+            vec![player("bob", 500, 2), player("alice", 900, 1)],
+            &[true, false],
+        );
+        history.record_game(&recorded).unwrap();
+
+        let game = history.game(recorded.game_id).unwrap().unwrap();
+        assert_eq!(game.game_type, GameType::GeoGuessr);
+        assert_eq!(game.lobby_code, "ABCD");
+        assert_eq!(game.finished_at, recorded.finished_at);
+        let players: Vec<_> = game.players.iter().map(|p| p.username.as_str()).collect();
+        assert_eq!(players, ["alice", "bob"]);
+        assert_eq!(game.rounds.len(), 2);
+        assert_eq!(game.rounds[0].details["imageId"], "pano");
+        assert_eq!(game.rounds[1].results.len(), 2);
+        assert_eq!(game.rounds[1].results[0].correct, Some(false));
+
+        assert!(history.game(Uuid::new_v4()).unwrap().is_none());
+    }
+
+    #[test]
+    fn counts_wins_including_ties() {
+        let history = History::open_in_memory();
+        let games = [
+            vec![player("alice", 900, 1), player("bob", 500, 2)],
+            // Tied for first, both win
+            vec![player("alice", 700, 1), player("bob", 700, 1)],
+            vec![player("bob", 800, 1), player("alice", 100, 2)],
+        ];
+        for players in games {
+            history
+                .record_game(&game(GameType::GeoGuessr, players, &[]))
+                .unwrap();
+        }
+
+        let alice = history
+            .player_stats(&PlayerKey::Username("alice".to_string()))
+            .unwrap();
+        assert_eq!(alice.games_played, 3);
+        assert_eq!(alice.wins, 2);
+        let geo = &alice.modes["geo-guessr"];
+        assert_eq!(geo.best_score, 900);
+        assert!((geo.average_score - 1700.0 / 3.0).abs() < 1e-9);
+
+        // Usernames are matched regardless of case
+        let bob = history
+            .player_stats(&PlayerKey::Username("BOB".to_string()))
+            .unwrap();
+        assert_eq!(bob.wins, 2);
+    }
+
+    #[test]
+    fn averages_round_accuracy() {
+        let history = History::open_in_memory();
+        let alice = player("alice", 2000, 1);
+        let alice_id = alice.player_id;
+        history
+            .record_game(&game(
+                GameType::GuessTheSong,
+                vec![alice],
+                &[true, false, true, true],
+            ))
+            .unwrap();
+
+        let stats = history.player_stats(&PlayerKey::Id(alice_id)).unwrap();
+        let songs = &stats.modes["guess-the-song"];
+        assert_eq!(songs.rounds, 4);
+        assert_eq!(songs.accuracy, Some(0.75));
+        assert_eq!(songs.average_round_points, 750.0);
+        assert_eq!(songs.average_distance_km, Some(10.0));
+
+        let nobody = history
+            .player_stats(&PlayerKey::Username("nobody".to_string()))
+            .unwrap();
+        assert_eq!(nobody.games_played, 0);
+        assert!(nobody.modes.is_empty());
+    }
+
+    #[test]
+    fn ranks_the_leaderboard_by_wins_then_total_score() {
+        let history = History::open_in_memory();
+        let games = [
+            (
+                GameType::GeoGuessr,
+                vec![player("alice", 900, 1), player("bob", 800, 2)],
+            ),
+            (
+                GameType::GeoGuessr,
+                vec![player("carol", 300, 1), player("bob", 200, 2)],
+            ),
+            (
+                GameType::GeoGuessr,
+                vec![player("alice", 100, 1), player("carol", 50, 2)],
+            ),
+            // Other game types don't count
+            (GameType::GuessTheSong, vec![player("bob", 5000, 1)]),
+        ];
+        for (game_type, players) in games {
+            history.record_game(&game(game_type, players, &[])).unwrap();
+        }
+
+        let board = history.leaderboard(GameType::GeoGuessr, 10).unwrap();
+        let order: Vec<_> = board
+            .iter()
+            .map(|e| (e.username.as_str(), e.wins, e.total_score))
+            .collect();
+        assert_eq!(
+            order,
+            [("alice", 2, 1000), ("carol", 1, 350), ("bob", 0, 1000)]
+        );
+        assert_eq!(board[0].games, 2);
+        assert_eq!(board[0].best_score, 900);
+
+        assert_eq!(
+            history.leaderboard(GameType::GeoGuessr, 1).unwrap().len(),
+            1
+        );
+    }
+}
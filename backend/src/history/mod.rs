@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{accounts::authenticate, history::db::History, state::GameType};
+pub mod db;
+
+/// ===============================================
+/// Game History
+/// ===============================================
+/// Every finished game, stored in the local SQLite database
+pub static HISTORY: LazyLock<History> = LazyLock::new(History::open);
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+
+/// A finished game as it's stored
+pub(crate) struct GameRecord {
+    pub game_id: Uuid,
+    pub game_type: GameType,
+    pub lobby_code: String,
+    pub settings: serde_json::Value,
+    pub finished_at: u64,
+    pub players: Vec<PlayerRecord>,
+    pub rounds: Vec<RoundRecord>,
+}
+
+pub(crate) struct PlayerRecord {
+    pub player_id: Uuid,
+    pub username: String,
+    pub score: u32,
+    pub rank: u32,
+}
+
+pub(crate) struct RoundRecord {
+    // The location or song played
+    pub details: serde_json::Value,
+    pub results: Vec<PlayerRoundRecord>,
+}
+
+pub(crate) struct PlayerRoundRecord {
+    pub player_id: Uuid,
+    pub points: u32,
+    pub distance_km: Option<f64>,
+    pub correct: Option<bool>,
+    pub details: Option<serde_json::Value>,
+}
+
+impl GameRecord {
+    pub fn new(game_type: GameType, lobby_code: &str, settings: &impl Serialize) -> Self {
+        GameRecord {
+            game_id: Uuid::new_v4(),
+            game_type,
+            lobby_code: lobby_code.to_string(),
+            settings: serde_json::to_value(settings).unwrap_or_default(),
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            players: Vec::new(),
+            rounds: Vec::new(),
+        }
+    }
+}
+
+/// Saves a finished game in the background, logging rather than failing the game
+pub(crate) fn record_game(game: GameRecord) {
+    tokio::task::spawn_blocking(move || match HISTORY.record_game(&game) {
+        Ok(()) => info!(game_id=%game.game_id, "Recorded {} game", game.game_type.as_str()),
+        Err(e) => error!(game_id=%game.game_id, "Failed to record game: {}", e),
+    });
+}
+
+/// A recorded game by id, or none if it isn't kept or can't be read
+pub(crate) async fn find_game(game_id: Uuid) -> Option<GameRecord> {
+    match tokio::task::spawn_blocking(move || HISTORY.game(game_id)).await {
+        Ok(Ok(game)) => game,
+        Ok(Err(e)) => {
+            error!(game_id=%game_id, "Failed to read game from history: {}", e);
+            None
+        }
+        Err(e) => {
+            error!(game_id=%game_id, "Game history task failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Whose games to look up. Anyone can be looked up by the name they played under,
+/// players with an account also by their id, which follows them across name changes.
+pub(crate) enum PlayerKey {
+    Username(String),
+    Id(Uuid),
+}
+
+impl PlayerKey {
+    /// The `game_players` (aliased `p`) condition matching `?1`
+    fn condition(&self) -> &'static str {
+        match self {
+            PlayerKey::Username(_) => "p.username = ?1",
+            PlayerKey::Id(_) => "p.player_id = ?1",
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            PlayerKey::Username(username) => username.clone(),
+            PlayerKey::Id(id) => id.to_string(),
+        }
+    }
+}
+
+/// ===============================================
+/// Responses
+/// ===============================================
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RecentGame {
+    pub game_id: String,
+    pub game_type: String,
+    pub finished_at: i64,
+    pub score: u32,
+    pub rank: u32,
+    pub player_count: u32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PlayerStats {
+    pub games_played: u32,
+    pub wins: u32,
+    // Game type -> stats for that game
+    pub modes: HashMap<String, ModeStats>,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ModeStats {
+    pub games: u32,
+    pub wins: u32,
+    pub average_score: f64,
+    pub best_score: u32,
+    pub rounds: u32,
+    pub average_round_points: f64,
+    // GeoGuessr rounds with a guess
+    pub average_distance_km: Option<f64>,
+    // Share of rounds answered correctly, see `round_results.correct`
+    pub accuracy: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LeaderboardEntry {
+    pub username: String,
+    pub games: u32,
+    pub wins: u32,
+    pub total_score: i64,
+    pub best_score: u32,
+    pub average_score: f64,
+}
+
+#[derive(Deserialize)]
+pub struct LimitQuery {
+    limit: Option<u32>,
+}
+
+impl LimitQuery {
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+/// ===============================================
+/// Handlers
+/// ===============================================
+pub async fn player_games(
+    Path(username): Path<String>,
+    Query(query): Query<LimitQuery>,
+) -> impl IntoResponse {
+    recent_games(PlayerKey::Username(username), query.limit()).await
+}
+
+pub async fn player_stats(Path(username): Path<String>) -> impl IntoResponse {
+    stats(PlayerKey::Username(username)).await
+}
+
+/// The signed in player's games, under any name they've played as
+pub async fn my_games(headers: HeaderMap, Query(query): Query<LimitQuery>) -> Response {
+    match authenticate(&headers).await {
+        Ok(account) => recent_games(PlayerKey::Id(account.player_id), query.limit()).await,
+        Err(response) => response,
+    }
+}
+
+pub async fn my_stats(headers: HeaderMap) -> Response {
+    match authenticate(&headers).await {
+        Ok(account) => stats(PlayerKey::Id(account.player_id)).await,
+        Err(response) => response,
+    }
+}
+
+async fn recent_games(player: PlayerKey, limit: u32) -> Response {
+    respond(tokio::task::spawn_blocking(move || HISTORY.recent_games(&player, limit)).await)
+}
+
+async fn stats(player: PlayerKey) -> Response {
+    respond(tokio::task::spawn_blocking(move || HISTORY.player_stats(&player)).await)
+}
+
+pub async fn leaderboard(
+    Path(game): Path<String>,
+    Query(query): Query<LimitQuery>,
+) -> impl IntoResponse {
+    let Some(game_type) = GameType::from_name(&game) else {
+        return (StatusCode::NOT_FOUND, "Game mode not found").into_response();
+    };
+    let limit = query.limit();
+    respond(tokio::task::spawn_blocking(move || HISTORY.leaderboard(game_type, limit)).await)
+}
+
+fn respond<T: Serialize>(result: Result<rusqlite::Result<T>, tokio::task::JoinError>) -> Response {
+    match result {
+        Ok(Ok(body)) => Json(body).into_response(),
+        Ok(Err(e)) => {
+            warn!("Failed to query game history: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load history").into_response()
+        }
+        Err(e) => {
+            warn!("Game history query panicked: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load history").into_response()
+        }
+    }
+}
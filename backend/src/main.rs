@@ -1,24 +1,34 @@
 use std::env;
 use std::time::Duration;
 
-use crate::geo_guessr::create_geo_guessr_lobby;
+use crate::geo_guessr::{
+    create_geo_guessr_lobby, get_results, list_maps, results::RESULTS, upload_map,
+};
+use crate::guess_the_song::sources::local::{LOCAL_MUSIC_ROUTE, local_music_dir};
 use crate::{guess_the_song::guess_the_song_create_lobby, state::AppState};
 use axum::http::StatusCode;
 use axum::{
     Router,
     extract::{Path, State, ws::WebSocketUpgrade},
     response::{IntoResponse, Response},
-    routing::{any, post},
+    routing::{any, get, post},
 };
 use rand::{Rng, distr::Alphanumeric};
 use tokio::sync::mpsc;
 use tokio::time::interval;
-use tower_http::cors::CorsLayer;
+use tower_http::{cors::CorsLayer, services::ServeDir};
 use tracing::{Instrument, Level, info, instrument};
 
+mod accounts;
+mod cache;
 mod connections;
+mod dailies;
+mod db;
+mod denylist;
 mod geo_guessr;
 mod guess_the_song;
+mod health;
+mod history;
 mod state;
 
 #[tokio::main]
@@ -30,11 +40,17 @@ async fn main() {
         .init();
     info!("Starting server...");
 
-    let s = guess_the_song::api::get_spotify_client().await;
+    let s = guess_the_song::api::SpotifyProvider::from_env();
     let (clean_tx, mut clean_rx) = mpsc::unbounded_channel();
     let state = AppState::new(s, clean_tx);
     let cleanup_state = state.clone();
     let scan_state = state.clone();
+    let spotify = state.spotify.clone();
+
+    // Warm up the Spotify client in the background, later requests retry on failure
+    tokio::spawn(async move {
+        let _ = spotify.get().await;
+    });
 
     // Spawn direct cleanup thread
     tokio::spawn(
@@ -51,6 +67,8 @@ async fn main() {
         let mut interval = interval(Duration::from_secs(60));
         loop {
             interval.tick().await;
+            scan_state.song_cache.purge_expired();
+            RESULTS.purge_expired();
             scan_state
                 .games
                 .guess_the_song
@@ -59,16 +77,22 @@ async fn main() {
                 .games
                 .geo_guessr
                 .retain(|_, v| !v.lobby.lock().unwrap().empty());
+            scan_state
+                .games
+                .dailies
+                .retain(|_, v| !v.lobby.lock().unwrap().empty());
 
             // Remove registry entries whose game no longer exists
             scan_state.games.registry.retain(|code, _| {
                 scan_state.games.guess_the_song.contains_key(code)
                     || scan_state.games.geo_guessr.contains_key(code)
+                    || scan_state.games.dailies.contains_key(code)
             });
         }
     });
 
     let app = Router::new()
+        .route("/api/health", get(health::health))
         .route(
             "/api/guess-the-song/create-lobby",
             post(guess_the_song_create_lobby),
@@ -77,6 +101,23 @@ async fn main() {
             "/api/geo-guessr/create-lobby",
             post(create_geo_guessr_lobby),
         )
+        .route(
+            "/api/dailies/create-lobby",
+            post(dailies::create_dailies_lobby),
+        )
+        .route("/api/geo-guessr/maps", get(list_maps).post(upload_map))
+        .route("/api/geo-guessr/results/{game_id}", get(get_results))
+        .route("/api/auth/guest", post(accounts::create_guest))
+        .route("/api/auth/register", post(accounts::register))
+        .route("/api/auth/login", post(accounts::login))
+        .route("/api/auth/logout", post(accounts::logout))
+        .route("/api/auth/me", get(accounts::me))
+        .route("/api/me/games", get(history::my_games))
+        .route("/api/me/stats", get(history::my_stats))
+        .route("/api/players/{username}/games", get(history::player_games))
+        .route("/api/players/{username}/stats", get(history::player_stats))
+        .route("/api/leaderboards/{game}", get(history::leaderboard))
+        .nest_service(LOCAL_MUSIC_ROUTE, ServeDir::new(local_music_dir()))
         .route("/api/{game}", any(handle_ws))
         .layer(CorsLayer::very_permissive())
         .with_state(state);
@@ -102,6 +143,7 @@ async fn handle_ws(
             ws.on_upgrade(move |socket| guess_the_song::handle_guess_the_song(socket, state))
         }
         "geo-guessr" => ws.on_upgrade(move |socket| geo_guessr::handle_geo_guessr(socket, state)),
+        "dailies" => ws.on_upgrade(move |socket| dailies::handle_dailies(socket, state)),
         _ => (StatusCode::NOT_FOUND, "Game mode not found").into_response(),
     }
 }
@@ -0,0 +1,261 @@
+use tokio::{
+    sync::watch,
+    time::{Duration, Instant, sleep_until},
+};
+
+/// ===============================================
+/// Game Controls
+/// ===============================================
+/// Lets the lobby pause, skip a round of or abort a running game. Game loops wait on
+/// `GameControl::wait` rather than sleeping, so a paused timer picks up where it left off.
+pub(crate) struct GameControl {
+    state: watch::Sender<ControlState>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ControlState {
+    running: bool,
+    // When the timer being waited on runs out, unset while paused
+    deadline: Option<Instant>,
+    paused_at: Option<Instant>,
+    // Time left on the timer when it was paused
+    remaining: Duration,
+    // Bumped on every skip, so each wait only reacts to skips made while it's waiting
+    skips: u32,
+    aborted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ControlCommand {
+    Pause,
+    Resume,
+    SkipRound,
+    AbortGame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ControlOutcome {
+    Paused {
+        remaining: Duration,
+    },
+    Resumed {
+        remaining: Duration,
+        paused_for: Duration,
+    },
+    Skipped,
+    Aborted,
+}
+
+/// How a wait ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Wait {
+    Elapsed,
+    Skipped,
+    Aborted,
+}
+
+impl GameControl {
+    pub fn new() -> Self {
+        GameControl {
+            state: watch::Sender::new(ControlState::default()),
+        }
+    }
+
+    /// Accepts controls until `stop`, clearing anything left over from the last game
+    pub fn start(&self) {
+        self.state.send_replace(ControlState {
+            running: true,
+            ..ControlState::default()
+        });
+    }
+
+    pub fn stop(&self) {
+        self.state.send_replace(ControlState::default());
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.borrow().paused_at.is_some()
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.state.borrow().aborted
+    }
+
+    pub fn apply(&self, command: ControlCommand) -> Result<ControlOutcome, String> {
+        let mut result = Err("No game is running".to_string());
+        self.state.send_if_modified(|s| {
+            if !s.running || s.aborted {
+                return false;
+            }
+            let now = Instant::now();
+            result = match (command, s.paused_at) {
+                (ControlCommand::Pause, None) => {
+                    s.remaining = s
+                        .deadline
+                        .map_or(Duration::ZERO, |d| d.saturating_duration_since(now));
+                    s.deadline = None;
+                    s.paused_at = Some(now);
+                    Ok(ControlOutcome::Paused {
+                        remaining: s.remaining,
+                    })
+                }
+                (ControlCommand::Pause, Some(_)) => Err("The game is already paused".to_string()),
+                (ControlCommand::Resume, Some(paused_at)) => {
+                    s.deadline = Some(now + s.remaining);
+                    s.paused_at = None;
+                    Ok(ControlOutcome::Resumed {
+                        remaining: s.remaining,
+                        paused_for: now - paused_at,
+                    })
+                }
+                (ControlCommand::Resume, None) => Err("The game isn't paused".to_string()),
+                (ControlCommand::SkipRound, None) => {
+                    s.skips += 1;
+                    Ok(ControlOutcome::Skipped)
+                }
+                (ControlCommand::SkipRound, Some(_)) => {
+                    Err("Resume the game before skipping".to_string())
+                }
+                (ControlCommand::AbortGame, _) => {
+                    s.aborted = true;
+                    Ok(ControlOutcome::Aborted)
+                }
+            };
+            result.is_ok()
+        });
+        result
+    }
+
+    /// Waits out a timer, which stands still while the game is paused
+    pub async fn wait(&self, duration: Duration) -> Wait {
+        let mut rx = self.state.subscribe();
+        let skips = rx.borrow().skips;
+        self.state.send_modify(|s| match s.paused_at {
+            Some(_) => s.remaining = duration,
+            None => s.deadline = Some(Instant::now() + duration),
+        });
+        loop {
+            let state = *rx.borrow_and_update();
+            if state.aborted {
+                return Wait::Aborted;
+            }
+            if state.skips != skips {
+                return Wait::Skipped;
+            }
+            match state.deadline {
+                Some(deadline) if state.paused_at.is_none() => {
+                    tokio::select! {
+                        _ = sleep_until(deadline) => return Wait::Elapsed,
+                        _ = rx.changed() => {}
+                    }
+                }
+                _ => {
+                    let _ = rx.changed().await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{task::JoinHandle, time::advance};
+
+    use super::*;
+
+    fn running() -> Arc<GameControl> {
+        let control = Arc::new(GameControl::new());
+        control.start();
+        control
+    }
+
+    /// Starts waiting in the background, letting the wait set its deadline first
+    async fn start_waiting(control: &Arc<GameControl>, duration: Duration) -> JoinHandle<Wait> {
+        let waiting = tokio::spawn({
+            let control = control.clone();
+            async move { control.wait(duration).await }
+        });
+        tokio::task::yield_now().await;
+        waiting
+    }
+
+    async fn still_waiting(waiting: &JoinHandle<Wait>) -> bool {
+        tokio::task::yield_now().await;
+        !waiting.is_finished()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_with_the_time_left_when_paused() {
+        let control = running();
+        let waiting = start_waiting(&control, Duration::from_secs(10)).await;
+
+        advance(Duration::from_secs(4)).await;
+        assert_eq!(
+            control.apply(ControlCommand::Pause),
+            Ok(ControlOutcome::Paused {
+                remaining: Duration::from_secs(6)
+            })
+        );
+        assert!(control.is_paused());
+        advance(Duration::from_secs(100)).await;
+        assert!(still_waiting(&waiting).await);
+
+        assert_eq!(
+            control.apply(ControlCommand::Resume),
+            Ok(ControlOutcome::Resumed {
+                remaining: Duration::from_secs(6),
+                paused_for: Duration::from_secs(100),
+            })
+        );
+        advance(Duration::from_secs(5)).await;
+        assert!(still_waiting(&waiting).await);
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(waiting.await.unwrap(), Wait::Elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ignores_skips_made_before_the_wait() {
+        let control = running();
+        assert_eq!(
+            control.apply(ControlCommand::SkipRound),
+            Ok(ControlOutcome::Skipped)
+        );
+        let started = Instant::now();
+        assert_eq!(control.wait(Duration::from_secs(5)).await, Wait::Elapsed);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+
+        let waiting = start_waiting(&control, Duration::from_secs(5)).await;
+        control.apply(ControlCommand::SkipRound).unwrap();
+        assert_eq!(waiting.await.unwrap(), Wait::Skipped);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_while_paused() {
+        let control = running();
+        let waiting = start_waiting(&control, Duration::from_secs(10)).await;
+        control.apply(ControlCommand::Pause).unwrap();
+        assert!(control.apply(ControlCommand::SkipRound).is_err());
+
+        assert_eq!(
+            control.apply(ControlCommand::AbortGame),
+            Ok(ControlOutcome::Aborted)
+        );
+        assert_eq!(waiting.await.unwrap(), Wait::Aborted);
+        assert!(control.is_aborted());
+        // Nothing more can be done with an aborted game
+        assert!(control.apply(ControlCommand::Resume).is_err());
+        assert_eq!(control.wait(Duration::from_secs(1)).await, Wait::Aborted);
+    }
+
+    #[test]
+    fn rejects_controls_without_a_game() {
+        let control = GameControl::new();
+        assert!(control.apply(ControlCommand::Pause).is_err());
+        control.start();
+        assert!(control.apply(ControlCommand::Resume).is_err());
+        control.stop();
+        assert!(control.apply(ControlCommand::AbortGame).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    connections::{ConnectionManager, PlayerChannels},
+    dailies::run_dailies,
+    history::{GameRecord, PlayerRecord, PlayerRoundRecord, RoundRecord},
+    state::{
+        AppState, ControlCommand, ControlOutcome, GameControl, GameType, GuessTheSongGame,
+        GuessTheSongGameSettings, GuessTheSongServerEvent, GuessTheSongUserEvent, LobbyServerEvent,
+        LobbyState, LobbyStatus, LobbyUserEvent, RematchOptions, SeriesScoring, SeriesSettings,
+        geoguessr::{GeoGuessr, GeoGuessrServerEvent, GeoGuessrSettings, GeoGuessrUserGameEvent},
+        rank_scores,
+    },
+};
+
+/// ===============================================
+/// Main Parent Struct for Dailies
+/// ===============================================
+/// A meta-lobby playing a list of stages from different games back to back. Each stage
+/// runs in one of the lobby's own games, which share its players, and the stage scores
+/// add up into one set of series standings.
+pub(crate) struct Dailies {
+    pub lobby: Mutex<LobbyState>,
+    pub broadcast: broadcast::Sender<DailiesServerEvent>,
+    pub direct: PlayerChannels<DailiesServerEvent>,
+    pub settings: Mutex<DailiesSettings>,
+    pub lobby_code: String,
+    pub geo_guessr: Arc<GeoGuessr>,
+    pub guess_the_song: Arc<GuessTheSongGame>,
+    pub in_order: mpsc::UnboundedSender<(DailiesServerEvent, oneshot::Sender<()>)>,
+    // Shared with both stage games
+    pub control: Arc<GameControl>,
+}
+
+impl Dailies {
+    pub fn send_to(&self, player_id: &Uuid, event: DailiesServerEvent) {
+        self.direct.send(player_id, event);
+    }
+
+    pub fn send_error(&self, player_id: &Uuid, message: String) {
+        self.send_to(
+            player_id,
+            DailiesServerEvent::DailiesEvent(DailiesEvent::Error { message }),
+        );
+    }
+
+    /// Forwards both stage games' broadcasts to the lobby until the games are dropped,
+    /// along with the events the dailies sends through `in_order`
+    pub fn relay_stage_events(
+        &self,
+        mut in_order: mpsc::UnboundedReceiver<(DailiesServerEvent, oneshot::Sender<()>)>,
+    ) {
+        let mut geo_guessr = self.geo_guessr.broadcast.subscribe();
+        let mut guess_the_song = self.guess_the_song.broadcast.subscribe();
+        let tx = self.broadcast.clone();
+        tokio::spawn(async move {
+            loop {
+                // Biased so a stage's events always go out before anything the dailies
+                // sent after them, e.g. its standings after the stage's `GameEnd`
+                let event = tokio::select! {
+                    biased;
+                    event = geo_guessr.recv() => match event {
+                        Ok(event) => DailiesServerEvent::GeoGuessr(event),
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Stage relay lagged, skipped {} events", n);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    event = guess_the_song.recv() => match event {
+                        Ok(event) => DailiesServerEvent::GuessTheSong(event),
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Stage relay lagged, skipped {} events", n);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    event = in_order.recv() => match event {
+                        Some((event, sent)) => {
+                            let _ = tx.send(event);
+                            let _ = sent.send(());
+                            continue;
+                        }
+                        None => break,
+                    },
+                };
+                let _ = tx.send(event);
+            }
+        });
+    }
+
+    /// Broadcasts an event after every stage event already sent, returning once it's out
+    /// so the stage events that follow can't overtake it
+    pub async fn send_in_order(&self, event: DailiesServerEvent) {
+        let (sent, done) = oneshot::channel();
+        if self.in_order.send((event, sent)).is_ok() {
+            let _ = done.await;
+        }
+    }
+
+    /// Forwards the stage games' private events for a player, until they disconnect
+    pub fn relay_direct_events(self: &Arc<Self>, player_id: Uuid, connection_id: Uuid) {
+        let geo_guessr = self.geo_guessr.direct.register(player_id, connection_id);
+        let guess_the_song = self
+            .guess_the_song
+            .direct
+            .register(player_id, connection_id);
+        relay_direct(
+            Arc::clone(self),
+            player_id,
+            geo_guessr,
+            DailiesServerEvent::GeoGuessr,
+        );
+        relay_direct(
+            Arc::clone(self),
+            player_id,
+            guess_the_song,
+            DailiesServerEvent::GuessTheSong,
+        );
+    }
+
+    pub fn player_join(&self, player_id: Uuid, player_username: String) -> Result<(), &str> {
+        let mut lobby = self.lobby.lock().unwrap();
+        if lobby.has_player(&player_id) {
+            return Err("Already connected to this lobby");
+        }
+        if !matches!(lobby.status, LobbyStatus::Waiting | LobbyStatus::Finished) {
+            return Err("Cannot join game in progress");
+        }
+        self.geo_guessr
+            .player_join(player_id, player_username.clone())?;
+        self.guess_the_song
+            .player_join(player_id, player_username.clone())?;
+        lobby.player_join(player_id, player_username);
+        Ok(())
+    }
+
+    pub fn get_players(&self) -> Vec<(Uuid, String, bool)> {
+        self.lobby.lock().unwrap().get_players()
+    }
+
+    pub fn get_settings(&self) -> DailiesSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn get_lobby_status(&self) -> LobbyStatus {
+        self.lobby.lock().unwrap().status.clone()
+    }
+
+    pub fn get_standings(&self) -> HashMap<Uuid, u32> {
+        self.lobby.lock().unwrap().series.standings.clone()
+    }
+
+    pub fn update_settings(&self, player_id: Uuid, mut settings: DailiesSettings) {
+        if self.get_lobby_status() != LobbyStatus::Waiting {
+            self.send_error(
+                &player_id,
+                "Settings can only be changed in the waiting room".to_string(),
+            );
+            return;
+        }
+        if let Err(message) = settings.validate() {
+            self.send_error(&player_id, message);
+            return;
+        }
+        *self.settings.lock().unwrap() = settings.clone();
+        let _ = self.broadcast.send(DailiesServerEvent::DailiesEvent(
+            DailiesEvent::SettingsUpdated { settings },
+        ));
+    }
+
+    /// Ready and rematch events, returning true once everyone is ready to start
+    pub fn handle_lobby_event(&self, player_id: Uuid, event: LobbyUserEvent) -> bool {
+        match event {
+            LobbyUserEvent::Ready if self.get_lobby_status() == LobbyStatus::Finished => {
+                self.vote_rematch(player_id)
+            }
+            LobbyUserEvent::Ready => {
+                self.lobby.lock().unwrap().player_ready(&player_id);
+                self.broadcast_lobby(LobbyServerEvent::PlayerReady { player_id });
+                self.try_begin()
+            }
+            LobbyUserEvent::Unready => {
+                self.lobby.lock().unwrap().player_unready(&player_id);
+                self.broadcast_lobby(LobbyServerEvent::PlayerUnready { player_id });
+                false
+            }
+            LobbyUserEvent::Rematch => self.vote_rematch(player_id),
+            LobbyUserEvent::SetRematchOptions { options } => {
+                let result = self.lobby.lock().unwrap().set_rematch_options(options);
+                match result {
+                    Ok(()) => self.broadcast_rematch_votes(),
+                    Err(message) => self.send_error(&player_id, message),
+                }
+                false
+            }
+            LobbyUserEvent::JoinTeam { .. } | LobbyUserEvent::AutoBalanceTeams => {
+                self.send_error(&player_id, "Dailies are played solo".to_string());
+                false
+            }
+            LobbyUserEvent::Join { .. } => false,
+            event => {
+                if let Some(command) = event.control_command() {
+                    self.control_game(player_id, command);
+                }
+                false
+            }
+        }
+    }
+
+    fn control_game(&self, player_id: Uuid, command: ControlCommand) {
+        // The controls are shared, so one apply covers both stages. Both round starts move
+        // on resume so the pause doesn't count against anyone's guess in either game.
+        match self.control.apply(command) {
+            Ok(outcome) => {
+                if let ControlOutcome::Resumed { paused_for, .. } = outcome {
+                    let geo_guessr = &self.geo_guessr.state;
+                    geo_guessr.lock().unwrap().shift_round_start(paused_for);
+                    let guess_the_song = &self.guess_the_song.state;
+                    guess_the_song.lock().unwrap().shift_round_start(paused_for);
+                }
+                info!(?command, "Game control");
+                self.broadcast_lobby(LobbyServerEvent::from_control(player_id, outcome));
+            }
+            Err(message) => self.send_error(&player_id, message),
+        }
+    }
+
+    /// Stage events are only for the game currently being played, settings come
+    /// from the dailies
+    pub fn handle_geo_guessr_event(&self, player_id: Uuid, event: GeoGuessrUserGameEvent) {
+        match event {
+            GeoGuessrUserGameEvent::UpdateGameSettings { .. } => self.send_error(
+                &player_id,
+                "Change the stage in the dailies settings instead".to_string(),
+            ),
+            event => self.geo_guessr.handle_game_event(player_id, event),
+        }
+    }
+
+    /// Atomically moves a fully ready waiting room to `Playing`, returning whether the
+    /// dailies should start
+    fn try_begin(&self) -> bool {
+        let mut lobby = self.lobby.lock().unwrap();
+        if lobby.all_ready() && lobby.status == LobbyStatus::Waiting {
+            lobby.update_lobby_status(LobbyStatus::Playing);
+            return true;
+        }
+        false
+    }
+
+    fn vote_rematch(&self, player_id: Uuid) -> bool {
+        let voted = self.lobby.lock().unwrap().vote_rematch(player_id);
+        match voted {
+            Ok(rematch) => {
+                self.broadcast_rematch_votes();
+                rematch.is_some_and(|options| self.start_rematch(options))
+            }
+            Err(message) => {
+                self.send_error(&player_id, message);
+                false
+            }
+        }
+    }
+
+    fn broadcast_rematch_votes(&self) {
+        let (votes, options) = {
+            let lobby = self.lobby.lock().unwrap();
+            (lobby.get_rematch_votes(), lobby.rematch_options.clone())
+        };
+        self.broadcast_lobby(LobbyServerEvent::RematchVotes { votes, options });
+    }
+
+    /// Stages always get new locations and songs, so only the other options apply
+    fn start_rematch(&self, options: RematchOptions) -> bool {
+        info!(?options, "Starting rematch");
+        self.broadcast_lobby(LobbyServerEvent::UpdateLobbyStatus {
+            new_status: LobbyStatus::Waiting,
+        });
+        options.same_settings && self.try_begin()
+    }
+
+    pub fn broadcast_lobby(&self, event: LobbyServerEvent) {
+        let _ = self.broadcast.send(DailiesServerEvent::LobbyEvent(event));
+    }
+
+    /// The dailies as they're kept in the game history, one round per stage
+    pub fn to_record(&self, stages: &[(GameType, HashMap<Uuid, u32>)]) -> GameRecord {
+        let mut record = GameRecord::new(GameType::Dailies, &self.lobby_code, &self.get_settings());
+        let usernames: HashMap<Uuid, String> = self
+            .get_players()
+            .into_iter()
+            .map(|(id, username, _)| (id, username))
+            .collect();
+        let standings = self.get_standings();
+        let ranks = rank_scores(&standings);
+        record.players = standings
+            .iter()
+            .filter_map(|(player_id, score)| {
+                Some(PlayerRecord {
+                    player_id: *player_id,
+                    username: usernames.get(player_id)?.clone(),
+                    score: *score,
+                    rank: ranks[player_id],
+                })
+            })
+            .collect();
+        record.rounds = stages
+            .iter()
+            .map(|(game_type, scores)| RoundRecord {
+                details: serde_json::json!({ "game": game_type.as_str() }),
+                results: scores
+                    .iter()
+                    .filter(|(player_id, _)| usernames.contains_key(player_id))
+                    .map(|(player_id, points)| PlayerRoundRecord {
+                        player_id: *player_id,
+                        points: *points,
+                        distance_km: None,
+                        correct: None,
+                        details: None,
+                    })
+                    .collect(),
+            })
+            .collect();
+        record
+    }
+}
+
+impl ConnectionManager for Dailies {
+    fn connection_drop(self: &Arc<Self>, player_id: Uuid, connection_id: Uuid, state: &AppState) {
+        self.lobby.lock().unwrap().player_leave(&player_id);
+        self.geo_guessr
+            .lobby
+            .lock()
+            .unwrap()
+            .player_leave(&player_id);
+        {
+            let mut state = self.geo_guessr.state.lock().unwrap();
+            state.scores.remove(&player_id);
+            state.eliminated.remove(&player_id);
+        }
+        self.guess_the_song
+            .lobby_state
+            .lock()
+            .unwrap()
+            .player_leave(&player_id);
+        self.guess_the_song
+            .state
+            .lock()
+            .unwrap()
+            .scores
+            .remove(&player_id);
+        self.direct.remove(&player_id, connection_id);
+        self.geo_guessr.direct.remove(&player_id, connection_id);
+        self.guess_the_song.direct.remove(&player_id, connection_id);
+        info!(
+            "Player {} disconnected from lobby: {}",
+            player_id, self.lobby_code
+        );
+        self.broadcast_lobby(LobbyServerEvent::PlayerLeave { player_id });
+        // Only the stage being played has a round open to report
+        self.geo_guessr.recount_broken_reports();
+        self.guess_the_song.recount_broken_reports();
+        let rematch = self.lobby.lock().unwrap().try_start_rematch();
+        if rematch.is_some_and(|(_, options)| self.start_rematch(options)) {
+            tokio::spawn(run_dailies(self.clone(), state.clone()));
+        }
+    }
+    fn lobby_code(&self) -> String {
+        self.lobby_code.clone()
+    }
+    fn no_connections(&self) -> bool {
+        self.lobby.lock().unwrap().empty()
+    }
+}
+
+fn relay_direct<E: Send + 'static>(
+    dailies: Arc<Dailies>,
+    player_id: Uuid,
+    mut rx: mpsc::UnboundedReceiver<E>,
+    wrap: fn(E) -> DailiesServerEvent,
+) {
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            dailies.send_to(&player_id, wrap(event));
+        }
+    });
+}
+
+/// ===============================================
+/// Settings
+/// ===============================================
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DailiesSettings {
+    pub stages: Vec<DailyStage>,
+    // How each stage's scores count towards the standings, by placement unless set
+    #[serde(default)]
+    pub scoring: SeriesScoring,
+}
+
+/// One game's worth of rounds, e.g. 3 locations on a map or 3 songs from a playlist
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "game", content = "settings", rename_all = "camelCase")]
+pub(crate) enum DailyStage {
+    GeoGuessr(GeoGuessrSettings),
+    GuessTheSong(GuessTheSongGameSettings),
+}
+
+pub(crate) const MAX_STAGES: usize = 10;
+const DEFAULT_STAGE_ROUNDS: u8 = 3;
+
+impl DailiesSettings {
+    pub fn new() -> Self {
+        DailiesSettings {
+            stages: vec![DailyStage::GeoGuessr(GeoGuessrSettings {
+                num_rounds: DEFAULT_STAGE_ROUNDS,
+                ..GeoGuessrSettings::new()
+            })],
+            scoring: SeriesScoring::default(),
+        }
+    }
+
+    /// Validates every stage with its own game's rules. Stages are played solo, so
+    /// team and series settings are dropped.
+    pub fn validate(&mut self) -> Result<(), String> {
+        if self.stages.is_empty() {
+            return Err("Dailies need at least one stage".to_string());
+        }
+        if self.stages.len() > MAX_STAGES {
+            return Err(format!("Dailies can have at most {} stages", MAX_STAGES));
+        }
+        for stage in &mut self.stages {
+            match stage {
+                DailyStage::GeoGuessr(settings) => {
+                    let mut validated = GeoGuessrSettings::new();
+                    validated.update_game_settings(settings.clone())?;
+                    validated.teams = None;
+                    validated.series = None;
+                    *settings = validated;
+                }
+                DailyStage::GuessTheSong(settings) => {
+                    settings.validate()?;
+                    if settings.playlist_link.is_empty() {
+                        return Err("Guess The Song stages need a playlist".to_string());
+                    }
+                    settings.teams = None;
+                    settings.series = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every stage is one game of the series
+    pub fn series(&self) -> SeriesSettings {
+        SeriesSettings {
+            num_games: self.stages.len() as u8,
+            scoring: self.scoring,
+        }
+    }
+}
+
+impl DailyStage {
+    pub fn game_type(&self) -> GameType {
+        match self {
+            DailyStage::GeoGuessr(_) => GameType::GeoGuessr,
+            DailyStage::GuessTheSong(_) => GameType::GuessTheSong,
+        }
+    }
+}
+
+/// ===============================================
+/// Server Events
+/// ===============================================
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event")]
+pub(crate) enum DailiesEvent {
+    SyncState {
+        players: Vec<(Uuid, String, bool)>,
+        settings: DailiesSettings,
+        status: LobbyStatus,
+        standings: HashMap<Uuid, u32>,
+    },
+    SettingsUpdated {
+        settings: DailiesSettings,
+    },
+    // The stage's own game events follow, under its game's type
+    StageStart {
+        stage: usize,
+        num_stages: usize,
+        details: DailyStage,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "data")]
+pub(crate) enum DailiesServerEvent {
+    LobbyEvent(LobbyServerEvent),
+    DailiesEvent(DailiesEvent),
+    GeoGuessr(GeoGuessrServerEvent),
+    GuessTheSong(GuessTheSongServerEvent),
+}
+
+/// ===============================================
+/// User Events
+/// ===============================================
+#[derive(Deserialize, Debug)]
+#[serde(tag = "event")]
+pub(crate) enum DailiesUserEvent {
+    UpdateSettings { settings: DailiesSettings },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", content = "data")]
+pub(crate) enum DailiesClientEvent {
+    LobbyEvent(LobbyUserEvent),
+    DailiesEvent(DailiesUserEvent),
+    GeoGuessr(GeoGuessrUserGameEvent),
+    GuessTheSong(GuessTheSongUserEvent),
+}
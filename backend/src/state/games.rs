@@ -1,22 +1,49 @@
 use std::sync::{Arc, Mutex};
 
 use dashmap::DashMap;
-use tokio::sync::{Notify, broadcast};
+use tokio::sync::{Notify, broadcast, mpsc};
 
-use crate::state::{
-    GuessTheSongGame, GuessTheSongGameState, GuessTheSongServerEvent, LobbyState,
-    geoguessr::{GeoGuessr, GeoGuessrServerEvent, GeoGuessrSettings, GeoGuessrState},
-    guessthesong::GuessTheSongGameSettings,
+use crate::{
+    connections::PlayerChannels,
+    state::{
+        Dailies, DailiesServerEvent, DailiesSettings, GameControl, GuessTheSongGame,
+        GuessTheSongGameState, GuessTheSongServerEvent, LobbyState,
+        geoguessr::{GeoGuessr, GeoGuessrServerEvent, GeoGuessrSettings, GeoGuessrState},
+        guessthesong::GuessTheSongGameSettings,
+    },
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum GameType {
     GuessTheSong,
     GeoGuessr,
+    Dailies,
+}
+
+impl GameType {
+    /// The name used in routes and stored game history
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameType::GuessTheSong => "guess-the-song",
+            GameType::GeoGuessr => "geo-guessr",
+            GameType::Dailies => "dailies",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "guess-the-song" => Some(GameType::GuessTheSong),
+            "geo-guessr" => Some(GameType::GeoGuessr),
+            "dailies" => Some(GameType::Dailies),
+            _ => None,
+        }
+    }
 }
 
 pub(crate) struct Games {
     pub guess_the_song: DashMap<String, Arc<GuessTheSongGame>>,
     pub geo_guessr: DashMap<String, Arc<GeoGuessr>>,
+    pub dailies: DashMap<String, Arc<Dailies>>,
     pub registry: DashMap<String, GameType>,
 }
 
@@ -25,39 +52,58 @@ impl Games {
         Games {
             guess_the_song: DashMap::new(),
             geo_guessr: DashMap::new(),
+            dailies: DashMap::new(),
             registry: DashMap::new(),
         }
     }
 
     pub fn add_guess_the_song_lobby(&self, lobby_code: &String) {
-        let (send, _) = broadcast::channel::<GuessTheSongServerEvent>(64);
-        let lobby = GuessTheSongGame {
-            lobby_state: Mutex::new(LobbyState::new()),
-            broadcast: send,
-            settings: Mutex::new(GuessTheSongGameSettings::new()),
-            state: Mutex::new(GuessTheSongGameState::new()),
-            lobby_code: lobby_code.to_string(),
-        };
-        self.guess_the_song
-            .insert(lobby_code.to_string(), Arc::new(lobby));
+        self.guess_the_song.insert(
+            lobby_code.to_string(),
+            Arc::new(new_guess_the_song(
+                lobby_code,
+                Arc::new(GameControl::new()),
+                false,
+            )),
+        );
         self.registry
             .insert(lobby_code.to_string(), GameType::GuessTheSong);
     }
 
     pub fn add_geo_guessr_lobby(&self, lobby_code: &String) {
-        let (send, _) = broadcast::channel::<GeoGuessrServerEvent>(64);
-        let lobby = GeoGuessr {
+        self.geo_guessr.insert(
+            lobby_code.to_string(),
+            Arc::new(new_geo_guessr(
+                lobby_code,
+                Arc::new(GameControl::new()),
+                false,
+            )),
+        );
+        self.registry
+            .insert(lobby_code.to_string(), GameType::GeoGuessr);
+    }
+
+    /// The stage games aren't registered, they're only reachable through the dailies lobby.
+    /// They share its controls, so pausing the lobby pauses whichever stage is running.
+    pub fn add_dailies_lobby(&self, lobby_code: &String) {
+        let (send, _) = broadcast::channel::<DailiesServerEvent>(64);
+        let (in_order, in_order_rx) = mpsc::unbounded_channel();
+        let control = Arc::new(GameControl::new());
+        let lobby = Dailies {
             lobby: Mutex::new(LobbyState::new()),
             broadcast: send,
-            settings: Mutex::new(GeoGuessrSettings::new()),
-            state: Mutex::new(GeoGuessrState::new()),
+            direct: PlayerChannels::new(),
+            settings: Mutex::new(DailiesSettings::new()),
             lobby_code: lobby_code.to_string(),
-            round_notify: Mutex::new(Arc::new(Notify::new())),
+            geo_guessr: Arc::new(new_geo_guessr(lobby_code, control.clone(), true)),
+            guess_the_song: Arc::new(new_guess_the_song(lobby_code, control.clone(), true)),
+            in_order,
+            control,
         };
-        self.geo_guessr
-            .insert(lobby_code.to_string(), Arc::new(lobby));
+        lobby.relay_stage_events(in_order_rx);
+        self.dailies.insert(lobby_code.to_string(), Arc::new(lobby));
         self.registry
-            .insert(lobby_code.to_string(), GameType::GeoGuessr);
+            .insert(lobby_code.to_string(), GameType::Dailies);
     }
 
     pub fn remove_lobby(&self, lobby_code: &str) {
@@ -69,6 +115,9 @@ impl Games {
                 GameType::GeoGuessr => {
                     self.geo_guessr.remove(lobby_code);
                 }
+                GameType::Dailies => {
+                    self.dailies.remove(lobby_code);
+                }
             }
         }
     }
@@ -77,3 +126,37 @@ impl Games {
         self.registry.contains_key(lobby_code)
     }
 }
+
+fn new_guess_the_song(
+    lobby_code: &str,
+    control: Arc<GameControl>,
+    stage: bool,
+) -> GuessTheSongGame {
+    let (send, _) = broadcast::channel::<GuessTheSongServerEvent>(64);
+    GuessTheSongGame {
+        lobby_state: Mutex::new(LobbyState::new()),
+        broadcast: send,
+        direct: PlayerChannels::new(),
+        settings: Mutex::new(GuessTheSongGameSettings::new()),
+        state: Mutex::new(GuessTheSongGameState::new()),
+        lobby_code: lobby_code.to_string(),
+        loading: Mutex::new(None),
+        control,
+        stage,
+    }
+}
+
+fn new_geo_guessr(lobby_code: &str, control: Arc<GameControl>, stage: bool) -> GeoGuessr {
+    let (send, _) = broadcast::channel::<GeoGuessrServerEvent>(64);
+    GeoGuessr {
+        lobby: Mutex::new(LobbyState::new()),
+        broadcast: send,
+        direct: PlayerChannels::new(),
+        settings: Mutex::new(GeoGuessrSettings::new()),
+        state: Mutex::new(GeoGuessrState::new()),
+        lobby_code: lobby_code.to_string(),
+        round_notify: Mutex::new(Arc::new(Notify::new())),
+        control,
+        stage,
+    }
+}
@@ -1,8 +1,27 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
+use crate::{
+    accounts::{identify, player_name},
+    connections::{ConnectionManager, PlayerChannels},
+    denylist::{denied, report_item},
+    geo_guessr::{
+        api::MAPS,
+        countries::{CountryRef, countries_loaded, country_at},
+        results::{GameSummary, PlayerSummary, RESULTS, RoundSummary},
+    },
+    history::{GameRecord, PlayerRecord, PlayerRoundRecord, RoundRecord, record_game},
+    state::{
+        AppState, ControlCommand, ControlOutcome, GameControl, GameType, GuessTheSongServerEvent,
+        LobbyServerEvent, LobbyState, LobbyStatus, LobbyUserEvent, MAX_TEAMS, RematchOptions,
+        SERIES_BREAK_SECONDS, SeriesSettings, TeamLeaderboard, TeamSettings, Wait,
+        apply_team_round, rank_scores,
+        validation::{MAX_ROUND_DELAY_SECONDS, require_nonzero, validate_coordinates},
+    },
+};
 use axum::extract::ws::{Message, WebSocket};
 use futures_util::{
     SinkExt, StreamExt,
@@ -11,14 +30,10 @@ use futures_util::{
 use serde::{Deserialize, Serialize};
 use tokio::{
     sync::{Notify, broadcast},
-    time::{Duration, sleep},
+    time::Duration,
 };
 use tracing::info;
 use uuid::Uuid;
-use rand::prelude::IndexedRandom;
-use crate::{
-    connections::ConnectionManager, geo_guessr::api::MAPS, state::{GuessTheSongServerEvent, LobbyServerEvent, LobbyState, LobbyStatus, LobbyUserEvent}
-};
 
 /// ===============================================
 /// Main Parent Struct for GeoGuessr Game
@@ -26,22 +41,47 @@ use crate::{
 pub(crate) struct GeoGuessr {
     pub lobby: Mutex<LobbyState>,
     pub broadcast: broadcast::Sender<GeoGuessrServerEvent>,
+    pub direct: PlayerChannels<GeoGuessrServerEvent>,
     pub settings: Mutex<GeoGuessrSettings>,
     pub state: Mutex<GeoGuessrState>,
     pub lobby_code: String,
     pub round_notify: Mutex<Arc<Notify>>,
+    // Shared with the other stage game in a dailies lobby
+    pub control: Arc<GameControl>,
+    // Played as a dailies stage, so it's recorded as part of the dailies rather than alone
+    pub stage: bool,
 }
 
 impl GeoGuessr {
-    fn reset(&self) {
-        self.state.lock().unwrap().reset();
-        self.lobby.lock().unwrap().reset();
+    /// Adds the game to the series standings, returning whether the series has games left.
+    /// Otherwise the lobby moves to the post-game results, keeping the scores and locations
+    /// until a rematch starts.
+    fn finish(&self) -> bool {
+        let scores = self.get_leaderboard();
+        let series = self.get_settings().series;
+        let standings = self.lobby.lock().unwrap().finish(&scores, series.as_ref());
+        let complete = standings.complete;
+        if complete {
+            let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+                LobbyServerEvent::UpdateLobbyStatus {
+                    new_status: LobbyStatus::Finished,
+                },
+            ));
+        }
+        let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+            LobbyServerEvent::SeriesStandings(standings),
+        ));
+        !complete
+    }
+
+    pub fn send_to(&self, player_id: &Uuid, event: GeoGuessrServerEvent) {
+        self.direct.send(player_id, event);
     }
 
     pub async fn await_join_req(
         receiver: &mut SplitStream<WebSocket>,
         sender: &mut SplitSink<WebSocket, Message>,
-    ) -> Result<(String, String), ()> {
+    ) -> Result<(String, String, Option<Uuid>), ()> {
         let join_req = match receiver.next().await {
             Some(Ok(Message::Text(m))) => m,
             _ => return Err(()),
@@ -65,11 +105,12 @@ impl GeoGuessr {
             }
         };
 
-        let (lobby_code, player_username) = match event {
+        let (lobby_code, player_username, token) = match event {
             GeoGuesserClientEvent::LobbyEvent(LobbyUserEvent::Join {
                 lobby_code,
                 username,
-            }) => (lobby_code, username),
+                token,
+            }) => (lobby_code, username, token),
             _ => {
                 info!("JOIN ERROR");
                 let _ = sender
@@ -84,17 +125,42 @@ impl GeoGuessr {
                 return Err(());
             }
         };
-        Ok((lobby_code, player_username))
+        let identity = match identify(token).await {
+            Ok(account) => player_name(account.as_ref(), &player_username)
+                .await
+                .map(|username| (account.map(|a| a.player_id), username)),
+            Err(message) => Err(message),
+        };
+        let (player_id, player_username) = match identity {
+            Ok(identity) => identity,
+            Err(message) => {
+                info!("JOIN ERROR");
+                let _ = sender
+                    .send(Message::Text(
+                        serde_json::to_string(&GeoGuessrServerEvent::LobbyEvent(
+                            LobbyServerEvent::JoinError { message },
+                        ))
+                        .unwrap()
+                        .into(),
+                    ))
+                    .await;
+                return Err(());
+            }
+        };
+        Ok((lobby_code, player_username, player_id))
     }
 
     pub fn player_join(&self, player_id: Uuid, player_username: String) -> Result<(), &str> {
         let mut lobby = self.lobby.lock().unwrap();
         let mut state = self.state.lock().unwrap();
-        if lobby.status != crate::state::LobbyStatus::Waiting {
+        if lobby.has_player(&player_id) {
+            return Err("Already connected to this lobby");
+        }
+        if !matches!(lobby.status, LobbyStatus::Waiting | LobbyStatus::Finished) {
             return Err("Cannot join game in progress");
         }
-        lobby.player_join(player_id.clone(), player_username);
-        state.scores.insert(player_id.clone(), 0);
+        lobby.player_join(player_id, player_username);
+        state.scores.insert(player_id, 0);
         Ok(())
     }
 
@@ -120,7 +186,46 @@ impl GeoGuessr {
         lobby.status = status;
     }
 
-    async fn load_locations(&self) -> Result<(), String> {
+    pub fn get_teams(&self) -> HashMap<Uuid, u8> {
+        self.lobby.lock().unwrap().get_teams()
+    }
+
+    pub fn get_team_leaderboard(&self) -> Option<TeamLeaderboard> {
+        self.settings.lock().unwrap().teams.as_ref()?;
+        Some(self.state.lock().unwrap().team_scores.clone())
+    }
+
+    /// Places anyone without a team and zeroes every team's score before a game,
+    /// returning the final teams if teams are enabled
+    fn prepare_teams(&self) -> Option<HashMap<Uuid, u8>> {
+        let teams = self.get_settings().teams?;
+        let assignments = {
+            let mut lobby = self.lobby.lock().unwrap();
+            lobby.fill_teams(teams.count);
+            lobby.get_teams()
+        };
+        self.state.lock().unwrap().team_scores = (0..teams.count).map(|t| (t, 0)).collect();
+        Some(assignments)
+    }
+
+    fn update_teams(&self, player_id: Uuid, result: Result<(), String>) {
+        match result {
+            Ok(_) => {
+                let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+                    LobbyServerEvent::TeamsUpdated {
+                        teams: self.get_teams(),
+                    },
+                ));
+            }
+            Err(message) => self.send_to(
+                &player_id,
+                GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+            ),
+        }
+    }
+
+    pub async fn load_locations(&self) -> Result<(), String> {
+        let denied = denied(GameType::GeoGuessr).await;
         let mut rng = rand::rng();
         let settings = self.settings.lock().unwrap().clone();
 
@@ -128,107 +233,417 @@ impl GeoGuessr {
             .get(&settings.map)
             .ok_or_else(|| format!("Unknown map: {}", settings.map))?;
 
-        let sample: Vec<Location> = map
-            .locations
-            .choose_multiple(&mut rng, settings.num_rounds as usize)
-            .cloned()
-            .collect();
+        let num_rounds = match settings.mode {
+            GeoGuessrMode::Classic => settings.num_rounds as usize,
+            GeoGuessrMode::CountryStreak => STREAK_MAX_ROUNDS,
+        };
+        let mut state = self.state.lock().unwrap();
+        // A rematch replaying the last game keeps its locations
+        if !state.locations.is_empty() {
+            return Ok(());
+        }
+        let seen = state.seen_locations.entry(map.name.clone()).or_default();
+        // Start the cycle over once there aren't enough unseen locations left for a game
+        if map
+            .playable()
+            .filter(|l| !seen.contains(&l.image_id) && !denied.contains(&l.image_id))
+            .count()
+            < num_rounds
+        {
+            seen.clear();
+        }
+        let skipped: HashSet<String> = seen.union(&denied).cloned().collect();
+        let mut sample = map.sample(num_rounds, &skipped, &mut rng);
+        if sample.is_empty() {
+            return Err("Every location on this map has been reported broken".to_string());
+        }
+        if settings.mode == GeoGuessrMode::CountryStreak {
+            // Rounds are right or wrong by country, so skip locations outside the dataset
+            sample.retain(|l| country_at(l.lat, l.lng).is_some());
+            if sample.is_empty() {
+                return Err("No locations on this map are inside a known country".to_string());
+            }
+        }
+        seen.extend(sample.iter().map(|l| l.image_id.clone()));
 
-        self.state.lock().unwrap().locations = sample;
+        state.locations = sample;
+        state.score_scale_km = map.score_scale_km();
         Ok(())
     }
 
     pub fn handle_game_event(&self, player_id: Uuid, event: GeoGuessrUserGameEvent) {
+        if self.control.is_paused()
+            && !matches!(event, GeoGuessrUserGameEvent::UpdateGameSettings { .. })
+        {
+            self.send_to(
+                &player_id,
+                GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error {
+                    message: "The game is paused".to_string(),
+                }),
+            );
+            return;
+        }
         match event {
             GeoGuessrUserGameEvent::UpdateGameSettings { settings } => {
                 let lobby = self.lobby.lock().unwrap();
                 if lobby.status != crate::state::LobbyStatus::Waiting {
+                    self.send_to(
+                        &player_id,
+                        GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error {
+                            message: "Settings can only be changed in the waiting room".to_string(),
+                        }),
+                    );
                     return;
                 }
-                self.settings
-                    .lock()
-                    .unwrap()
-                    .update_game_settings(settings.clone());
+                let updated = self.settings.lock().unwrap().update_game_settings(settings);
+                if let Err(message) = updated {
+                    self.send_to(
+                        &player_id,
+                        GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+                    );
+                    return;
+                }
+                // Locations kept for a replay may not suit the new settings
+                self.state.lock().unwrap().locations.clear();
                 let _ = self.broadcast.send(GeoGuessrServerEvent::GameEvent(
                     GeoGuessrGameEvent::GameSettingsUpdated {
                         settings: self.settings.lock().unwrap().clone(),
                     },
                 ));
             }
+            GeoGuessrUserGameEvent::PlaceMarker { lat, lng } => {
+                if self.lobby.lock().unwrap().status != LobbyStatus::Playing {
+                    return;
+                }
+                let placed = self.state.lock().unwrap().place_marker(player_id, lat, lng);
+                if let Err(message) = placed {
+                    self.send_to(
+                        &player_id,
+                        GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+                    );
+                }
+            }
+            GeoGuessrUserGameEvent::LockGuess => self.lock_guess(player_id),
+            GeoGuessrUserGameEvent::ReportBroken => self.report_broken(player_id),
             GeoGuessrUserGameEvent::Guess { lat, lng } => {
                 if self.lobby.lock().unwrap().status != LobbyStatus::Playing {
                     return;
                 }
+                let placed = self.state.lock().unwrap().place_marker(player_id, lat, lng);
+                match placed {
+                    Ok(()) => self.lock_guess(player_id),
+                    Err(message) => self.send_to(
+                        &player_id,
+                        GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+                    ),
+                }
+            }
+        }
+    }
 
-                let player_count = self.lobby.lock().unwrap().player_count();
-                let all_guessed = {
-                    let mut state = self.state.lock().unwrap();
-                    state.record_guess(player_id, lat, lng);
-
-                    // Check if all lobby members have now guessed
-                    state.all_players_guessed(player_count)
-                };
+    fn lock_guess(&self, player_id: Uuid) {
+        if self.lobby.lock().unwrap().status != LobbyStatus::Playing {
+            return;
+        }
 
-                if all_guessed {
-                    info!("All players guessed — ending round early");
+        let player_count = self.lobby.lock().unwrap().player_count();
+        let locked = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .lock_guess(player_id)
+                // Check if all lobby members have now locked in
+                .map(|_| state.all_players_locked(player_count))
+        };
+        match locked {
+            Ok(all_locked) => {
+                let _ = self.broadcast.send(GeoGuessrServerEvent::GameEvent(
+                    GeoGuessrGameEvent::GuessLocked { player_id },
+                ));
+                if all_locked {
+                    info!("All players locked in — ending round early");
                     self.round_notify.lock().unwrap().notify_one();
                 }
             }
+            Err(message) => self.send_to(
+                &player_id,
+                GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+            ),
+        }
+    }
+
+    fn report_broken(&self, player_id: Uuid) {
+        let reported = self.lobby.lock().unwrap().report_broken(player_id);
+        match reported {
+            Ok((votes, needed)) => self.apply_broken_reports(votes, needed),
+            Err(message) => self.send_to(
+                &player_id,
+                GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+            ),
+        }
+    }
+
+    /// Checks the round's reports again after a player leaves, as the rest may now be a majority
+    pub fn recount_broken_reports(&self) {
+        let reports = self.lobby.lock().unwrap().count_broken_reports();
+        if let Some((votes, needed)) = reports {
+            self.apply_broken_reports(votes, needed);
+        }
+    }
+
+    /// Skips the round once most of the lobby has reported it, and adds the lobby's report
+    /// to the denylist
+    fn apply_broken_reports(&self, votes: Vec<Uuid>, needed: usize) {
+        let skip = votes.len() >= needed;
+        let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+            LobbyServerEvent::BrokenReports { votes, needed },
+        ));
+        if !skip {
+            return;
         }
+        let location = self.state.lock().unwrap().get_current_location();
+        if let Some(location) = location {
+            info!(location=%location.image_id, "Location reported broken");
+            let label = format!(
+                "{} ({}, {})",
+                self.get_settings().map,
+                location.lat,
+                location.lng
+            );
+            report_item(
+                GameType::GeoGuessr,
+                location.image_id,
+                label,
+                self.lobby_code.clone(),
+            );
+        }
+        let _ = self.control.apply(ControlCommand::SkipRound);
     }
 
     pub fn handle_lobby_event(self: &Arc<Self>, player_id: Uuid, event: LobbyUserEvent) {
         match event {
             LobbyUserEvent::Ready => {
+                // Readying up on the results screen votes for a rematch
+                if self.get_lobby_status() == LobbyStatus::Finished {
+                    self.vote_rematch(player_id);
+                    return;
+                }
                 self.lobby.lock().unwrap().player_ready(&player_id);
                 let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
-                    LobbyServerEvent::PlayerReady {
-                        player_id: player_id.clone(),
-                    },
+                    LobbyServerEvent::PlayerReady { player_id },
                 ));
-                if self.lobby.lock().unwrap().all_ready()
-                    && self.lobby.lock().unwrap().status == LobbyStatus::Waiting
-                {
-                    let _ = self.broadcast.send(GeoGuessrServerEvent::GameEvent(
-                        GeoGuessrGameEvent::AllReady,
-                    ));
-                    let l = Arc::clone(self);
-                    tokio::spawn(async move {
-                        let res = l.load_locations().await;
-                        info!("Locations loaded: {:?}", l.state.lock().unwrap().locations);
-                        match res {
-                            Ok(_) => {
-                                l.update_lobby_status(LobbyStatus::Playing);
-                            }
-                            Err(e) => {
-                                l.update_lobby_status(LobbyStatus::Waiting);
-                                l.lobby.lock().unwrap().player_unready(&player_id);
-                                let _ = l.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
-                                    LobbyServerEvent::PlayerUnready { player_id },
-                                ));
-                                let _ = l.broadcast.send(GeoGuessrServerEvent::GameEvent(
-                                    GeoGuessrGameEvent::LoadingError {
-                                        message: format!("Failed to load locations: {}", e),
-                                    },
-                                ));
-                                return;
-                            }
-                        }
-                        GeoGuessr::run_game(l).await;
-                    });
-                }
+                self.try_start_game(player_id);
             }
             LobbyUserEvent::Unready => {
                 self.lobby.lock().unwrap().player_unready(&player_id);
                 let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
-                    LobbyServerEvent::PlayerUnready {
-                        player_id: player_id.clone(),
-                    },
+                    LobbyServerEvent::PlayerUnready { player_id },
                 ));
             }
-            _ => {
-                return;
+            LobbyUserEvent::JoinTeam { team } => {
+                let teams = self.get_settings().teams;
+                let result = self
+                    .lobby
+                    .lock()
+                    .unwrap()
+                    .join_team(player_id, team, teams.as_ref());
+                self.update_teams(player_id, result);
+            }
+            LobbyUserEvent::AutoBalanceTeams => {
+                let teams = self.get_settings().teams;
+                let result = self
+                    .lobby
+                    .lock()
+                    .unwrap()
+                    .auto_balance_teams(teams.as_ref());
+                self.update_teams(player_id, result);
+            }
+            LobbyUserEvent::Rematch => self.vote_rematch(player_id),
+            LobbyUserEvent::SetRematchOptions { options } => {
+                let result = self.lobby.lock().unwrap().set_rematch_options(options);
+                match result {
+                    Ok(()) => self.broadcast_rematch_votes(),
+                    Err(message) => self.send_to(
+                        &player_id,
+                        GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+                    ),
+                }
+            }
+            event => {
+                if let Some(command) = event.control_command() {
+                    self.control_game(player_id, command);
+                }
+            }
+        }
+    }
+
+    /// Applies a control, moving the round start on resume so the pause doesn't count
+    /// towards anyone's guess time
+    fn apply_control(&self, command: ControlCommand) -> Result<ControlOutcome, String> {
+        let outcome = self.control.apply(command)?;
+        if let ControlOutcome::Resumed { paused_for, .. } = outcome {
+            self.state.lock().unwrap().shift_round_start(paused_for);
+        }
+        Ok(outcome)
+    }
+
+    fn control_game(&self, player_id: Uuid, command: ControlCommand) {
+        match self.apply_control(command) {
+            Ok(outcome) => {
+                info!(?command, "Game control");
+                let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+                    LobbyServerEvent::from_control(player_id, outcome),
+                ));
             }
+            Err(message) => self.send_to(
+                &player_id,
+                GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+            ),
+        }
+    }
+
+    /// Drops the aborted game and its scores, back to the waiting room
+    fn abort(&self) {
+        info!("Game aborted");
+        self.state.lock().unwrap().reset();
+        self.lobby.lock().unwrap().abort_game();
+        let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+            LobbyServerEvent::UpdateLobbyStatus {
+                new_status: LobbyStatus::Waiting,
+            },
+        ));
+    }
+
+    /// Loads the locations and runs the game once everyone in the waiting room is ready
+    fn try_start_game(self: &Arc<Self>, player_id: Uuid) {
+        if self.try_begin_loading() {
+            let _ = self.broadcast.send(GeoGuessrServerEvent::GameEvent(
+                GeoGuessrGameEvent::AllReady,
+            ));
+            if let Some(teams) = self.prepare_teams() {
+                let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+                    LobbyServerEvent::TeamsUpdated { teams },
+                ));
+            }
+            let l = Arc::clone(self);
+            tokio::spawn(async move {
+                let res = l.load_locations().await;
+                info!("Locations loaded: {:?}", l.state.lock().unwrap().locations);
+                match res {
+                    Ok(_) => {
+                        l.update_lobby_status(LobbyStatus::Playing);
+                    }
+                    Err(e) => {
+                        l.update_lobby_status(LobbyStatus::Waiting);
+                        l.lobby.lock().unwrap().player_unready(&player_id);
+                        let _ = l.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+                            LobbyServerEvent::PlayerUnready { player_id },
+                        ));
+                        let _ = l.broadcast.send(GeoGuessrServerEvent::GameEvent(
+                            GeoGuessrGameEvent::LoadingError {
+                                message: format!("Failed to load locations: {}", e),
+                            },
+                        ));
+                        return;
+                    }
+                }
+                GeoGuessr::run_series(l).await;
+            });
+        }
+    }
+
+    /// Atomically moves a fully ready waiting room to `Loading`, returning whether the
+    /// game should start (so concurrent readies and rematches load it only once)
+    fn try_begin_loading(&self) -> bool {
+        let mut lobby = self.lobby.lock().unwrap();
+        if lobby.all_ready() && lobby.status == LobbyStatus::Waiting {
+            lobby.update_lobby_status(LobbyStatus::Loading);
+            return true;
+        }
+        false
+    }
+
+    fn vote_rematch(self: &Arc<Self>, player_id: Uuid) {
+        let voted = self.lobby.lock().unwrap().vote_rematch(player_id);
+        match voted {
+            Ok(rematch) => {
+                self.broadcast_rematch_votes();
+                if let Some(options) = rematch {
+                    self.start_rematch(player_id, options);
+                }
+            }
+            Err(message) => self.send_to(
+                &player_id,
+                GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::Error { message }),
+            ),
+        }
+    }
+
+    fn broadcast_rematch_votes(&self) {
+        let (votes, options) = {
+            let lobby = self.lobby.lock().unwrap();
+            (lobby.get_rematch_votes(), lobby.rematch_options.clone())
+        };
+        let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+            LobbyServerEvent::RematchVotes { votes, options },
+        ));
+    }
+
+    /// Clears the finished game once the lobby has left its results, then either starts
+    /// the next one straight away or stays in the waiting room, depending on the options
+    fn start_rematch(self: &Arc<Self>, player_id: Uuid, options: RematchOptions) {
+        info!(?options, "Starting rematch");
+        {
+            let mut state = self.state.lock().unwrap();
+            if options.new_seed {
+                state.reset();
+            } else {
+                state.rewind();
+            }
+        }
+        let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+            LobbyServerEvent::UpdateLobbyStatus {
+                new_status: LobbyStatus::Waiting,
+            },
+        ));
+        if options.same_settings {
+            self.try_start_game(player_id);
+        }
+    }
+
+    /// Every round played and the final standings, also stored in `RESULTS`
+    fn summarise(&self, settings: &GeoGuessrSettings) -> GameSummary {
+        let teams = self.get_teams();
+        let usernames: HashMap<Uuid, String> = self
+            .get_players()
+            .into_iter()
+            .map(|(id, username, _)| (id, username))
+            .collect();
+        let state = self.state.lock().unwrap();
+        let ranks = rank_scores(&state.scores);
+        let mut players: Vec<PlayerSummary> = state
+            .scores
+            .iter()
+            .map(|(player_id, score)| PlayerSummary {
+                player_id: *player_id,
+                username: usernames.get(player_id).cloned().unwrap_or_default(),
+                score: *score,
+                rank: ranks[player_id],
+                team: teams.get(player_id).copied(),
+            })
+            .collect();
+        players.sort_by(|a, b| a.rank.cmp(&b.rank).then(a.username.cmp(&b.username)));
+        GameSummary {
+            game_id: Uuid::new_v4(),
+            lobby_code: self.lobby_code.clone(),
+            map: settings.map.clone(),
+            mode: settings.mode,
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            players,
+            rounds: state.rounds.clone(),
+            team_leaderboard: settings.teams.as_ref().map(|_| state.team_scores.clone()),
         }
     }
 
@@ -242,7 +657,13 @@ impl GeoGuessr {
             GeoGuessrGameEvent::GameStart,
         ));
 
-        for _ in 0..settings.num_rounds {
+        let round_delay = Duration::from_secs(settings.round_delay_seconds as u64);
+        // Streak games carry on until everyone is out or the locations run out
+        let max_rounds = match settings.mode {
+            GeoGuessrMode::Classic => settings.num_rounds as usize,
+            GeoGuessrMode::CountryStreak => usize::MAX,
+        };
+        for _ in 0..max_rounds {
             if game.lobby.lock().unwrap().empty() {
                 info!("Game empty, terminating loop");
                 return;
@@ -254,9 +675,6 @@ impl GeoGuessr {
                     Some(s) => s,
                     None => {
                         info!("No locations left, ending game");
-                        let _ = game
-                            .broadcast
-                            .send(GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::GameEnd));
                         break;
                     }
                 }
@@ -266,29 +684,70 @@ impl GeoGuessr {
             *game.round_notify.lock().unwrap() = Arc::clone(&round_notify);
 
             game.state.lock().unwrap().begin_round();
+            game.lobby.lock().unwrap().open_broken_reports();
 
             info!(location=%location.image_id, "ROUNDSTART");
             let _ = game.broadcast.send(GeoGuessrServerEvent::GameEvent(
                 GeoGuessrGameEvent::RoundStart {
                     image_id: location.image_id.clone(),
+                    movement: settings.movement,
                 },
             ));
 
-            tokio::select! {
-                _ = sleep(Duration::from_secs(settings.round_length_seconds as u64)) => {
-                    info!("ROUNDEND (timeout)");
+            let ended = tokio::select! {
+                ended = game.control.wait(Duration::from_secs(settings.round_length_seconds as u64)) => {
+                    info!("ROUNDEND ({:?})", ended);
+                    ended
                 }
                 _ = round_notify.notified() => {
-                    info!("ROUNDEND (all players guessed)");
+                    info!("ROUNDEND (all players locked in)");
+                    Wait::Elapsed
                 }
+            };
+            game.lobby.lock().unwrap().close_broken_reports();
+            match ended {
+                Wait::Aborted => return,
+                // The round ends without anyone scoring
+                Wait::Skipped => {
+                    let _ = game.broadcast.send(GeoGuessrServerEvent::GameEvent(
+                        GeoGuessrGameEvent::RoundEnd {
+                            correct_lat: location.lat,
+                            correct_lng: location.lng,
+                            leaderboard: game.get_leaderboard(),
+                            team_leaderboard: game.get_team_leaderboard(),
+                            results: HashMap::new(),
+                        },
+                    ));
+                    if game.control.wait(round_delay).await == Wait::Aborted {
+                        return;
+                    }
+                    continue;
+                }
+                Wait::Elapsed => {}
             }
 
-            let round_results = {
+            let teams = game.get_teams();
+            let (round_results, everyone_out) = {
                 let mut state = game.state.lock().unwrap();
-                let player_ids: Vec<Uuid> = state.scores.keys().cloned().collect();
-                let results = state.build_round_results(&location, &player_ids); // no deadlock — same lock
-                state.calculate_and_apply_scores(&location, &player_ids);
-                results
+                let player_ids: Vec<Uuid> = state
+                    .scores
+                    .keys()
+                    .filter(|id| !state.eliminated.contains(id))
+                    .cloned()
+                    .collect();
+                let results = state.build_round_results(&location, &player_ids, &settings);
+                let round_points = state.apply_round_results(&location, &results);
+                if let Some(team_settings) = &settings.teams {
+                    apply_team_round(
+                        &mut state.team_scores,
+                        &teams,
+                        &round_points,
+                        team_settings.aggregation,
+                    );
+                }
+                let everyone_out = settings.mode == GeoGuessrMode::CountryStreak
+                    && state.scores.keys().all(|id| state.eliminated.contains(id));
+                (results, everyone_out)
             };
 
             let _ = game.broadcast.send(GeoGuessrServerEvent::GameEvent(
@@ -296,41 +755,120 @@ impl GeoGuessr {
                     correct_lat: location.lat,
                     correct_lng: location.lng,
                     leaderboard: game.get_leaderboard(),
+                    team_leaderboard: game.get_team_leaderboard(),
                     results: round_results,
                 },
             ));
 
             info!("{:?}", settings.round_delay_seconds);
-            sleep(Duration::from_secs(settings.round_delay_seconds as u64)).await;
+            if game.control.wait(round_delay).await == Wait::Aborted {
+                return;
+            }
+            if everyone_out {
+                info!("Every player's streak has ended");
+                break;
+            }
         }
 
         info!("GAME END");
-        if (settings.round_delay_seconds as u64) < 3 {
-            sleep(Duration::from_secs(3 - settings.round_delay_seconds as u64)).await;
+        if (settings.round_delay_seconds as u64) < 3
+            && game
+                .control
+                .wait(Duration::from_secs(3 - settings.round_delay_seconds as u64))
+                .await
+                == Wait::Aborted
+        {
+            return;
+        }
+        let summary = game.summarise(&settings);
+        if !game.stage {
+            RESULTS.insert(summary.game_id, summary.clone());
+            info!(game_id=%summary.game_id, "Saved game summary");
+            record_game(summary.to_record(&settings));
+        }
+        let _ = game.broadcast.send(GeoGuessrServerEvent::GameEvent(
+            GeoGuessrGameEvent::GameEnd {
+                leaderboard: game.get_leaderboard(),
+                team_leaderboard: game.get_team_leaderboard(),
+                summary: Box::new(summary),
+            },
+        ));
+    }
+
+    /// Runs games back to back until the lobby's series is over, or just the one game
+    /// without a series. Aborting any of them goes back to the waiting room.
+    pub async fn run_series(game: Arc<GeoGuessr>) {
+        game.control.start();
+        GeoGuessr::play_series(&game).await;
+        if game.control.is_aborted() {
+            game.abort();
+        }
+        game.control.stop();
+    }
+
+    async fn play_series(game: &Arc<GeoGuessr>) {
+        loop {
+            GeoGuessr::run_game(Arc::clone(game)).await;
+            if game.control.is_aborted() || game.lobby.lock().unwrap().empty() || !game.finish() {
+                return;
+            }
+            info!("Next game of the series in {}s", SERIES_BREAK_SECONDS);
+            let wait = game
+                .control
+                .wait(Duration::from_secs(SERIES_BREAK_SECONDS))
+                .await;
+            if wait == Wait::Aborted || game.lobby.lock().unwrap().empty() {
+                return;
+            }
+            game.state.lock().unwrap().reset();
+            // Each game announces its teams, like the first one does in `try_start_game`
+            if let Some(teams) = game.prepare_teams() {
+                let _ = game.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+                    LobbyServerEvent::TeamsUpdated { teams },
+                ));
+            }
+            if let Err(e) = game.load_locations().await {
+                game.lobby.lock().unwrap().show_results();
+                let _ = game.broadcast.send(GeoGuessrServerEvent::GameEvent(
+                    GeoGuessrGameEvent::LoadingError {
+                        message: format!("Failed to load locations: {}", e),
+                    },
+                ));
+                let _ = game.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
+                    LobbyServerEvent::UpdateLobbyStatus {
+                        new_status: LobbyStatus::Finished,
+                    },
+                ));
+                return;
+            }
         }
-        let _ = game
-            .broadcast
-            .send(GeoGuessrServerEvent::GameEvent(GeoGuessrGameEvent::GameEnd));
-        game.reset();
     }
 }
 
 impl ConnectionManager for GeoGuessr {
-    fn connection_drop(&self, player_id: Uuid) {
+    fn connection_drop(self: &Arc<Self>, player_id: Uuid, connection_id: Uuid, _: &AppState) {
         self.lobby.lock().unwrap().player_leave(&player_id);
-        self.state.lock().unwrap().scores.remove(&player_id);
+        {
+            let mut state = self.state.lock().unwrap();
+            state.scores.remove(&player_id);
+            state.eliminated.remove(&player_id);
+        }
+        self.direct.remove(&player_id, connection_id);
         info!(
             "Player {} disconnected from lobby: {}",
             player_id, self.lobby_code
         );
         let _ = self.broadcast.send(GeoGuessrServerEvent::LobbyEvent(
-            LobbyServerEvent::PlayerLeave {
-                player_id: player_id.clone(),
-            },
+            LobbyServerEvent::PlayerLeave { player_id },
         ));
+        self.recount_broken_reports();
+        let rematch = self.lobby.lock().unwrap().try_start_rematch();
+        if let Some((starter, options)) = rematch {
+            self.start_rematch(starter, options);
+        }
     }
     fn lobby_code(&self) -> String {
-        return self.lobby_code.clone();
+        self.lobby_code.clone()
     }
     fn no_connections(&self) -> bool {
         self.lobby.lock().unwrap().empty()
@@ -349,8 +887,114 @@ pub(crate) struct GeoGuessrSettings {
     pub map: String,
     pub map_center: (f32, f32),
     pub zoom: u8,
+    #[serde(default)]
+    pub teams: Option<TeamSettings>,
+    #[serde(default)]
+    pub series: Option<SeriesSettings>,
+    #[serde(default)]
+    pub scoring: ScoringOptions,
+    #[serde(default)]
+    pub mode: GeoGuessrMode,
+    #[serde(default)]
+    pub movement: Movement,
+    // Setting a preset overrides the fields it bundles. Updated settings report the preset
+    // they still match, if any.
+    #[serde(default)]
+    pub preset: Option<GeoGuessrPreset>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Movement {
+    #[default]
+    Moving,
+    // Can look around but not move
+    NoMove,
+    // No moving, panning or zooming
+    Nmpz,
+}
+
+/// Named bundles of round length, movement and scoring
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum GeoGuessrPreset {
+    Casual,
+    NoMove,
+    Nmpz,
+    Competitive,
+    Blitz,
+}
+
+impl GeoGuessrPreset {
+    const ALL: [GeoGuessrPreset; 5] = [
+        GeoGuessrPreset::Casual,
+        GeoGuessrPreset::NoMove,
+        GeoGuessrPreset::Nmpz,
+        GeoGuessrPreset::Competitive,
+        GeoGuessrPreset::Blitz,
+    ];
+
+    /// (round length, movement, scoring)
+    fn values(&self) -> (u8, Movement, ScoringOptions) {
+        let no_bonuses = ScoringOptions::default();
+        match self {
+            GeoGuessrPreset::Casual => (120, Movement::Moving, no_bonuses),
+            GeoGuessrPreset::NoMove => (60, Movement::NoMove, no_bonuses),
+            GeoGuessrPreset::Nmpz => (30, Movement::Nmpz, no_bonuses),
+            GeoGuessrPreset::Competitive => (
+                60,
+                Movement::NoMove,
+                ScoringOptions {
+                    country_bonus: true,
+                    streak_bonus: true,
+                    ..no_bonuses
+                },
+            ),
+            GeoGuessrPreset::Blitz => (
+                10,
+                Movement::Nmpz,
+                ScoringOptions {
+                    time_bonus: true,
+                    streak_bonus: true,
+                    ..no_bonuses
+                },
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum GeoGuessrMode {
+    // Scored by distance for a fixed number of rounds
+    #[default]
+    Classic,
+    // A point per round for guessing inside the right country, until a player gets one wrong
+    CountryStreak,
+}
+
+/// Bonuses awarded on top of the distance score
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct ScoringOptions {
+    // Guessing inside the right country
+    pub country_bonus: bool,
+    // Guessing quickly, scaled by how good the guess was
+    pub time_bonus: bool,
+    // Consecutive rounds with a high distance score
+    pub streak_bonus: bool,
+}
+
+pub(crate) const MAX_ROUND_POINTS: u32 = 5000;
+const COUNTRY_BONUS_POINTS: u32 = 1000;
+const TIME_BONUS_MAX_POINTS: u32 = 1000;
+const STREAK_THRESHOLD_POINTS: u32 = 4000;
+const STREAK_BONUS_STEP: u32 = 250;
+const STREAK_BONUS_MAX: u32 = 1000;
+const STREAK_MAX_ROUNDS: usize = 100;
+const MAX_ROUNDS: u8 = 50;
+const MIN_ROUND_LENGTH_SECONDS: u8 = 10;
+
 impl GeoGuessrSettings {
     pub fn new() -> Self {
         Self {
@@ -360,18 +1004,76 @@ impl GeoGuessrSettings {
             map: "World".to_string(),
             map_center: (0.0, 0.0),
             zoom: 3,
+            teams: None,
+            series: None,
+            scoring: ScoringOptions::default(),
+            mode: GeoGuessrMode::Classic,
+            movement: Movement::Moving,
+            preset: None,
         }
     }
 
-    pub fn update_game_settings(&mut self, settings: GeoGuessrSettings) {
+    /// Applies new settings, taking the map's center and zoom from the map itself.
+    /// Settings naming a map that doesn't exist are rejected.
+    pub fn update_game_settings(&mut self, mut settings: GeoGuessrSettings) -> Result<(), String> {
+        let map = MAPS
+            .get(&settings.map)
+            .ok_or_else(|| format!("Unknown map: {}", settings.map))?;
+        if settings.mode == GeoGuessrMode::CountryStreak && !countries_loaded() {
+            return Err(
+                "Country streak needs country borders, which this server doesn't have".to_string(),
+            );
+        }
+        settings.validate(map.playable().count())?;
         self.num_rounds = settings.num_rounds;
         self.round_length_seconds = settings.round_length_seconds;
         self.round_delay_seconds = settings.round_delay_seconds;
         self.map = settings.map.clone();
-        if let Some(map) = MAPS.get(&settings.map) {
-            self.map_center = map.center;
-            self.zoom = map.zoom;
+        self.teams = settings.teams;
+        self.series = settings.series;
+        self.scoring = settings.scoring;
+        self.mode = settings.mode;
+        self.movement = settings.movement;
+        if let Some(preset) = settings.preset {
+            (self.round_length_seconds, self.movement, self.scoring) = preset.values();
         }
+        self.preset = self.active_preset();
+        self.map_center = map.center;
+        self.zoom = map.zoom;
+        Ok(())
+    }
+
+    /// Rejects settings with nothing to play and clamps the rest into range.
+    /// Classic games can't have more rounds than the map has locations.
+    pub fn validate(&mut self, location_count: usize) -> Result<(), String> {
+        require_nonzero(self.num_rounds, "Number of rounds")?;
+        require_nonzero(self.round_length_seconds, "Round length")?;
+        if location_count == 0 {
+            return Err(format!("{} has no locations", self.map));
+        }
+        let max_rounds = MAX_ROUNDS.min(location_count.try_into().unwrap_or(u8::MAX));
+        self.num_rounds = self.num_rounds.min(max_rounds);
+        self.round_length_seconds = self.round_length_seconds.max(MIN_ROUND_LENGTH_SECONDS);
+        self.round_delay_seconds = self.round_delay_seconds.min(MAX_ROUND_DELAY_SECONDS);
+        if let Some(teams) = &mut self.teams {
+            teams.count = teams.count.clamp(2, MAX_TEAMS);
+        }
+        if let Some(series) = &mut self.series {
+            series.validate()?;
+        }
+        Ok(())
+    }
+
+    /// The preset these settings match, if they haven't been customised
+    pub fn active_preset(&self) -> Option<GeoGuessrPreset> {
+        GeoGuessrPreset::ALL.into_iter().find(|preset| {
+            preset.values()
+                == (
+                    self.round_length_seconds,
+                    self.movement,
+                    self.scoring.clone(),
+                )
+        })
     }
 }
 
@@ -380,12 +1082,26 @@ impl GeoGuessrSettings {
 /// ===============================================
 pub(crate) struct GeoGuessrState {
     pub scores: HashMap<Uuid, u32>,
-    // Current round's guesses: player_id -> (lat, lng)
+    // Current round's markers: player_id -> (lat, lng). Provisional until locked in,
+    // but still scored at the end of the round.
     pub current_round_guesses: HashMap<Uuid, (f32, f32)>,
-    // All rounds' guesses (accumulated for history/replay if needed)
-    pub guesses: Vec<HashMap<Uuid, (f32, f32)>>,
+    pub locked_guesses: HashSet<Uuid>,
+    // Every finished round's location and results, for the end of game summary
+    pub rounds: Vec<RoundSummary>,
     pub locations: Vec<Location>,
     pub location_index: usize,
+    pub team_scores: TeamLeaderboard,
+    // Map name -> image ids already played in this lobby, kept across games to avoid repeats
+    pub seen_locations: HashMap<String, HashSet<String>>,
+    pub score_scale_km: f64,
+    pub round_started_at: Option<Instant>,
+    // How many seconds into the round each player locked in, or last placed their marker
+    pub guess_times: HashMap<Uuid, f64>,
+    // Consecutive rounds each player has scored at least `STREAK_THRESHOLD_POINTS`,
+    // or got the country right in country streak games
+    pub streaks: HashMap<Uuid, u32>,
+    // Players whose country streak has ended this game
+    pub eliminated: HashSet<Uuid>,
 }
 
 impl GeoGuessrState {
@@ -393,72 +1109,196 @@ impl GeoGuessrState {
         GeoGuessrState {
             scores: HashMap::new(),
             current_round_guesses: HashMap::new(),
-            guesses: Vec::new(),
+            locked_guesses: HashSet::new(),
+            rounds: Vec::new(),
             locations: Vec::new(),
             location_index: 0,
+            team_scores: HashMap::new(),
+            seen_locations: HashMap::new(),
+            score_scale_km: DEFAULT_SCORE_SCALE_KM,
+            round_started_at: None,
+            guess_times: HashMap::new(),
+            streaks: HashMap::new(),
+            eliminated: HashSet::new(),
         }
     }
 
     pub fn reset(&mut self) {
+        self.locations = Vec::new();
+        self.score_scale_km = DEFAULT_SCORE_SCALE_KM;
+        self.rewind();
+    }
+
+    /// Clears the last game's progress but keeps its locations, to play them again
+    pub fn rewind(&mut self) {
         self.scores.iter_mut().for_each(|(_, score)| *score = 0);
         self.location_index = 0;
-        self.locations = Vec::new();
         self.current_round_guesses.clear();
-        self.guesses.clear();
+        self.locked_guesses.clear();
+        self.rounds.clear();
+        self.team_scores.clear();
+        self.round_started_at = None;
+        self.guess_times.clear();
+        self.streaks.clear();
+        self.eliminated.clear();
     }
 
     pub fn begin_round(&mut self) {
         self.current_round_guesses.clear();
+        self.locked_guesses.clear();
+        self.guess_times.clear();
+        self.round_started_at = Some(Instant::now());
+    }
+
+    /// Places or moves the player's marker for the current round
+    pub fn place_marker(&mut self, player_id: Uuid, lat: f32, lng: f32) -> Result<(), String> {
+        validate_coordinates(lat, lng)?;
+        if self.eliminated.contains(&player_id) {
+            return Err("Your streak has ended".to_string());
+        }
+        if self.locked_guesses.contains(&player_id) {
+            return Err("Guess already locked in".to_string());
+        }
+        self.current_round_guesses.insert(player_id, (lat, lng));
+        self.record_guess_time(player_id);
+        Ok(())
+    }
+
+    /// Finalises the player's marker
+    pub fn lock_guess(&mut self, player_id: Uuid) -> Result<(), String> {
+        if self.locked_guesses.contains(&player_id) {
+            return Err("Guess already locked in".to_string());
+        }
+        if !self.current_round_guesses.contains_key(&player_id) {
+            return Err("Place a marker before locking in".to_string());
+        }
+        self.locked_guesses.insert(player_id);
+        self.record_guess_time(player_id);
+        Ok(())
+    }
+
+    pub fn shift_round_start(&mut self, by: Duration) {
+        if let Some(started) = &mut self.round_started_at {
+            *started += by;
+        }
     }
 
-    pub fn record_guess(&mut self, player_id: Uuid, lat: f32, lng: f32) {
-        self.current_round_guesses
-            .entry(player_id)
-            .or_insert((lat, lng));
+    fn record_guess_time(&mut self, player_id: Uuid) {
+        let elapsed = self
+            .round_started_at
+            .map(|t| t.elapsed().as_secs_f64())
+            .unwrap_or_default();
+        self.guess_times.insert(player_id, elapsed);
     }
 
-    pub fn all_players_guessed(&self, player_count: usize) -> bool {
-        player_count > 0 && self.current_round_guesses.len() >= player_count
+    pub fn all_players_locked(&self, player_count: usize) -> bool {
+        let active = player_count.saturating_sub(self.eliminated.len());
+        active > 0 && self.locked_guesses.len() >= active
     }
 
-    /// Scores all players for the current round using Haversine distance,
-    /// then saves the round's guesses into history.
-    pub fn calculate_and_apply_scores(&mut self, correct: &Location, player_ids: &[Uuid]) {
-        for player_id in player_ids {
-            let points = match self.current_round_guesses.get(player_id) {
-                Some(&(lat, lng)) => haversine_score(lat, lng, correct.lat, correct.lng),
-                // No guess submitted — 0 points
-                None => 0,
-            };
-            self.increment_player_score(player_id, points);
+    /// Applies a round's results to the scores and streaks, then saves the round into
+    /// history. Returns each player's points for the round.
+    pub fn apply_round_results(
+        &mut self,
+        location: &Location,
+        results: &HashMap<Uuid, PlayerRoundResult>,
+    ) -> HashMap<Uuid, u32> {
+        let mut round_points = HashMap::new();
+        for (player_id, result) in results {
+            self.increment_player_score(player_id, result.points_gained);
+            self.streaks.insert(*player_id, result.streak);
+            if result.eliminated {
+                self.eliminated.insert(*player_id);
+            }
+            round_points.insert(*player_id, result.points_gained);
         }
-        self.guesses.push(self.current_round_guesses.clone());
+        self.rounds.push(RoundSummary {
+            location: location.clone(),
+            results: results.clone(),
+        });
+        round_points
     }
 
+    /// Scores each player's guess by Haversine distance, decaying over the map's scale,
+    /// plus whichever bonuses are enabled. Country streak games only score the country.
     pub fn build_round_results(
         &self,
         correct: &Location,
         player_ids: &[Uuid],
+        settings: &GeoGuessrSettings,
     ) -> HashMap<Uuid, PlayerRoundResult> {
+        let scoring = &settings.scoring;
+        let country_streak = settings.mode == GeoGuessrMode::CountryStreak;
+        let actual_country = (scoring.country_bonus || country_streak)
+            .then(|| country_at(correct.lat, correct.lng))
+            .flatten();
         player_ids
             .iter()
             .map(|id| {
-                let result = match self.current_round_guesses.get(id) {
-                    Some(&(lat, lng)) => {
-                        let distance_km = haversine_km(lat, lng, correct.lat, correct.lng) as f32;
-                        let points_gained = haversine_score(lat, lng, correct.lat, correct.lng);
+                let Some(&(lat, lng)) = self.current_round_guesses.get(id) else {
+                    // No guess submitted — 0 points, and the end of a country streak
+                    return (
+                        *id,
                         PlayerRoundResult {
-                            guess: Some((lat, lng)),
-                            distance_km: Some(distance_km),
-                            points_gained,
+                            actual_country: actual_country.map(|c| c.to_ref()),
+                            eliminated: country_streak,
+                            ..Default::default()
+                        },
+                    );
+                };
+                let distance_km = haversine_km(lat, lng, correct.lat, correct.lng);
+                let guessed_country = actual_country.and_then(|_| country_at(lat, lng));
+                let country_correct = actual_country.map(|actual| {
+                    guessed_country.is_some_and(|guessed| guessed.code == actual.code)
+                });
+                let mut result = PlayerRoundResult {
+                    guess: Some((lat, lng)),
+                    locked: self.locked_guesses.contains(id),
+                    distance_km: Some(distance_km as f32),
+                    country_correct,
+                    guessed_country: guessed_country.map(|c| c.to_ref()),
+                    actual_country: actual_country.map(|c| c.to_ref()),
+                    ..Default::default()
+                };
+                let previous_streak = self.streaks.get(id).copied().unwrap_or(0);
+
+                if country_streak {
+                    match country_correct {
+                        Some(true) => {
+                            result.points_gained = 1;
+                            result.streak = previous_streak + 1;
                         }
+                        Some(false) => result.eliminated = true,
+                        // The location isn't in any known country, so the round doesn't count
+                        None => result.streak = previous_streak,
                     }
-                    None => PlayerRoundResult {
-                        guess: None,
-                        distance_km: None,
-                        points_gained: 0,
-                    },
-                };
+                    return (*id, result);
+                }
+
+                result.distance_points = haversine_score(distance_km, self.score_scale_km);
+                if country_correct == Some(true) {
+                    result.country_bonus = COUNTRY_BONUS_POINTS;
+                }
+                if scoring.time_bonus
+                    && let Some(elapsed) = self.guess_times.get(id)
+                {
+                    let round_length = (settings.round_length_seconds as f64).max(1.0);
+                    let remaining = (1.0 - elapsed / round_length).clamp(0.0, 1.0);
+                    let accuracy = result.distance_points as f64 / MAX_ROUND_POINTS as f64;
+                    result.time_bonus =
+                        (TIME_BONUS_MAX_POINTS as f64 * remaining * accuracy).round() as u32;
+                }
+                if result.distance_points >= STREAK_THRESHOLD_POINTS {
+                    result.streak = previous_streak + 1;
+                }
+                if scoring.streak_bonus {
+                    result.streak_bonus =
+                        (STREAK_BONUS_STEP * result.streak.saturating_sub(1)).min(STREAK_BONUS_MAX);
+                }
+                result.points_gained = result.distance_points
+                    + result.country_bonus
+                    + result.time_bonus
+                    + result.streak_bonus;
                 (*id, result)
             })
             .collect()
@@ -467,7 +1307,7 @@ impl GeoGuessrState {
     pub fn get_current_location(&self) -> Option<Location> {
         if self.locations.is_empty()
             || self.location_index == 0
-            || self.location_index - 1 >= self.locations.len()
+            || self.location_index > self.locations.len()
         {
             return None;
         }
@@ -490,7 +1330,7 @@ impl GeoGuessrState {
     }
 }
 
-fn haversine_km(lat1: f32, lng1: f32, lat2: f32, lng2: f32) -> f64 {
+pub(crate) fn haversine_km(lat1: f32, lng1: f32, lat2: f32, lng2: f32) -> f64 {
     const EARTH_RADIUS_KM: f64 = 6371.0;
     let (lat1, lng1, lat2, lng2) = (lat1 as f64, lng1 as f64, lat2 as f64, lng2 as f64);
     let d_lat = (lat2 - lat1).to_radians();
@@ -501,9 +1341,10 @@ fn haversine_km(lat1: f32, lng1: f32, lat2: f32, lng2: f32) -> f64 {
     EARTH_RADIUS_KM * c
 }
 
-fn haversine_score(lat1: f32, lng1: f32, lat2: f32, lng2: f32) -> u32 {
-    let distance_km = haversine_km(lat1, lng1, lat2, lng2);
-    let score = 5000.0 * (-distance_km / 2000.0_f64).exp();
+const DEFAULT_SCORE_SCALE_KM: f64 = 2000.0;
+
+fn haversine_score(distance_km: f64, scale_km: f64) -> u32 {
+    let score = MAX_ROUND_POINTS as f64 * (-distance_km / scale_km).exp();
     score.round() as u32
 }
 
@@ -518,6 +1359,9 @@ pub(crate) enum GeoGuessrGameEvent {
         settings: GeoGuessrSettings,
         leaderboard: HashMap<Uuid, u32>,
         status: LobbyStatus,
+        preset: Option<GeoGuessrPreset>,
+        teams: HashMap<Uuid, u8>,
+        team_leaderboard: Option<TeamLeaderboard>,
     },
     AllReady,
     GameStart,
@@ -526,22 +1370,36 @@ pub(crate) enum GeoGuessrGameEvent {
     },
     RoundStart {
         image_id: String,
+        // Enforced by the client's street view
+        movement: Movement,
     },
     RoundEnd {
         correct_lat: f32,
         correct_lng: f32,
         leaderboard: HashMap<Uuid, u32>,
+        team_leaderboard: Option<TeamLeaderboard>,
         results: HashMap<Uuid, PlayerRoundResult>,
     },
-    GameEnd,
+    GameEnd {
+        leaderboard: HashMap<Uuid, u32>,
+        team_leaderboard: Option<TeamLeaderboard>,
+        summary: Box<GameSummary>,
+    },
     PlayerGuess {
         player_id: Uuid,
         lat: f32,
         lng: f32,
     },
+    // Marker positions stay hidden until the round ends
+    GuessLocked {
+        player_id: Uuid,
+    },
     LoadingError {
         message: String,
     },
+    Error {
+        message: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -558,7 +1416,12 @@ pub(crate) enum GeoGuessrServerEvent {
 #[serde(tag = "event")]
 pub(crate) enum GeoGuessrUserGameEvent {
     UpdateGameSettings { settings: GeoGuessrSettings },
+    PlaceMarker { lat: f32, lng: f32 },
+    LockGuess,
+    // Places and locks a marker in one go
     Guess { lat: f32, lng: f32 },
+    // The location won't load, a majority of reports skips the round
+    ReportBroken,
 }
 
 #[derive(Deserialize, Debug)]
@@ -568,10 +1431,93 @@ pub(crate) enum GeoGuesserClientEvent {
     GameEvent(GeoGuessrUserGameEvent),
 }
 
+impl GameSummary {
+    /// The summary as it's kept in the game history, under the same game id
+    fn to_record(&self, settings: &GeoGuessrSettings) -> GameRecord {
+        let mut record = GameRecord::new(GameType::GeoGuessr, &self.lobby_code, settings);
+        record.game_id = self.game_id;
+        record.finished_at = self.finished_at;
+        record.players = self
+            .players
+            .iter()
+            .map(|p| PlayerRecord {
+                player_id: p.player_id,
+                username: p.username.clone(),
+                score: p.score,
+                rank: p.rank,
+            })
+            .collect();
+        record.rounds = self
+            .rounds
+            .iter()
+            .map(|round| RoundRecord {
+                details: serde_json::to_value(&round.location).unwrap_or_default(),
+                results: round
+                    .results
+                    .iter()
+                    .map(|(player_id, result)| PlayerRoundRecord {
+                        player_id: *player_id,
+                        points: result.points_gained,
+                        distance_km: result.distance_km.map(f64::from),
+                        correct: result.country_correct,
+                        details: serde_json::to_value(result).ok(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        record
+    }
+
+    /// Rebuilds a summary from the game history, once it's dropped out of `RESULTS`.
+    /// Teams aren't part of the history, so they're left out.
+    pub fn from_record(record: GameRecord) -> Option<Self> {
+        if record.game_type != GameType::GeoGuessr {
+            return None;
+        }
+        let settings: GeoGuessrSettings = serde_json::from_value(record.settings).ok()?;
+        Some(GameSummary {
+            game_id: record.game_id,
+            lobby_code: record.lobby_code,
+            map: settings.map,
+            mode: settings.mode,
+            finished_at: record.finished_at,
+            players: record
+                .players
+                .into_iter()
+                .map(|p| PlayerSummary {
+                    player_id: p.player_id,
+                    username: p.username,
+                    score: p.score,
+                    rank: p.rank,
+                    team: None,
+                })
+                .collect(),
+            rounds: record
+                .rounds
+                .into_iter()
+                .map(|round| {
+                    Some(RoundSummary {
+                        location: serde_json::from_value(round.details).ok()?,
+                        results: round
+                            .results
+                            .into_iter()
+                            .map(|r| {
+                                let result = serde_json::from_value(r.details?).ok()?;
+                                Some((r.player_id, result))
+                            })
+                            .collect::<Option<_>>()?,
+                    })
+                })
+                .collect::<Option<_>>()?,
+            team_leaderboard: None,
+        })
+    }
+}
+
 /// ===============================================
 /// Helper Structs
 /// ===============================================
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Location {
     pub image_id: String,
@@ -579,10 +1525,223 @@ pub(crate) struct Location {
     pub lng: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct PlayerRoundResult {
     pub guess: Option<(f32, f32)>,
+    // False if the round ended on the player's unlocked marker
+    pub locked: bool,
     pub distance_km: Option<f32>,
+    // Total for the round, distance score plus bonuses
     pub points_gained: u32,
+    pub distance_points: u32,
+    // Country fields are only set when the country bonus is on or in country streak games
+    pub country_correct: Option<bool>,
+    pub guessed_country: Option<CountryRef>,
+    pub actual_country: Option<CountryRef>,
+    pub country_bonus: u32,
+    pub time_bonus: u32,
+    pub streak: u32,
+    pub streak_bonus: u32,
+    // The player's country streak ended this round
+    pub eliminated: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::MAX_SERIES_GAMES;
+
+    fn settings() -> GeoGuessrSettings {
+        GeoGuessrSettings::new()
+    }
+
+    #[test]
+    fn rejects_zero_rounds() {
+        let mut s = GeoGuessrSettings {
+            num_rounds: 0,
+            ..settings()
+        };
+        assert!(s.validate(100).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_round_length() {
+        let mut s = GeoGuessrSettings {
+            round_length_seconds: 0,
+            ..settings()
+        };
+        assert!(s.validate(100).is_err());
+    }
+
+    #[test]
+    fn rejects_maps_without_locations() {
+        assert!(settings().validate(0).is_err());
+    }
+
+    #[test]
+    fn clamps_rounds_to_location_count() {
+        let mut s = GeoGuessrSettings {
+            num_rounds: 20,
+            ..settings()
+        };
+        s.validate(3).unwrap();
+        assert_eq!(s.num_rounds, 3);
+
+        let mut s = GeoGuessrSettings {
+            num_rounds: 200,
+            ..settings()
+        };
+        s.validate(10_000).unwrap();
+        assert_eq!(s.num_rounds, MAX_ROUNDS);
+    }
+
+    #[test]
+    fn clamps_timings() {
+        let mut s = GeoGuessrSettings {
+            round_length_seconds: 1,
+            round_delay_seconds: 200,
+            ..settings()
+        };
+        s.validate(100).unwrap();
+        assert_eq!(s.round_length_seconds, MIN_ROUND_LENGTH_SECONDS);
+        assert_eq!(s.round_delay_seconds, MAX_ROUND_DELAY_SECONDS);
+    }
+
+    #[test]
+    fn clamps_team_count() {
+        let mut s = GeoGuessrSettings {
+            teams: Some(TeamSettings {
+                count: 50,
+                aggregation: Default::default(),
+            }),
+            ..settings()
+        };
+        s.validate(100).unwrap();
+        assert_eq!(s.teams.unwrap().count, MAX_TEAMS);
+    }
+
+    #[test]
+    fn validates_series_length() {
+        let series = |num_games| SeriesSettings {
+            num_games,
+            scoring: Default::default(),
+        };
+        let mut s = GeoGuessrSettings {
+            series: Some(series(0)),
+            ..settings()
+        };
+        assert!(s.validate(100).is_err());
+        s.series = Some(series(50));
+        s.validate(100).unwrap();
+        assert_eq!(s.series.unwrap().num_games, MAX_SERIES_GAMES);
+    }
+
+    #[test]
+    fn leaves_valid_settings_alone() {
+        let mut s = settings();
+        s.validate(100).unwrap();
+        assert_eq!(s.num_rounds, 10);
+        assert_eq!(s.round_length_seconds, 30);
+        assert_eq!(s.round_delay_seconds, 5);
+    }
+
+    #[test]
+    fn presets_pass_validation() {
+        for preset in GeoGuessrPreset::ALL {
+            let mut s = GeoGuessrSettings {
+                preset: Some(preset),
+                ..settings()
+            };
+            (s.round_length_seconds, s.movement, s.scoring) = preset.values();
+            s.validate(100).unwrap();
+            assert_eq!(s.active_preset(), Some(preset));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_maps() {
+        let update = GeoGuessrSettings {
+            map: "Nowhere".to_string(),
+            ..settings()
+        };
+        assert!(settings().update_game_settings(update).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_guesses() {
+        let mut state = GeoGuessrState::new();
+        let player = Uuid::new_v4();
+        assert!(state.place_marker(player, f32::NAN, 0.0).is_err());
+        assert!(state.place_marker(player, 0.0, f32::INFINITY).is_err());
+        assert!(state.place_marker(player, 91.0, 0.0).is_err());
+        assert!(state.place_marker(player, 0.0, -181.0).is_err());
+        assert!(state.current_round_guesses.is_empty());
+        assert!(state.place_marker(player, -33.87, 151.21).is_ok());
+    }
+
+    #[test]
+    fn rebuilds_summaries_from_the_history() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let result = |points_gained| PlayerRoundResult {
+            guess: Some((48.85, 2.35)),
+            locked: true,
+            distance_km: Some(12.5),
+            points_gained,
+            distance_points: points_gained,
+            ..Default::default()
+        };
+        let player = |player_id, username: &str, score, rank| PlayerSummary {
+            player_id,
+            username: username.to_string(),
+            score,
+            rank,
+            team: Some(0),
+        };
+        let summary = GameSummary {
+            game_id: Uuid::new_v4(),
+            lobby_code: "ABCD".to_string(),
+            map: settings().map,
+            mode: GeoGuessrMode::Classic,
+            finished_at: 1_700_000_000,
+            players: vec![player(alice, "alice", 4000, 1), player(bob, "bob", 3000, 2)],
+            rounds: vec![RoundSummary {
+                location: Location {
+                    image_id: "pano".to_string(),
+                    lat: 48.86,
+                    lng: 2.34,
+                },
+                results: HashMap::from([(alice, result(4000)), (bob, result(3000))]),
+            }],
+            team_leaderboard: Some(HashMap::from([(0, 7000)])),
+        };
+
+        let rebuilt = GameSummary::from_record(summary.to_record(&settings())).unwrap();
+        assert_eq!(rebuilt.game_id, summary.game_id);
+        assert_eq!(rebuilt.map, summary.map);
+        assert_eq!(rebuilt.finished_at, summary.finished_at);
+        let players: Vec<_> = rebuilt
+            .players
+            .iter()
+            .map(|p| (p.player_id, p.rank))
+            .collect();
+        assert_eq!(players, vec![(alice, 1), (bob, 2)]);
+        assert_eq!(rebuilt.rounds[0].location.image_id, "pano");
+        assert_eq!(rebuilt.rounds[0].results[&bob].points_gained, 3000);
+        // Teams aren't kept in the history
+        assert!(rebuilt.team_leaderboard.is_none());
+    }
+
+    #[test]
+    fn pauses_dont_count_towards_guess_times() {
+        let mut state = GeoGuessrState::new();
+        let player = Uuid::new_v4();
+        state.begin_round();
+        state.round_started_at = Some(Instant::now() - Duration::from_secs(20));
+        // Paused for 15 of those 20 seconds
+        state.shift_round_start(Duration::from_secs(15));
+        state.place_marker(player, -33.87, 151.21).unwrap();
+        let elapsed = state.guess_times[&player];
+        assert!((5.0..6.0).contains(&elapsed), "{}", elapsed);
+    }
 }
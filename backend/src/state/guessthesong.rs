@@ -1,11 +1,26 @@
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use serde::{Deserialize, Serialize};
 use strsim::damerau_levenshtein;
-use tokio::sync::broadcast;
+use tokio::{sync::broadcast, task::AbortHandle};
+use tracing::info;
 use uuid::Uuid;
 
-use crate::state::{LobbyState, LobbyStatus};
+use crate::{
+    connections::PlayerChannels,
+    denylist::{report_item, song_key},
+    history::{GameRecord, PlayerRecord, PlayerRoundRecord, RoundRecord},
+    state::{
+        ControlCommand, ControlOutcome, GameControl, GameType, LobbyState, LobbyStatus, MAX_TEAMS,
+        RematchOptions, SeriesSettings, SeriesStandings, TeamLeaderboard, TeamSettings,
+        apply_team_round, rank_scores,
+        validation::{MAX_ROUND_DELAY_SECONDS, require_nonzero},
+    },
+};
 
 /// ===============================================
 /// Main Parent Struct for Guess The Song Game
@@ -13,9 +28,16 @@ use crate::state::{LobbyState, LobbyStatus};
 pub(crate) struct GuessTheSongGame {
     pub lobby_state: Mutex<LobbyState>,
     pub broadcast: broadcast::Sender<GuessTheSongServerEvent>,
+    pub direct: PlayerChannels<GuessTheSongServerEvent>,
     pub settings: Mutex<GuessTheSongGameSettings>,
     pub state: Mutex<GuessTheSongGameState>,
     pub lobby_code: String,
+    // Abort handle for the playlist loader while the lobby is `Loading`
+    pub loading: Mutex<Option<AbortHandle>>,
+    // Shared with the other stage game in a dailies lobby
+    pub control: Arc<GameControl>,
+    // Played as a dailies stage, so it's recorded as part of the dailies rather than alone
+    pub stage: bool,
 }
 
 pub(crate) enum PlayerJoinResult {
@@ -24,9 +46,147 @@ pub(crate) enum PlayerJoinResult {
 }
 
 impl GuessTheSongGame {
-    pub fn reset(&self) {
-        self.lobby_state.lock().unwrap().reset();
+    /// Adds the game to the series standings, returning whether the series has games left.
+    /// Otherwise the lobby moves to the post-game results, keeping the scores and songs
+    /// until a rematch starts.
+    pub fn finish(&self) -> bool {
+        let scores = self.get_leaderboard();
+        let series = self.get_settings().series;
+        let standings = self
+            .lobby_state
+            .lock()
+            .unwrap()
+            .finish(&scores, series.as_ref());
+        let complete = standings.complete;
+        if complete {
+            let _ = self
+                .broadcast
+                .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                    new_status: LobbyStatus::Finished,
+                });
+        }
+        let _ = self
+            .broadcast
+            .send(GuessTheSongServerEvent::SeriesStandings(standings));
+        !complete
+    }
+
+    /// Records a player's rematch vote, returning the rematch's options if this vote
+    /// started it
+    pub fn vote_rematch(&self, player_id: Uuid) -> Result<Option<RematchOptions>, String> {
+        let rematch = self.lobby_state.lock().unwrap().vote_rematch(player_id)?;
+        self.broadcast_rematch_votes();
+        Ok(rematch)
+    }
+
+    pub fn set_rematch_options(&self, options: RematchOptions) -> Result<(), String> {
+        self.lobby_state
+            .lock()
+            .unwrap()
+            .set_rematch_options(options)?;
+        self.broadcast_rematch_votes();
+        Ok(())
+    }
+
+    fn broadcast_rematch_votes(&self) {
+        let (votes, options) = {
+            let lobby = self.lobby_state.lock().unwrap();
+            (lobby.get_rematch_votes(), lobby.rematch_options.clone())
+        };
+        let _ = self
+            .broadcast
+            .send(GuessTheSongServerEvent::RematchVotes { votes, options });
+    }
+
+    /// Clears the finished game once the lobby has left its results for a rematch
+    pub fn start_rematch(&self, options: &RematchOptions) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if options.new_seed {
+                state.reset();
+            } else {
+                state.rewind();
+            }
+        }
+        let _ = self
+            .broadcast
+            .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                new_status: LobbyStatus::Waiting,
+            });
+    }
+
+    /// Applies a game control, moving the round start along on resume so buzzer points
+    /// don't count the pause, and cancelling the next series game's playlist load on abort
+    pub fn apply_control(&self, command: ControlCommand) -> Result<ControlOutcome, String> {
+        let outcome = self.control.apply(command)?;
+        match outcome {
+            ControlOutcome::Resumed { paused_for, .. } => {
+                self.state.lock().unwrap().shift_round_start(paused_for);
+            }
+            ControlOutcome::Aborted => {
+                self.cancel_loading("The game was aborted".to_string());
+            }
+            _ => {}
+        }
+        Ok(outcome)
+    }
+
+    pub fn report_broken(&self, player_id: Uuid) -> Result<(), String> {
+        if self.control.is_paused() {
+            return Err("The game is paused".to_string());
+        }
+        let (votes, needed) = self.lobby_state.lock().unwrap().report_broken(player_id)?;
+        self.apply_broken_reports(votes, needed);
+        Ok(())
+    }
+
+    /// Checks the round's reports again after a player leaves, as the rest may now be a majority
+    pub fn recount_broken_reports(&self) {
+        let reports = self.lobby_state.lock().unwrap().count_broken_reports();
+        if let Some((votes, needed)) = reports {
+            self.apply_broken_reports(votes, needed);
+        }
+    }
+
+    /// Skips the round once most of the lobby has reported it, and adds the lobby's report
+    /// to the denylist
+    fn apply_broken_reports(&self, votes: Vec<Uuid>, needed: usize) {
+        let _ = self.broadcast.send(GuessTheSongServerEvent::BrokenReports {
+            votes: votes.clone(),
+            needed,
+        });
+        if votes.len() >= needed {
+            if let Some(song) = self.get_current_song() {
+                info!(song=%song.title, "Song reported broken");
+                report_item(
+                    GameType::GuessTheSong,
+                    song_key(&song.title, &song.artists),
+                    format!("{} - {}", song.artists.join(", "), song.title),
+                    self.lobby_code.clone(),
+                );
+            }
+            let _ = self.control.apply(ControlCommand::SkipRound);
+        }
+    }
+
+    /// Drops the aborted game and its scores, back to the waiting room
+    pub fn abort(&self) {
         self.state.lock().unwrap().reset();
+        self.lobby_state.lock().unwrap().abort_game();
+        let _ = self
+            .broadcast
+            .send(GuessTheSongServerEvent::UpdateLobbyStatus {
+                new_status: LobbyStatus::Waiting,
+            });
+    }
+
+    /// Whether a rematch replaying the last game still has its songs, so loading can be skipped
+    pub fn has_songs(&self) -> bool {
+        !self.state.lock().unwrap().songs.is_empty()
+    }
+
+    pub fn send_to(&self, player_id: &Uuid, event: GuessTheSongServerEvent) {
+        self.direct.send(player_id, event);
     }
 
     pub fn get_new_player_id(&self) -> Uuid {
@@ -45,16 +205,18 @@ impl GuessTheSongGame {
     ) -> Result<PlayerJoinResult, &str> {
         let mut lobby = self.lobby_state.lock().unwrap();
         let mut state = self.state.lock().unwrap();
+        if lobby.has_player(&player_id) {
+            return Err("Already connected to this lobby");
+        }
         if state.scores.contains_key(&player_id) {
-            lobby.player_join(player_id.clone(), player_username);
+            lobby.player_join(player_id, player_username);
             return Ok(PlayerJoinResult::ReJoin);
-        } else {
-            if lobby.status != crate::state::LobbyStatus::Waiting {
-                return Err("Cannot join game in progress");
-            }
-            lobby.player_join(player_id.clone(), player_username);
-            state.scores.insert(player_id.clone(), 0);
         }
+        if !matches!(lobby.status, LobbyStatus::Waiting | LobbyStatus::Finished) {
+            return Err("Cannot join game in progress");
+        }
+        lobby.player_join(player_id, player_username);
+        state.scores.insert(player_id, 0);
         Ok(PlayerJoinResult::NewJoin)
     }
 
@@ -66,8 +228,35 @@ impl GuessTheSongGame {
         self.lobby_state.lock().unwrap().player_unready(user_id);
     }
 
-    pub fn all_ready(&self) -> bool {
-        self.lobby_state.lock().unwrap().all_ready()
+    /// Atomically moves a fully ready lobby from `Waiting` to `Loading`, returning
+    /// false if the game shouldn't start (so concurrent readies start it only once)
+    pub fn try_begin_loading(&self) -> bool {
+        let mut lobby = self.lobby_state.lock().unwrap();
+        if lobby.all_ready() && lobby.status == LobbyStatus::Waiting {
+            lobby.update_lobby_status(LobbyStatus::Loading);
+            return true;
+        }
+        false
+    }
+
+    /// Aborts an in-progress playlist load, returning whether there was one
+    pub fn cancel_loading(&self, reason: String) -> bool {
+        match self.loading.lock().unwrap().take() {
+            Some(handle) => {
+                handle.abort();
+                let _ = self
+                    .broadcast
+                    .send(GuessTheSongServerEvent::LoadingCancelled { message: reason });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear_songs(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.songs.clear();
+        state.song_index = 0;
     }
 
     pub fn get_lobby_status(&self) -> LobbyStatus {
@@ -99,17 +288,54 @@ impl GuessTheSongGame {
         self.state.lock().unwrap().get_current_song()
     }
 
-    pub fn update_game_settings(&self, settings: GuessTheSongGameSettings) {
-        let mut game_settings = self.settings.lock().unwrap();
-        game_settings.update_game_settings(settings);
+    pub fn update_game_settings(
+        &self,
+        mut settings: GuessTheSongGameSettings,
+    ) -> Result<(), String> {
+        settings.validate()?;
+        self.settings.lock().unwrap().update_game_settings(settings);
+        // Songs kept for a replay were prepared for the old settings
+        self.clear_songs();
+        Ok(())
+    }
+
+    pub fn evaluate_guess(&self, player_id: Uuid, guess: &str) -> Vec<GuessOutcome> {
+        if self.control.is_paused() {
+            return vec![GuessOutcome::Invalid("The game is paused".to_string())];
+        }
+        let settings = self.get_settings();
+        self.state
+            .lock()
+            .unwrap()
+            .evaluate_guess(&settings, player_id, guess)
+    }
+
+    pub fn choose(&self, player_id: Uuid, index: usize) -> GuessOutcome {
+        if self.control.is_paused() {
+            return GuessOutcome::Invalid("The game is paused".to_string());
+        }
+        let settings = self.get_settings();
+        self.state
+            .lock()
+            .unwrap()
+            .choose(&settings, player_id, index)
     }
 
-    pub fn is_correct_artist(&self, guess: &str) -> Option<String> {
-        self.state.lock().unwrap().is_correct_artist(guess)
+    pub fn get_current_choices(&self) -> Option<Vec<String>> {
+        self.state.lock().unwrap().get_current_choices()
     }
 
-    pub fn is_correct_song(&self, guess: &str) -> Option<String> {
-        self.state.lock().unwrap().is_correct_song(guess)
+    pub fn get_current_clip(&self) -> Option<Clip> {
+        let settings = self.get_settings();
+        self.state.lock().unwrap().get_current_clip(&settings)
+    }
+
+    /// Unlocks the next step of a progressive round, returning the new audible window
+    pub fn unlock_reveal_step(&self, step: usize) -> Option<Clip> {
+        let settings = self.get_settings();
+        let mut state = self.state.lock().unwrap();
+        state.unlock_reveal_step(step);
+        state.get_current_clip(&settings)
     }
 
     pub fn get_leaderboard(&self) -> HashMap<Uuid, u32> {
@@ -117,11 +343,9 @@ impl GuessTheSongGame {
         state.scores.clone()
     }
 
-    pub fn increment_player_score(&self, player_id: &Uuid, points: u32) {
-        self.state
-            .lock()
-            .unwrap()
-            .increment_player_score(player_id, points);
+    /// Stops accepting answers, before the round's answer is revealed
+    pub fn close_round(&self) {
+        self.state.lock().unwrap().round_open = false;
     }
 
     pub fn get_settings(&self) -> GuessTheSongGameSettings {
@@ -131,6 +355,130 @@ impl GuessTheSongGame {
     pub fn get_round_start_time(&self) -> Option<u64> {
         self.state.lock().unwrap().get_round_start_time()
     }
+
+    pub fn get_teams(&self) -> HashMap<Uuid, u8> {
+        self.lobby_state.lock().unwrap().get_teams()
+    }
+
+    pub fn join_team(&self, player_id: Uuid, team: u8) -> Result<(), String> {
+        let teams = self.get_settings().teams;
+        self.lobby_state
+            .lock()
+            .unwrap()
+            .join_team(player_id, team, teams.as_ref())
+    }
+
+    pub fn auto_balance_teams(&self) -> Result<(), String> {
+        let teams = self.get_settings().teams;
+        self.lobby_state
+            .lock()
+            .unwrap()
+            .auto_balance_teams(teams.as_ref())
+    }
+
+    /// Places anyone without a team and zeroes every team's score before a game,
+    /// returning the final teams if teams are enabled
+    pub fn prepare_teams(&self) -> Option<HashMap<Uuid, u8>> {
+        let teams = self.get_settings().teams?;
+        let assignments = {
+            let mut lobby = self.lobby_state.lock().unwrap();
+            lobby.fill_teams(teams.count);
+            lobby.get_teams()
+        };
+        self.state.lock().unwrap().team_scores = (0..teams.count).map(|t| (t, 0)).collect();
+        Some(assignments)
+    }
+
+    /// Saves this round's points into the game's history and folds them into the team scores
+    pub fn end_round(&self) {
+        let team_settings = self.get_settings().teams;
+        let teams = self.get_teams();
+        let mut state = self.state.lock().unwrap();
+        let round_points = std::mem::take(&mut state.round_points);
+        if let Some(settings) = team_settings {
+            apply_team_round(
+                &mut state.team_scores,
+                &teams,
+                &round_points,
+                settings.aggregation,
+            );
+        }
+        if let Some(song) = state.get_current_song() {
+            state.rounds.push(PlayedRound {
+                title: song.title,
+                artists: song.artists,
+                points: round_points,
+            });
+        }
+    }
+
+    /// Takes back the points scored in a skipped round, leaving it out of the game history
+    pub fn discard_round(&self) {
+        let mut state = self.state.lock().unwrap();
+        let round_points = std::mem::take(&mut state.round_points);
+        for (player_id, points) in round_points {
+            if let Some(score) = state.scores.get_mut(&player_id) {
+                *score = score.saturating_sub(points);
+            }
+        }
+    }
+
+    /// The finished game as it's kept in the game history
+    pub fn to_record(&self) -> GameRecord {
+        let mut record = GameRecord::new(
+            GameType::GuessTheSong,
+            &self.lobby_code,
+            &self.get_settings(),
+        );
+        let usernames: HashMap<Uuid, String> = self
+            .get_players()
+            .into_iter()
+            .map(|(id, username, _)| (id, username))
+            .collect();
+        let state = self.state.lock().unwrap();
+        let ranks = rank_scores(&state.scores);
+        record.players = state
+            .scores
+            .iter()
+            .map(|(player_id, score)| PlayerRecord {
+                player_id: *player_id,
+                username: usernames.get(player_id).cloned().unwrap_or_default(),
+                score: *score,
+                rank: ranks[player_id],
+            })
+            .collect();
+        record.rounds = state
+            .rounds
+            .iter()
+            .map(|round| RoundRecord {
+                details: serde_json::json!({
+                    "title": round.title,
+                    "artists": round.artists,
+                }),
+                // Players who didn't score this round still played it
+                results: state
+                    .scores
+                    .keys()
+                    .map(|player_id| {
+                        let points = round.points.get(player_id).copied().unwrap_or(0);
+                        PlayerRoundRecord {
+                            player_id: *player_id,
+                            points,
+                            distance_km: None,
+                            correct: Some(points > 0),
+                            details: None,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+        record
+    }
+
+    pub fn get_team_leaderboard(&self) -> Option<TeamLeaderboard> {
+        self.get_settings().teams?;
+        Some(self.state.lock().unwrap().team_scores.clone())
+    }
 }
 
 /// ===============================================
@@ -144,6 +492,30 @@ pub(crate) struct GuessTheSongGameSettings {
     pub round_length_seconds: u8,
     pub answer_delay_seconds: u64,
     pub round_delay_seconds: u8,
+    #[serde(default)]
+    pub mode: GuessMode,
+    // Audible window of each preview, starting at a random point if `random_clip_start`
+    #[serde(default = "default_clip_length")]
+    pub clip_length_seconds: u8,
+    #[serde(default)]
+    pub random_clip_start: bool,
+    // Heardle style rounds where the window grows every `reveal_interval_seconds`
+    #[serde(default)]
+    pub progressive_reveal: bool,
+    #[serde(default = "default_reveal_interval")]
+    pub reveal_interval_seconds: u8,
+    #[serde(default)]
+    pub teams: Option<TeamSettings>,
+    #[serde(default)]
+    pub series: Option<SeriesSettings>,
+}
+
+fn default_clip_length() -> u8 {
+    PREVIEW_LENGTH_SECONDS
+}
+
+fn default_reveal_interval() -> u8 {
+    5
 }
 
 impl GuessTheSongGameSettings {
@@ -154,6 +526,13 @@ impl GuessTheSongGameSettings {
             round_length_seconds: 30,
             answer_delay_seconds: 0,
             round_delay_seconds: 3,
+            mode: GuessMode::Classic,
+            clip_length_seconds: default_clip_length(),
+            random_clip_start: false,
+            progressive_reveal: false,
+            reveal_interval_seconds: default_reveal_interval(),
+            teams: None,
+            series: None,
         }
     }
 
@@ -171,6 +550,111 @@ impl GuessTheSongGameSettings {
         self.round_length_seconds = settings.round_length_seconds;
         self.answer_delay_seconds = settings.answer_delay_seconds;
         self.round_delay_seconds = settings.round_delay_seconds;
+        self.mode = settings.mode;
+        self.clip_length_seconds = settings.clip_length_seconds;
+        self.random_clip_start = settings.random_clip_start;
+        self.progressive_reveal = settings.progressive_reveal;
+        self.reveal_interval_seconds = settings.reveal_interval_seconds;
+        self.teams = settings.teams;
+        self.series = settings.series;
+    }
+
+    /// Rejects settings with nothing to play and clamps the rest into range
+    pub fn validate(&mut self) -> Result<(), String> {
+        require_nonzero(self.num_songs, "Number of songs")?;
+        require_nonzero(self.round_length_seconds, "Round length")?;
+        if self.playlist_link.len() > MAX_PLAYLIST_LINK_LENGTH {
+            return Err("Playlist link is too long".to_string());
+        }
+        self.playlist_link = self.playlist_link.trim().to_string();
+        self.num_songs = self.num_songs.min(MAX_SONGS);
+        self.round_length_seconds = self.round_length_seconds.max(MIN_ROUND_LENGTH_SECONDS);
+        // A delay as long as the round would stop players guessing twice
+        self.answer_delay_seconds = self
+            .answer_delay_seconds
+            .min(self.round_length_seconds as u64);
+        self.round_delay_seconds = self.round_delay_seconds.min(MAX_ROUND_DELAY_SECONDS);
+        self.clip_length_seconds = self.clip_length_seconds.clamp(1, PREVIEW_LENGTH_SECONDS);
+        self.reveal_interval_seconds = self
+            .reveal_interval_seconds
+            .clamp(1, self.round_length_seconds);
+        if let Some(teams) = &mut self.teams {
+            teams.count = teams.count.clamp(2, MAX_TEAMS);
+        }
+        if let Some(series) = &mut self.series {
+            series.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn clip_length(&self) -> u8 {
+        self.clip_length_seconds.clamp(1, PREVIEW_LENGTH_SECONDS)
+    }
+
+    /// Window lengths unlocked over a round, ending with the full clip.
+    /// Without progressive reveal the whole clip is audible from the start.
+    pub fn reveal_steps(&self) -> Vec<u8> {
+        let clip_length = self.clip_length();
+        if !self.progressive_reveal {
+            return vec![clip_length];
+        }
+        REVEAL_STEPS_SECONDS
+            .iter()
+            .copied()
+            .filter(|s| *s < clip_length)
+            .chain(std::iter::once(clip_length))
+            .collect()
+    }
+}
+
+/// Previews from every source are (at most) this long
+pub(crate) const PREVIEW_LENGTH_SECONDS: u8 = 30;
+const REVEAL_STEPS_SECONDS: [u8; 6] = [1, 2, 4, 7, 11, 16];
+const MAX_SONGS: u8 = 50;
+const MIN_ROUND_LENGTH_SECONDS: u8 = 5;
+const MAX_PLAYLIST_LINK_LENGTH: usize = 512;
+
+/// What players have to guess each round
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum GuessMode {
+    // The title and each artist
+    #[default]
+    Classic,
+    TitleOnly,
+    ArtistOnly,
+    Album,
+    // One guess per round, scored by distance from the release year
+    Year,
+    // One pick per round from four candidate titles, faster correct picks score more
+    MultipleChoice,
+}
+
+impl GuessMode {
+    /// Modes where each player only gets a single answer per round
+    pub fn is_one_shot(&self) -> bool {
+        matches!(self, GuessMode::Year | GuessMode::MultipleChoice)
+    }
+
+    fn answers(&self, song: &Song) -> Vec<(AnswerKind, String, bool)> {
+        let title = || vec![(AnswerKind::Title, song.title.clone(), false)];
+        let artists = || {
+            song.artists
+                .iter()
+                .map(|a| (AnswerKind::Artist, a.clone(), false))
+                .collect::<Vec<_>>()
+        };
+        match self {
+            GuessMode::Classic => [title(), artists()].concat(),
+            GuessMode::TitleOnly => title(),
+            GuessMode::ArtistOnly => artists(),
+            GuessMode::Album => song
+                .album
+                .iter()
+                .map(|a| (AnswerKind::Album, a.clone(), false))
+                .collect(),
+            GuessMode::Year | GuessMode::MultipleChoice => Vec::new(),
+        }
     }
 }
 
@@ -182,6 +666,25 @@ pub(crate) struct GuessTheSongGameState {
     pub songs: Vec<SongState>,
     pub song_index: usize,
     pub round_start_time: Option<u64>,
+    // Monotonic start of the current round, for scoring answer speed
+    pub round_started_at: Option<Instant>,
+    // Answers are only scored while this is set, from round start until the answer is revealed
+    pub round_open: bool,
+    // Answers locked in this round for one-shot modes
+    pub locked_answers: HashMap<Uuid, LockedAnswer>,
+    // How many reveal steps have been unlocked past the first this round
+    pub reveal_step: usize,
+    // Points each player has gained this round, aggregated into team scores at round end
+    pub round_points: HashMap<Uuid, u32>,
+    pub team_scores: TeamLeaderboard,
+    // Every finished round, for the game history
+    pub rounds: Vec<PlayedRound>,
+}
+
+pub(crate) struct PlayedRound {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub points: HashMap<Uuid, u32>,
 }
 
 impl GuessTheSongGameState {
@@ -191,35 +694,67 @@ impl GuessTheSongGameState {
             songs: Vec::new(),
             song_index: 0,
             round_start_time: None,
+            round_started_at: None,
+            round_open: false,
+            locked_answers: HashMap::new(),
+            reveal_step: 0,
+            round_points: HashMap::new(),
+            team_scores: HashMap::new(),
+            rounds: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) {
+        self.songs = Vec::new();
+        self.rewind();
+    }
+
+    /// Clears the last game's progress but keeps its songs, to play them again
+    pub fn rewind(&mut self) {
         self.scores.iter_mut().for_each(|(_, score)| *score = 0);
         self.song_index = 0;
-        self.songs = Vec::new();
+        for song in &mut self.songs {
+            song.answers
+                .iter_mut()
+                .for_each(|(_, _, found)| *found = false);
+        }
         self.round_start_time = None;
+        self.round_started_at = None;
+        self.round_open = false;
+        self.locked_answers.clear();
+        self.reveal_step = 0;
+        self.round_points.clear();
+        self.team_scores.clear();
+        self.rounds.clear();
     }
 
     pub fn get_round_start_time(&self) -> Option<u64> {
         self.round_start_time
     }
 
-    pub fn get_current_song(&self) -> Option<Song> {
-        if self.songs.is_empty() || self.song_index == 0 || self.song_index - 1 >= self.songs.len()
-        {
-            return None;
+    pub fn shift_round_start(&mut self, by: Duration) {
+        if let Some(started) = &mut self.round_started_at {
+            *started += by;
+        }
+        if let Some(started) = &mut self.round_start_time {
+            *started += by.as_secs_f64().round() as u64;
         }
-        Some(Song {
-            title: self.songs[self.song_index - 1].title.0.clone(),
-            artists: self.songs[self.song_index - 1]
-                .artists
-                .iter()
-                .map(|(artist, _)| artist)
-                .cloned()
-                .collect(),
-            url: self.songs[self.song_index - 1].url.clone(),
-        })
+    }
+
+    fn current(&self) -> Option<&SongState> {
+        self.song_index
+            .checked_sub(1)
+            .and_then(|i| self.songs.get(i))
+    }
+
+    pub fn get_current_song(&self) -> Option<Song> {
+        self.current().map(|s| s.song.clone())
+    }
+
+    pub fn get_current_choices(&self) -> Option<Vec<String>> {
+        self.current()
+            .map(|s| s.choices.clone())
+            .filter(|c| !c.is_empty())
     }
 
     pub fn add_song(&mut self, song: SongState) {
@@ -228,50 +763,187 @@ impl GuessTheSongGameState {
 
     pub fn get_next_song(&mut self) -> Option<Song> {
         self.song_index += 1;
-        self.get_current_song()
+        self.round_started_at = Some(Instant::now());
+        self.locked_answers.clear();
+        self.reveal_step = 0;
+        self.round_points.clear();
+        let song = self.get_current_song();
+        self.round_open = song.is_some();
+        song
     }
 
-    pub fn is_correct_artist(&mut self, guess: &str) -> Option<String> {
-        if self.song_index == 0 || self.songs.is_empty() {
-            return None;
-        }
-        let guess = guess.trim().to_lowercase();
-        for (artist, found) in self.songs[self.song_index - 1].artists.iter_mut() {
-            if !*found && damerau_levenshtein(&artist.trim().to_lowercase(), &guess) <= 1 {
-                *found = true;
-                return Some(artist.clone());
-            }
+    /// The part of the current preview players can hear right now
+    pub fn get_current_clip(&self, settings: &GuessTheSongGameSettings) -> Option<Clip> {
+        let song = self.current()?;
+        let steps = settings.reveal_steps();
+        Some(Clip {
+            start_seconds: song.clip_start,
+            length_seconds: steps[self.reveal_step.min(steps.len() - 1)],
+        })
+    }
+
+    pub fn unlock_reveal_step(&mut self, step: usize) {
+        self.reveal_step = step;
+    }
+
+    /// Points are multiplied by how many reveal steps were still locked when the guess came in
+    fn reveal_multiplier(&self, settings: &GuessTheSongGameSettings) -> u32 {
+        settings
+            .reveal_steps()
+            .len()
+            .saturating_sub(self.reveal_step)
+            .max(1) as u32
+    }
+
+    pub fn get_locked_answers(&self) -> HashMap<Uuid, LockedAnswer> {
+        self.locked_answers.clone()
+    }
+
+    /// Index of the correct title among the current round's choices
+    pub fn get_correct_choice(&self) -> Option<usize> {
+        let song = self.current()?;
+        song.choices.iter().position(|c| *c == song.song.title)
+    }
+
+    /// Locks in a multiple choice pick, scoring correct picks by how quickly they came in
+    pub fn choose(
+        &mut self,
+        settings: &GuessTheSongGameSettings,
+        player_id: Uuid,
+        index: usize,
+    ) -> GuessOutcome {
+        let Some(song) = self.current().filter(|_| self.round_open) else {
+            return GuessOutcome::Invalid("No round in progress".to_string());
+        };
+        let Some(choice) = song.choices.get(index) else {
+            return GuessOutcome::Invalid("Pick one of the choices".to_string());
+        };
+        if self.locked_answers.contains_key(&player_id) {
+            return GuessOutcome::AlreadyAnswered;
         }
-        None
+        let correct = *choice == song.song.title;
+        let elapsed = self
+            .round_started_at
+            .map(|t| t.elapsed())
+            .unwrap_or_default();
+        let points = if correct {
+            buzzer_points(elapsed, settings.round_length_seconds)
+        } else {
+            0
+        };
+        self.locked_answers
+            .insert(player_id, LockedAnswer::Choice(index));
+        self.increment_player_score(&player_id, points);
+        GuessOutcome::Choice { correct, points }
     }
 
-    pub fn is_correct_song(&mut self, guess: &str) -> Option<String> {
-        if self.song_index == 0 || self.songs.is_empty() {
-            return None;
+    /// Checks a typed guess against the current round, adding any points to the guesser's score
+    pub fn evaluate_guess(
+        &mut self,
+        settings: &GuessTheSongGameSettings,
+        player_id: Uuid,
+        guess: &str,
+    ) -> Vec<GuessOutcome> {
+        if !self.round_open {
+            return vec![GuessOutcome::Invalid("No round in progress".to_string())];
         }
-        if !self.songs[self.song_index - 1].title.1
-            && damerau_levenshtein(
-                &self.songs[self.song_index - 1]
-                    .title
-                    .0
-                    .trim()
-                    .to_lowercase(),
-                &guess.trim().to_lowercase(),
-            ) <= 1
-        {
-            self.songs[self.song_index - 1].title.1 = true;
-            return Some(self.songs[self.song_index - 1].title.0.clone());
+        let guess = guess.trim();
+        let locked = self.locked_answers.contains_key(&player_id);
+        let multiplier = self.reveal_multiplier(settings);
+        let Some(song) = self
+            .song_index
+            .checked_sub(1)
+            .and_then(|i| self.songs.get_mut(i))
+        else {
+            return Vec::new();
+        };
+
+        match settings.mode {
+            GuessMode::Year => {
+                let (Some(actual), Ok(guessed)) = (song.song.year, guess.parse::<i32>()) else {
+                    return vec![GuessOutcome::Invalid("Guess a year, e.g. 1994".to_string())];
+                };
+                if locked {
+                    return vec![GuessOutcome::AlreadyAnswered];
+                }
+                let points = year_points(guessed.abs_diff(actual)) * multiplier;
+                self.locked_answers
+                    .insert(player_id, LockedAnswer::Year(guessed));
+                self.increment_player_score(&player_id, points);
+                vec![GuessOutcome::Year {
+                    guessed,
+                    actual,
+                    points,
+                }]
+            }
+            GuessMode::MultipleChoice => {
+                // Typing a choice's title works the same as picking it
+                match song
+                    .choices
+                    .iter()
+                    .position(|c| c.trim().eq_ignore_ascii_case(guess))
+                {
+                    Some(index) => vec![self.choose(settings, player_id, index)],
+                    None => vec![GuessOutcome::Invalid("Pick one of the choices".to_string())],
+                }
+            }
+            _ => {
+                let guess = guess.to_lowercase();
+                let outcomes: Vec<GuessOutcome> = song
+                    .answers
+                    .iter_mut()
+                    .filter(|(_, answer, found)| {
+                        !*found && damerau_levenshtein(&answer.trim().to_lowercase(), &guess) <= 1
+                    })
+                    .map(|(kind, answer, found)| {
+                        *found = true;
+                        GuessOutcome::Correct {
+                            kind: *kind,
+                            answer: answer.clone(),
+                            points: CORRECT_ANSWER_POINTS * multiplier,
+                        }
+                    })
+                    .collect();
+                for outcome in &outcomes {
+                    if let GuessOutcome::Correct { points, .. } = outcome {
+                        self.increment_player_score(&player_id, *points);
+                    }
+                }
+                outcomes
+            }
         }
-        None
     }
 
     pub fn increment_player_score(&mut self, player_id: &Uuid, points: u32) {
         if let Some(score) = self.scores.get_mut(player_id) {
             *score += points;
+            *self.round_points.entry(*player_id).or_insert(0) += points;
         }
     }
 }
 
+const CORRECT_ANSWER_POINTS: u32 = 2;
+const BUZZER_MAX_POINTS: u32 = 10;
+const BUZZER_MIN_POINTS: u32 = 2;
+
+/// Scales linearly from `BUZZER_MAX_POINTS` for an instant answer down to
+/// `BUZZER_MIN_POINTS` for one at the buzzer
+fn buzzer_points(elapsed: Duration, round_length_seconds: u8) -> u32 {
+    let round_length = (round_length_seconds as f64).max(1.0);
+    let remaining = (1.0 - elapsed.as_secs_f64() / round_length).clamp(0.0, 1.0);
+    BUZZER_MIN_POINTS + ((BUZZER_MAX_POINTS - BUZZER_MIN_POINTS) as f64 * remaining).round() as u32
+}
+
+fn year_points(years_off: u32) -> u32 {
+    match years_off {
+        0 => 4,
+        1 => 3,
+        2 => 2,
+        3..=5 => 1,
+        _ => 0,
+    }
+}
+
 /// ===============================================
 /// Server Events
 /// ===============================================
@@ -285,6 +957,10 @@ pub(crate) enum GuessTheSongServerEvent {
         preview_url: Option<String>,
         status: LobbyStatus,
         round_start_time: Option<u64>,
+        choices: Option<Vec<String>>,
+        clip: Option<Clip>,
+        teams: HashMap<Uuid, u8>,
+        team_leaderboard: Option<TeamLeaderboard>,
     },
     PlayerJoin {
         player_id: Uuid,
@@ -299,6 +975,10 @@ pub(crate) enum GuessTheSongServerEvent {
     PlayerLeave {
         player_id: Uuid,
     },
+    // Player id -> team index, sent whenever a team changes
+    TeamsUpdated {
+        teams: HashMap<Uuid, u8>,
+    },
     AllReady,
     GameStart,
     GameSettingsUpdated {
@@ -307,13 +987,32 @@ pub(crate) enum GuessTheSongServerEvent {
     RoundStart {
         preview_url: String,
         round_start_time: u64,
+        mode: GuessMode,
+        choices: Option<Vec<String>>,
+        clip: Clip,
+        reveal_steps: usize,
+    },
+    // The audible window grew during a progressive round
+    ClipUnlocked {
+        step: usize,
+        clip: Clip,
     },
     RoundEnd {
         correct_title: String,
         correct_artists: Vec<String>,
+        album: Option<String>,
+        year: Option<i32>,
+        mode: GuessMode,
+        // Everyone's locked in answer for one-shot modes
+        answers: HashMap<Uuid, LockedAnswer>,
+        correct_choice: Option<usize>,
+        leaderboard: HashMap<Uuid, u32>,
+        team_leaderboard: Option<TeamLeaderboard>,
+    },
+    GameEnd {
         leaderboard: HashMap<Uuid, u32>,
+        team_leaderboard: Option<TeamLeaderboard>,
     },
-    GameEnd,
     PlayerGuess {
         username: String,
         content: String,
@@ -322,12 +1021,78 @@ pub(crate) enum GuessTheSongServerEvent {
         player_id: Uuid,
         msg: String,
     },
+    // Sent privately, e.g. how close a year guess was
+    GuessFeedback {
+        msg: String,
+    },
     JoinError {
         message: String,
     },
+    // Sent privately when a player's request is rejected
+    Error {
+        message: String,
+    },
     PlaylistError {
         message: String,
     },
+    UpdateLobbyStatus {
+        new_status: LobbyStatus,
+    },
+    LoadingProgress {
+        resolved: usize,
+        requested: usize,
+    },
+    TrackSkipped {
+        title: String,
+        reason: String,
+    },
+    LoadingCancelled {
+        message: String,
+    },
+    // Who has voted to play again after a game, and on what terms
+    RematchVotes {
+        votes: Vec<Uuid>,
+        options: RematchOptions,
+    },
+    SeriesStandings(SeriesStandings),
+    // Sent when a player uses the game controls, with the round's time left
+    GamePaused {
+        player_id: Uuid,
+        remaining_ms: u64,
+    },
+    GameResumed {
+        player_id: Uuid,
+        remaining_ms: u64,
+    },
+    RoundSkipped {
+        player_id: Uuid,
+    },
+    GameAborted {
+        player_id: Uuid,
+    },
+    // Who has reported the current round's song as broken, the round is skipped once
+    // `needed` have
+    BrokenReports {
+        votes: Vec<Uuid>,
+        needed: usize,
+    },
+}
+
+impl GuessTheSongServerEvent {
+    pub fn from_control(player_id: Uuid, outcome: ControlOutcome) -> Self {
+        match outcome {
+            ControlOutcome::Paused { remaining } => GuessTheSongServerEvent::GamePaused {
+                player_id,
+                remaining_ms: remaining.as_millis() as u64,
+            },
+            ControlOutcome::Resumed { remaining, .. } => GuessTheSongServerEvent::GameResumed {
+                player_id,
+                remaining_ms: remaining.as_millis() as u64,
+            },
+            ControlOutcome::Skipped => GuessTheSongServerEvent::RoundSkipped { player_id },
+            ControlOutcome::Aborted => GuessTheSongServerEvent::GameAborted { player_id },
+        }
+    }
 }
 
 /// ===============================================
@@ -339,6 +1104,9 @@ pub(crate) enum GuessTheSongUserEvent {
     Join {
         lobby_code: String,
         username: String,
+        // Guest or account token from `/api/auth`, for an id that lasts across sessions
+        #[serde(default)]
+        token: Option<String>,
     },
     Ready,
     Unready,
@@ -348,6 +1116,37 @@ pub(crate) enum GuessTheSongUserEvent {
     Guess {
         content: String,
     },
+    Choose {
+        index: usize,
+    },
+    JoinTeam {
+        team: u8,
+    },
+    AutoBalanceTeams,
+    // Only while the lobby is `Finished`, a rematch starts once everyone has voted
+    Rematch,
+    SetRematchOptions {
+        options: RematchOptions,
+    },
+    // Game controls, only while a game is running
+    Pause,
+    Resume,
+    SkipRound,
+    AbortGame,
+    // The preview won't play, a majority of reports skips the round
+    ReportBroken,
+}
+
+impl GuessTheSongUserEvent {
+    pub fn control_command(&self) -> Option<ControlCommand> {
+        match self {
+            GuessTheSongUserEvent::Pause => Some(ControlCommand::Pause),
+            GuessTheSongUserEvent::Resume => Some(ControlCommand::Resume),
+            GuessTheSongUserEvent::SkipRound => Some(ControlCommand::SkipRound),
+            GuessTheSongUserEvent::AbortGame => Some(ControlCommand::AbortGame),
+            _ => None,
+        }
+    }
 }
 /// ===============================================
 /// Helper Structs
@@ -355,9 +1154,31 @@ pub(crate) enum GuessTheSongUserEvent {
 
 #[derive(Debug)]
 pub(crate) struct SongState {
-    pub title: (String, bool),
-    pub artists: Vec<(String, bool)>,
-    pub url: String,
+    pub song: Song,
+    // Free-text answers for the round's mode, each scored once for whoever finds it first
+    pub answers: Vec<(AnswerKind, String, bool)>,
+    // Candidate titles for multiple choice, including the correct one
+    pub choices: Vec<String>,
+    // Offset into the preview the clip starts at
+    pub clip_start: u8,
+}
+
+impl SongState {
+    pub fn new(song: Song, mode: GuessMode, choices: Vec<String>, clip_start: u8) -> Self {
+        SongState {
+            answers: mode.answers(&song),
+            song,
+            choices,
+            clip_start,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Clip {
+    pub start_seconds: u8,
+    pub length_seconds: u8,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -365,4 +1186,238 @@ pub(crate) struct Song {
     pub title: String,
     pub artists: Vec<String>,
     pub url: String,
+    pub album: Option<String>,
+    pub year: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AnswerKind {
+    Title,
+    Artist,
+    Album,
+}
+
+impl AnswerKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AnswerKind::Title => "song",
+            AnswerKind::Artist => "artist",
+            AnswerKind::Album => "album",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum LockedAnswer {
+    Year(i32),
+    Choice(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum GuessOutcome {
+    Correct {
+        kind: AnswerKind,
+        answer: String,
+        points: u32,
+    },
+    Year {
+        guessed: i32,
+        actual: i32,
+        points: u32,
+    },
+    Choice {
+        correct: bool,
+        points: u32,
+    },
+    AlreadyAnswered,
+    Invalid(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> GuessTheSongGameSettings {
+        GuessTheSongGameSettings::new()
+    }
+
+    #[tokio::test]
+    async fn aborting_cancels_the_playlist_load() {
+        let games = crate::state::Games::new();
+        games.add_guess_the_song_lobby(&"ABCD".to_string());
+        let game = games.guess_the_song.get("ABCD").unwrap().clone();
+        let loader = tokio::spawn(std::future::pending::<()>());
+        *game.loading.lock().unwrap() = Some(loader.abort_handle());
+
+        game.control.start();
+        game.apply_control(ControlCommand::AbortGame).unwrap();
+        assert!(game.loading.lock().unwrap().is_none());
+        assert!(loader.await.unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn rejects_zero_songs() {
+        let mut s = GuessTheSongGameSettings {
+            num_songs: 0,
+            ..settings()
+        };
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_zero_round_length() {
+        let mut s = GuessTheSongGameSettings {
+            round_length_seconds: 0,
+            ..settings()
+        };
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_overlong_playlist_links() {
+        let mut s = GuessTheSongGameSettings {
+            playlist_link: "a".repeat(MAX_PLAYLIST_LINK_LENGTH + 1),
+            ..settings()
+        };
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn clamps_counts_and_timings() {
+        let mut s = GuessTheSongGameSettings {
+            playlist_link: "  https://open.spotify.com/playlist/abc \n".to_string(),
+            num_songs: 200,
+            round_length_seconds: 2,
+            answer_delay_seconds: 60,
+            round_delay_seconds: 100,
+            clip_length_seconds: 0,
+            reveal_interval_seconds: 0,
+            ..settings()
+        };
+        s.validate().unwrap();
+        assert_eq!(s.playlist_link, "https://open.spotify.com/playlist/abc");
+        assert_eq!(s.num_songs, MAX_SONGS);
+        assert_eq!(s.round_length_seconds, MIN_ROUND_LENGTH_SECONDS);
+        assert_eq!(s.answer_delay_seconds, MIN_ROUND_LENGTH_SECONDS as u64);
+        assert_eq!(s.round_delay_seconds, MAX_ROUND_DELAY_SECONDS);
+        assert_eq!(s.clip_length_seconds, 1);
+        assert_eq!(s.reveal_interval_seconds, 1);
+    }
+
+    #[test]
+    fn clamps_clip_length_to_preview() {
+        let mut s = GuessTheSongGameSettings {
+            clip_length_seconds: 90,
+            ..settings()
+        };
+        s.validate().unwrap();
+        assert_eq!(s.clip_length_seconds, PREVIEW_LENGTH_SECONDS);
+    }
+
+    #[test]
+    fn clamps_team_count() {
+        let mut s = GuessTheSongGameSettings {
+            teams: Some(TeamSettings {
+                count: 1,
+                aggregation: Default::default(),
+            }),
+            ..settings()
+        };
+        s.validate().unwrap();
+        assert_eq!(s.teams.unwrap().count, 2);
+    }
+
+    #[test]
+    fn leaves_valid_settings_alone() {
+        let mut s = settings();
+        s.validate().unwrap();
+        assert_eq!(s.num_songs, 10);
+        assert_eq!(s.round_length_seconds, 30);
+        assert_eq!(s.round_delay_seconds, 3);
+        assert_eq!(s.clip_length_seconds, PREVIEW_LENGTH_SECONDS);
+    }
+
+    #[test]
+    fn reveal_steps_end_with_full_clip() {
+        let mut s = GuessTheSongGameSettings {
+            clip_length_seconds: 10,
+            progressive_reveal: true,
+            ..settings()
+        };
+        s.validate().unwrap();
+        assert_eq!(s.reveal_steps(), vec![1, 2, 4, 7, 10]);
+    }
+
+    fn round(mode: GuessMode) -> (GuessTheSongGameState, Uuid) {
+        let song = Song {
+            title: "Wonderwall".to_string(),
+            artists: vec!["Oasis".to_string()],
+            url: String::new(),
+            album: None,
+            year: Some(1995),
+        };
+        let choices = vec!["Wonderwall".to_string(), "Champagne Supernova".to_string()];
+        let player_id = Uuid::new_v4();
+        let mut state = GuessTheSongGameState::new();
+        state.scores.insert(player_id, 0);
+        state.add_song(SongState::new(song, mode, choices, 0));
+        state.get_next_song().unwrap();
+        (state, player_id)
+    }
+
+    #[test]
+    fn picks_score_while_the_round_is_open() {
+        let settings = GuessTheSongGameSettings {
+            mode: GuessMode::MultipleChoice,
+            ..settings()
+        };
+        let (mut state, player_id) = round(GuessMode::MultipleChoice);
+        let GuessOutcome::Choice { correct, points } = state.choose(&settings, player_id, 0) else {
+            panic!("Expected a scored pick");
+        };
+        assert!(correct);
+        assert_eq!(state.scores[&player_id], points);
+        assert_eq!(state.round_points[&player_id], points);
+    }
+
+    #[test]
+    fn rejects_picks_after_the_round_closes() {
+        let settings = GuessTheSongGameSettings {
+            mode: GuessMode::MultipleChoice,
+            ..settings()
+        };
+        let (mut state, player_id) = round(GuessMode::MultipleChoice);
+        state.round_open = false;
+        assert!(matches!(
+            state.choose(&settings, player_id, 0),
+            GuessOutcome::Invalid(_)
+        ));
+        assert!(matches!(
+            state.evaluate_guess(&settings, player_id, "Wonderwall")[..],
+            [GuessOutcome::Invalid(_)]
+        ));
+        assert_eq!(state.scores[&player_id], 0);
+        assert!(state.round_points.is_empty());
+    }
+
+    #[test]
+    fn rejects_typed_answers_after_the_round_closes() {
+        for mode in [GuessMode::TitleOnly, GuessMode::Year] {
+            let settings = GuessTheSongGameSettings { mode, ..settings() };
+            let (mut state, player_id) = round(mode);
+            state.round_open = false;
+            let guess = if mode == GuessMode::Year {
+                "1995"
+            } else {
+                "Wonderwall"
+            };
+            assert!(matches!(
+                state.evaluate_guess(&settings, player_id, guess)[..],
+                [GuessOutcome::Invalid(_)]
+            ));
+            assert_eq!(state.scores[&player_id], 0);
+        }
+    }
 }
@@ -1,8 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::state::{
+    ControlCommand, ControlOutcome, Series, SeriesScoring, SeriesSettings, TeamSettings,
+};
+
 #[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub(crate) enum LobbyStatus {
@@ -34,6 +39,37 @@ pub(crate) enum LobbyServerEvent {
     JoinError {
         message: String,
     },
+    // Player id -> team index, sent whenever a team changes
+    TeamsUpdated {
+        teams: HashMap<Uuid, u8>,
+    },
+    // Who has voted to play again after a game, and on what terms
+    RematchVotes {
+        votes: Vec<Uuid>,
+        options: RematchOptions,
+    },
+    SeriesStandings(SeriesStandings),
+    // Sent when a player uses the game controls, with the round's time left
+    GamePaused {
+        player_id: Uuid,
+        remaining_ms: u64,
+    },
+    GameResumed {
+        player_id: Uuid,
+        remaining_ms: u64,
+    },
+    RoundSkipped {
+        player_id: Uuid,
+    },
+    GameAborted {
+        player_id: Uuid,
+    },
+    // Who has reported the current round's location or song as broken, the round is
+    // skipped once `needed` have
+    BrokenReports {
+        votes: Vec<Uuid>,
+        needed: usize,
+    },
 }
 
 #[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
@@ -42,15 +78,104 @@ pub(crate) enum LobbyUserEvent {
     Join {
         lobby_code: String,
         username: String,
+        // Guest or account token from `/api/auth`, for an id that lasts across sessions
+        #[serde(default)]
+        token: Option<String>,
     },
     Ready,
     Unready,
+    JoinTeam {
+        team: u8,
+    },
+    AutoBalanceTeams,
+    // Only while the lobby is `Finished`, a rematch starts once everyone has voted
+    Rematch,
+    SetRematchOptions {
+        options: RematchOptions,
+    },
+    // Game controls, only while a game is running
+    Pause,
+    Resume,
+    SkipRound,
+    AbortGame,
+}
+
+impl LobbyUserEvent {
+    pub fn control_command(&self) -> Option<ControlCommand> {
+        match self {
+            LobbyUserEvent::Pause => Some(ControlCommand::Pause),
+            LobbyUserEvent::Resume => Some(ControlCommand::Resume),
+            LobbyUserEvent::SkipRound => Some(ControlCommand::SkipRound),
+            LobbyUserEvent::AbortGame => Some(ControlCommand::AbortGame),
+            _ => None,
+        }
+    }
+}
+
+impl LobbyServerEvent {
+    pub fn from_control(player_id: Uuid, outcome: ControlOutcome) -> Self {
+        match outcome {
+            ControlOutcome::Paused { remaining } => LobbyServerEvent::GamePaused {
+                player_id,
+                remaining_ms: remaining.as_millis() as u64,
+            },
+            ControlOutcome::Resumed { remaining, .. } => LobbyServerEvent::GameResumed {
+                player_id,
+                remaining_ms: remaining.as_millis() as u64,
+            },
+            ControlOutcome::Skipped => LobbyServerEvent::RoundSkipped { player_id },
+            ControlOutcome::Aborted => LobbyServerEvent::GameAborted { player_id },
+        }
+    }
+}
+
+/// Sent at the end of every game
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SeriesStandings {
+    pub games_played: u8,
+    // Only set if the lobby is playing a series
+    pub num_games: Option<u8>,
+    pub standings: HashMap<Uuid, u32>,
+    // False while the series has games left, the next one starts after a short break
+    pub complete: bool,
+}
+
+/// What carries over from a finished game into its rematch
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct RematchOptions {
+    // Start straight away with the same settings, otherwise go back to the waiting room
+    // (keeping the settings) so they can be changed first
+    pub same_settings: bool,
+    // Play new locations or songs, otherwise replay the previous game's
+    pub new_seed: bool,
+    // Keep adding to the series standings, otherwise they start over
+    pub keep_scores: bool,
+}
+
+impl Default for RematchOptions {
+    fn default() -> Self {
+        RematchOptions {
+            same_settings: true,
+            new_seed: true,
+            keep_scores: false,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct LobbyState {
     pub players: HashMap<Uuid, (String, bool)>,
     pub status: LobbyStatus,
+    // Player id -> team index, only used when the game has teams enabled
+    pub teams: HashMap<Uuid, u8>,
+    // Players who want a rematch of the finished game
+    pub rematch_votes: HashSet<Uuid>,
+    pub rematch_options: RematchOptions,
+    pub series: Series,
+    // Players who reported the current round as broken, None between rounds
+    pub broken_reports: Option<HashSet<Uuid>>,
 }
 
 impl LobbyState {
@@ -58,12 +183,110 @@ impl LobbyState {
         LobbyState {
             players: HashMap::new(),
             status: LobbyStatus::Waiting,
+            teams: HashMap::new(),
+            rematch_votes: HashSet::new(),
+            rematch_options: RematchOptions::default(),
+            series: Series::default(),
+            broken_reports: None,
         }
     }
 
     pub fn reset(&mut self) {
         self.status = LobbyStatus::Waiting;
         self.players.iter_mut().for_each(|(_, v)| v.1 = false);
+        self.rematch_votes.clear();
+        self.broken_reports = None;
+    }
+
+    /// Adds a finished game to the series standings. The lobby moves to its post-game
+    /// results unless the series has games left, in which case it's still `Playing`.
+    pub fn finish(
+        &mut self,
+        scores: &HashMap<Uuid, u32>,
+        settings: Option<&SeriesSettings>,
+    ) -> SeriesStandings {
+        let scoring = settings.map_or(SeriesScoring::Raw, |s| s.scoring);
+        self.series.record_game(scores, scoring);
+        let complete = settings.is_none_or(|s| self.series.games_played >= s.num_games);
+        if complete {
+            self.show_results();
+        }
+        SeriesStandings {
+            games_played: self.series.games_played,
+            num_games: settings.map(|s| s.num_games),
+            standings: self.series.standings.clone(),
+            complete,
+        }
+    }
+
+    /// Drops a game cut short, along with the rest of its series, back to the waiting room
+    pub fn abort_game(&mut self) {
+        self.reset();
+        self.series = Series::default();
+    }
+
+    /// Moves the lobby to its post-game results, also used to end a series early
+    pub fn show_results(&mut self) {
+        self.reset();
+        self.status = LobbyStatus::Finished;
+    }
+
+    /// Records a player's rematch vote. The vote that completes the lobby also leaves the
+    /// results for the rematch, so only one caller gets its options back to start it.
+    pub fn vote_rematch(&mut self, player_id: Uuid) -> Result<Option<RematchOptions>, String> {
+        if self.status != LobbyStatus::Finished {
+            return Err("There is no finished game to rematch".to_string());
+        }
+        if self.players.contains_key(&player_id) {
+            self.rematch_votes.insert(player_id);
+        }
+        Ok(self.all_voted().then(|| self.start_rematch()))
+    }
+
+    /// Starts the rematch once everyone left in the finished lobby has voted, returning
+    /// someone to start it for and its options. Checked again when a player leaves, as
+    /// they may have been the last holdout.
+    pub fn try_start_rematch(&mut self) -> Option<(Uuid, RematchOptions)> {
+        if self.status != LobbyStatus::Finished || !self.all_voted() {
+            return None;
+        }
+        let starter = *self.players.keys().next()?;
+        Some((starter, self.start_rematch()))
+    }
+
+    fn all_voted(&self) -> bool {
+        self.players
+            .keys()
+            .all(|id| self.rematch_votes.contains(id))
+    }
+
+    /// Changing the options clears the votes, so everyone agrees to the new terms
+    pub fn set_rematch_options(&mut self, options: RematchOptions) -> Result<(), String> {
+        if self.status != LobbyStatus::Finished {
+            return Err("There is no finished game to rematch".to_string());
+        }
+        self.rematch_options = options;
+        self.rematch_votes.clear();
+        Ok(())
+    }
+
+    pub fn get_rematch_votes(&self) -> Vec<Uuid> {
+        self.rematch_votes.iter().cloned().collect()
+    }
+
+    /// Leaves the results for a rematch the whole lobby voted for. Everyone is readied
+    /// if it starts straight away, otherwise the lobby waits for them to ready up again.
+    fn start_rematch(&mut self) -> RematchOptions {
+        let options = self.rematch_options.clone();
+        self.reset();
+        self.series.games_played = 0;
+        if !options.keep_scores {
+            self.series.standings.clear();
+        }
+        if options.same_settings {
+            self.players.iter_mut().for_each(|(_, v)| v.1 = true);
+        }
+        options
     }
 
     pub fn player_join(&mut self, player_id: Uuid, player_username: String) {
@@ -85,7 +308,7 @@ impl LobbyState {
     pub fn get_players(&self) -> Vec<(Uuid, String, bool)> {
         self.players
             .iter()
-            .map(|(id, (username, ready))| (id.clone(), username.clone(), *ready))
+            .map(|(id, (username, ready))| (*id, username.clone(), *ready))
             .collect()
     }
 
@@ -95,6 +318,43 @@ impl LobbyState {
 
     pub fn player_leave(&mut self, player_id: &Uuid) {
         self.players.remove(player_id);
+        self.teams.remove(player_id);
+        self.rematch_votes.remove(player_id);
+        if let Some(reports) = &mut self.broken_reports {
+            reports.remove(player_id);
+        }
+    }
+
+    pub fn open_broken_reports(&mut self) {
+        self.broken_reports = Some(HashSet::new());
+    }
+
+    pub fn close_broken_reports(&mut self) {
+        self.broken_reports = None;
+    }
+
+    /// Records a player's report of the current round, returning everyone who has reported
+    /// it and how many reports it takes to skip. Reports close once a majority is reached.
+    pub fn report_broken(&mut self, player_id: Uuid) -> Result<(Vec<Uuid>, usize), String> {
+        if self.players.contains_key(&player_id)
+            && let Some(reports) = &mut self.broken_reports
+        {
+            reports.insert(player_id);
+        }
+        self.count_broken_reports()
+            .ok_or_else(|| "There is no round to report".to_string())
+    }
+
+    /// Everyone who has reported the current round and how many reports it takes to skip,
+    /// None between rounds. Checked again when a player leaves, as a smaller lobby may have
+    /// a majority without anyone else reporting. Reports close once a majority is reached.
+    pub fn count_broken_reports(&mut self) -> Option<(Vec<Uuid>, usize)> {
+        let needed = self.players.len() / 2 + 1;
+        let votes: Vec<Uuid> = self.broken_reports.as_ref()?.iter().copied().collect();
+        if votes.len() >= needed {
+            self.broken_reports = None;
+        }
+        Some((votes, needed))
     }
 
     pub fn update_lobby_status(&mut self, new_status: LobbyStatus) {
@@ -105,6 +365,10 @@ impl LobbyState {
         self.players.is_empty()
     }
 
+    pub fn has_player(&self, player_id: &Uuid) -> bool {
+        self.players.contains_key(player_id)
+    }
+
     pub fn get_new_player_id(&self) -> Uuid {
         loop {
             let new_id = Uuid::new_v4();
@@ -117,4 +381,173 @@ impl LobbyState {
     pub fn player_count(&self) -> usize {
         self.players.len()
     }
+
+    /// Teams can only be changed in the waiting room of a game that has them enabled
+    fn editable_teams<'a>(
+        &self,
+        teams: Option<&'a TeamSettings>,
+    ) -> Result<&'a TeamSettings, String> {
+        let teams = teams.ok_or_else(|| "Teams are not enabled".to_string())?;
+        if self.status != LobbyStatus::Waiting {
+            return Err("Cannot change teams during a game".to_string());
+        }
+        Ok(teams)
+    }
+
+    pub fn join_team(
+        &mut self,
+        player_id: Uuid,
+        team: u8,
+        teams: Option<&TeamSettings>,
+    ) -> Result<(), String> {
+        let teams = self.editable_teams(teams)?;
+        if team >= teams.count {
+            return Err(format!("There are only {} teams", teams.count));
+        }
+        if self.players.contains_key(&player_id) {
+            self.teams.insert(player_id, team);
+        }
+        Ok(())
+    }
+
+    /// Shuffles everyone into teams whose sizes differ by at most one
+    pub fn auto_balance_teams(&mut self, teams: Option<&TeamSettings>) -> Result<(), String> {
+        let team_count = self.editable_teams(teams)?.count as usize;
+        let mut player_ids: Vec<Uuid> = self.players.keys().cloned().collect();
+        player_ids.shuffle(&mut rand::rng());
+        self.teams = player_ids
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| (id, (i % team_count) as u8))
+            .collect();
+        Ok(())
+    }
+
+    /// Puts anyone who hasn't picked a team into the smallest one, and moves players
+    /// off teams that no longer exist
+    pub fn fill_teams(&mut self, team_count: u8) {
+        self.teams.retain(|_, team| *team < team_count);
+        let unassigned: Vec<Uuid> = self
+            .players
+            .keys()
+            .filter(|id| !self.teams.contains_key(id))
+            .cloned()
+            .collect();
+        for player_id in unassigned {
+            let smallest = (0..team_count)
+                .min_by_key(|team| self.teams.values().filter(|t| *t == team).count())
+                .unwrap_or(0);
+            self.teams.insert(player_id, smallest);
+        }
+    }
+
+    pub fn get_teams(&self) -> HashMap<Uuid, u8> {
+        self.teams.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lobby(count: usize) -> (LobbyState, Vec<Uuid>) {
+        let mut lobby = LobbyState::new();
+        let players: Vec<Uuid> = (0..count).map(|_| Uuid::new_v4()).collect();
+        for id in &players {
+            lobby.player_join(*id, "p".to_string());
+        }
+        (lobby, players)
+    }
+
+    #[test]
+    fn rematch_starts_once_everyone_votes() {
+        let (mut lobby, players) = lobby(2);
+        assert!(lobby.vote_rematch(players[0]).is_err());
+
+        lobby.show_results();
+        assert_eq!(lobby.vote_rematch(players[0]).unwrap(), None);
+        assert_eq!(lobby.try_start_rematch(), None);
+        assert!(lobby.vote_rematch(players[1]).unwrap().is_some());
+        assert_eq!(lobby.status, LobbyStatus::Waiting);
+    }
+
+    #[test]
+    fn rematch_starts_only_once() {
+        let (mut lobby, players) = lobby(2);
+        lobby.show_results();
+        lobby.vote_rematch(players[0]).unwrap();
+        assert!(lobby.vote_rematch(players[1]).unwrap().is_some());
+
+        // A repeated vote or a player leaving afterwards finds the results already left
+        assert!(lobby.vote_rematch(players[1]).is_err());
+        lobby.player_leave(&players[1]);
+        assert_eq!(lobby.try_start_rematch(), None);
+    }
+
+    #[test]
+    fn rematch_starts_when_the_last_holdout_leaves() {
+        let (mut lobby, players) = lobby(3);
+        lobby.show_results();
+        lobby.vote_rematch(players[0]).unwrap();
+        lobby.vote_rematch(players[1]).unwrap();
+        assert_eq!(lobby.try_start_rematch(), None);
+
+        lobby.player_leave(&players[2]);
+        let (starter, _) = lobby.try_start_rematch().unwrap();
+        assert!(players[..2].contains(&starter));
+        assert_eq!(lobby.status, LobbyStatus::Waiting);
+    }
+
+    #[test]
+    fn no_rematch_once_everyone_leaves() {
+        let (mut lobby, players) = lobby(2);
+        lobby.show_results();
+        lobby.vote_rematch(players[0]).unwrap();
+        lobby.player_leave(&players[0]);
+        lobby.player_leave(&players[1]);
+        assert_eq!(lobby.try_start_rematch(), None);
+    }
+
+    #[test]
+    fn broken_reports_need_a_majority() {
+        let (mut lobby, players) = lobby(3);
+        assert!(lobby.report_broken(players[0]).is_err());
+
+        lobby.open_broken_reports();
+        let (votes, needed) = lobby.report_broken(players[0]).unwrap();
+        assert_eq!((votes.len(), needed), (1, 2));
+        // Reporting twice doesn't count twice
+        let (votes, _) = lobby.report_broken(players[0]).unwrap();
+        assert_eq!(votes.len(), 1);
+        let (votes, needed) = lobby.report_broken(players[1]).unwrap();
+        assert_eq!((votes.len(), needed), (2, 2));
+        // The round is being skipped, so later reports are turned away
+        assert!(lobby.report_broken(players[2]).is_err());
+    }
+
+    #[test]
+    fn leaving_can_complete_a_broken_report_majority() {
+        let (mut lobby, players) = lobby(4);
+        lobby.open_broken_reports();
+        lobby.report_broken(players[0]).unwrap();
+        let (votes, needed) = lobby.report_broken(players[1]).unwrap();
+        assert_eq!((votes.len(), needed), (2, 3));
+
+        lobby.player_leave(&players[3]);
+        let (votes, needed) = lobby.count_broken_reports().unwrap();
+        assert_eq!((votes.len(), needed), (2, 2));
+        // Closed along with the skip, so nothing skips twice
+        assert_eq!(lobby.count_broken_reports(), None);
+    }
+
+    #[test]
+    fn leaving_withdraws_a_broken_report() {
+        let (mut lobby, players) = lobby(3);
+        lobby.open_broken_reports();
+        lobby.report_broken(players[0]).unwrap();
+
+        lobby.player_leave(&players[0]);
+        let (votes, needed) = lobby.count_broken_reports().unwrap();
+        assert_eq!((votes.len(), needed), (0, 2));
+    }
 }
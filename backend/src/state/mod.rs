@@ -1,29 +1,55 @@
-use rspotify::ClientCredsSpotify;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::mpsc;
+use uuid::Uuid;
 
+use crate::guess_the_song::api::{SongCache, SpotifyProvider, http_client};
+
+pub mod control;
+pub mod dailies;
 pub mod games;
 pub mod geoguessr;
 pub mod guessthesong;
 pub mod lobby;
+pub mod series;
+pub mod teams;
+pub mod validation;
 
+pub(crate) use control::*;
+pub(crate) use dailies::*;
 pub(crate) use games::*;
 pub(crate) use guessthesong::*;
 pub(crate) use lobby::*;
+pub(crate) use series::*;
+pub(crate) use teams::*;
 
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub games: Arc<Games>,
-    pub spotify_client: Arc<ClientCredsSpotify>,
+    pub spotify: Arc<SpotifyProvider>,
+    pub http: reqwest::Client,
+    pub song_cache: Arc<SongCache>,
     pub cleanup: mpsc::UnboundedSender<String>,
 }
 
 impl AppState {
-    pub fn new(spotify: ClientCredsSpotify, cleanup: mpsc::UnboundedSender<String>) -> Self {
+    pub fn new(spotify: SpotifyProvider, cleanup: mpsc::UnboundedSender<String>) -> Self {
         AppState {
             games: Arc::new(Games::new()),
-            spotify_client: Arc::new(spotify),
-            cleanup: cleanup,
+            spotify: Arc::new(spotify),
+            http: http_client(),
+            song_cache: Arc::new(SongCache::new()),
+            cleanup,
         }
     }
 }
+
+/// Competition ranks, highest score first. Tied players share a rank, e.g. 1, 1, 3
+pub(crate) fn rank_scores(scores: &HashMap<Uuid, u32>) -> HashMap<Uuid, u32> {
+    scores
+        .iter()
+        .map(|(id, score)| {
+            let ahead = scores.values().filter(|other| *other > score).count();
+            (*id, ahead as u32 + 1)
+        })
+        .collect()
+}
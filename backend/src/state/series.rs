@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::state::{rank_scores, validation::require_nonzero};
+
+/// ===============================================
+/// Series Settings
+/// ===============================================
+/// A series plays `num_games` games back to back in the same lobby, with a short
+/// break for the standings between each one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SeriesSettings {
+    pub num_games: u8,
+    #[serde(default)]
+    pub scoring: SeriesScoring,
+}
+
+/// How each game's scores turn into series points
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum SeriesScoring {
+    // Points by finishing position, so one blowout game can't decide the series
+    #[default]
+    Placement,
+    // Every game's score added up
+    Raw,
+}
+
+pub(crate) const MAX_SERIES_GAMES: u8 = 10;
+pub(crate) const SERIES_BREAK_SECONDS: u64 = 10;
+// Series points for finishing first, second, ..., later places score nothing
+const PLACEMENT_POINTS: [u32; 8] = [10, 8, 6, 5, 4, 3, 2, 1];
+
+impl SeriesSettings {
+    pub fn validate(&mut self) -> Result<(), String> {
+        require_nonzero(self.num_games, "Number of games")?;
+        self.num_games = self.num_games.min(MAX_SERIES_GAMES);
+        Ok(())
+    }
+}
+
+/// ===============================================
+/// Series Standings
+/// ===============================================
+/// Lobbies without a series still keep raw standings, carried across rematches
+/// that keep scores.
+#[derive(Debug, Default)]
+pub(crate) struct Series {
+    pub games_played: u8,
+    // Player id -> series points
+    pub standings: HashMap<Uuid, u32>,
+}
+
+impl Series {
+    pub fn record_game(&mut self, scores: &HashMap<Uuid, u32>, scoring: SeriesScoring) {
+        let ranks = rank_scores(scores);
+        for (player_id, score) in scores {
+            let points = match scoring {
+                SeriesScoring::Raw => *score,
+                SeriesScoring::Placement => PLACEMENT_POINTS
+                    .get(ranks[player_id] as usize - 1)
+                    .copied()
+                    .unwrap_or(0),
+            };
+            *self.standings.entry(*player_id).or_insert(0) += points;
+        }
+        self.games_played += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(points: &[(Uuid, u32)]) -> HashMap<Uuid, u32> {
+        points.iter().copied().collect()
+    }
+
+    #[test]
+    fn placement_scores_by_finishing_position() {
+        let players: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let mut series = Series::default();
+        series.record_game(
+            &scores(&[(players[0], 5000), (players[1], 10), (players[2], 4000)]),
+            SeriesScoring::Placement,
+        );
+        series.record_game(
+            &scores(&[(players[0], 100), (players[1], 900), (players[2], 200)]),
+            SeriesScoring::Placement,
+        );
+        assert_eq!(series.games_played, 2);
+        // A blowout win is still only worth first place
+        assert_eq!(series.standings[&players[0]], 10 + 6);
+        assert_eq!(series.standings[&players[1]], 6 + 10);
+        assert_eq!(series.standings[&players[2]], 8 + 8);
+    }
+
+    #[test]
+    fn tied_players_share_placement_points() {
+        let players: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let mut series = Series::default();
+        series.record_game(
+            &scores(&[
+                (players[0], 700),
+                (players[1], 700),
+                (players[2], 300),
+                (players[3], 300),
+            ]),
+            SeriesScoring::Placement,
+        );
+        // Ranked 1, 1, 3, 3
+        assert_eq!(series.standings[&players[0]], 10);
+        assert_eq!(series.standings[&players[1]], 10);
+        assert_eq!(series.standings[&players[2]], 6);
+        assert_eq!(series.standings[&players[3]], 6);
+    }
+
+    #[test]
+    fn places_past_the_table_score_nothing() {
+        let players: Vec<Uuid> = (0..10).map(|_| Uuid::new_v4()).collect();
+        let game: Vec<(Uuid, u32)> = (0u32..)
+            .zip(&players)
+            .map(|(i, id)| (*id, 100 - i))
+            .collect();
+        let mut series = Series::default();
+        series.record_game(&scores(&game), SeriesScoring::Placement);
+        assert_eq!(series.standings[&players[7]], 1);
+        assert_eq!(series.standings[&players[8]], 0);
+        assert_eq!(series.standings[&players[9]], 0);
+    }
+
+    #[test]
+    fn raw_scoring_adds_up_scores() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut series = Series::default();
+        series.record_game(&scores(&[(alice, 5000), (bob, 10)]), SeriesScoring::Raw);
+        series.record_game(&scores(&[(alice, 100), (bob, 900)]), SeriesScoring::Raw);
+        assert_eq!(series.standings[&alice], 5100);
+        assert_eq!(series.standings[&bob], 910);
+    }
+}
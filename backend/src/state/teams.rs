@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// ===============================================
+/// Team Settings
+/// ===============================================
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TeamSettings {
+    pub count: u8,
+    #[serde(default)]
+    pub aggregation: TeamAggregation,
+}
+
+/// How members' points for a round combine into their team's points
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum TeamAggregation {
+    #[default]
+    Sum,
+    Average,
+    // Only the best member's round counts, e.g. the closest GeoGuessr guess
+    Best,
+}
+
+pub(crate) const MAX_TEAMS: u8 = 8;
+
+/// Team index -> score
+pub(crate) type TeamLeaderboard = HashMap<u8, u32>;
+
+/// Adds one round's points to the team scores. Members who didn't score still
+/// count towards their team's average.
+pub(crate) fn apply_team_round(
+    team_scores: &mut TeamLeaderboard,
+    teams: &HashMap<Uuid, u8>,
+    round_points: &HashMap<Uuid, u32>,
+    aggregation: TeamAggregation,
+) {
+    let mut members: HashMap<u8, Vec<u32>> = HashMap::new();
+    for (player_id, team) in teams {
+        members
+            .entry(*team)
+            .or_default()
+            .push(round_points.get(player_id).copied().unwrap_or(0));
+    }
+    for (team, points) in members {
+        let gained = match aggregation {
+            TeamAggregation::Sum => points.iter().sum(),
+            TeamAggregation::Average => {
+                (points.iter().sum::<u32>() as f64 / points.len() as f64).round() as u32
+            }
+            TeamAggregation::Best => points.iter().copied().max().unwrap_or(0),
+        };
+        *team_scores.entry(team).or_insert(0) += gained;
+    }
+}
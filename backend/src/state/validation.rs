@@ -0,0 +1,58 @@
+// ===============================================
+// Client Payload Validation
+// ===============================================
+// Checks shared by both games. Settings are validated by their own `validate`,
+// which clamps values that are merely out of range and rejects ones that make no sense.
+
+pub(crate) const MAX_USERNAME_LENGTH: usize = 24;
+pub(crate) const MAX_GUESS_LENGTH: usize = 200;
+pub(crate) const MAX_ROUND_DELAY_SECONDS: u8 = 30;
+
+/// Trims the username, rejecting empty, overlong or unprintable ones
+pub(crate) fn validate_username(username: &str) -> Result<String, String> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err("Username cannot be empty".to_string());
+    }
+    if username.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(format!(
+            "Username cannot be longer than {} characters",
+            MAX_USERNAME_LENGTH
+        ));
+    }
+    if username.chars().any(char::is_control) {
+        return Err("Username contains invalid characters".to_string());
+    }
+    Ok(username.to_string())
+}
+
+pub(crate) fn validate_coordinates(lat: f32, lng: f32) -> Result<(), String> {
+    if !lat.is_finite() || !lng.is_finite() {
+        return Err("Coordinates must be finite numbers".to_string());
+    }
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err(format!("Coordinates out of range: ({}, {})", lat, lng));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_guess(guess: &str) -> Result<(), String> {
+    if guess.trim().is_empty() {
+        return Err("Guess cannot be empty".to_string());
+    }
+    if guess.chars().count() > MAX_GUESS_LENGTH {
+        return Err(format!(
+            "Guesses cannot be longer than {} characters",
+            MAX_GUESS_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects zero for settings where it would leave nothing to play
+pub(crate) fn require_nonzero(value: u8, name: &str) -> Result<(), String> {
+    if value == 0 {
+        return Err(format!("{} must be at least 1", name));
+    }
+    Ok(())
+}
@@ -173,6 +173,12 @@ export default function Game() {
             { user: msg.data.username, message: msg.data.content },
           ]);
           break;
+        case "Error":
+          setChat((c) => [
+            ...c,
+            { user: "ERROR", message: msg.data.message },
+          ]);
+          break;
         case "CorrectGuess":
           setChat((c) => [...c, { user: "", message: msg.data.msg }]);
           break;